@@ -0,0 +1,107 @@
+use super::provider::{CompletionRequest, CompletionResponse, LlmProvider, SendJson, TokenUsage};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+const DEFAULT_ENDPOINT: &str = "https://api.anthropic.com/v1/messages";
+const DEFAULT_MODELS_URL: &str = "https://api.anthropic.com/v1/models";
+pub(super) const DEFAULT_MODEL: &str = "claude-3-haiku-20240307";
+const API_VERSION: &str = "2023-06-01";
+
+/// Anthropic's Messages API.
+pub struct AnthropicProvider {
+    client: Client,
+    api_key: String,
+    endpoint: String,
+    models_url: String,
+    model: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String, endpoint: Option<String>, model: Option<String>) -> Self {
+        let endpoint = endpoint.unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+        let models_url = endpoint
+            .strip_suffix("/messages")
+            .map(|base| format!("{}/models", base))
+            .unwrap_or_else(|| DEFAULT_MODELS_URL.to_string());
+        Self {
+            client: Client::new(),
+            api_key,
+            endpoint,
+            models_url,
+            model: model.unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    async fn complete(&self, request: &CompletionRequest) -> Result<CompletionResponse> {
+        let mut body = json!({
+            "model": self.model,
+            "messages": [{"role": "user", "content": request.prompt}],
+            "max_tokens": request.max_tokens,
+        });
+        if let Some(system) = &request.system {
+            body["system"] = json!(system);
+        }
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        // Structured output is a forced call of a tool whose input is the schema
+        if let Some(schema) = &request.json_schema {
+            body["tools"] = json!([{
+                "name": schema.name,
+                "description": "Record the answer.",
+                "input_schema": schema.schema,
+            }]);
+            body["tool_choice"] = json!({"type": "tool", "name": schema.name});
+        }
+
+        let data: Value = self
+            .client
+            .post(&self.endpoint)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&body)
+            .send_json()
+            .await?;
+        let content = data["content"].as_array().cloned().unwrap_or_default();
+        let text = if request.json_schema.is_some() {
+            content
+                .iter()
+                .find(|block| block["type"] == "tool_use")
+                .map(|block| block["input"].to_string())
+                .ok_or_else(|| anyhow!("Anthropic response has no tool call"))?
+        } else {
+            content
+                .iter()
+                .find_map(|block| block["text"].as_str())
+                .map(|text| text.to_string())
+                .ok_or_else(|| anyhow!("Anthropic response has no text content"))?
+        };
+
+        Ok(CompletionResponse {
+            text,
+            model: data["model"].as_str().map(|model| model.to_string()),
+            usage: TokenUsage::from_fields(&data["usage"], "input_tokens", "output_tokens"),
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let data: Value = self
+            .client
+            .get(&self.models_url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .send_json()
+            .await?;
+        Ok(data["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["id"].as_str().map(|id| id.to_string()))
+            .collect())
+    }
+}
@@ -0,0 +1,124 @@
+use super::provider::{CompletionRequest, CompletionResponse, LlmProvider, SendJson, TokenUsage};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+pub(super) const DEFAULT_MODEL: &str = "gemini-pro";
+const MODELS_URL: &str = "https://generativelanguage.googleapis.com/v1/models";
+
+/// Google's Gemini generateContent API.
+pub struct GeminiProvider {
+    client: Client,
+    api_key: String,
+    /// Full generateContent URL; derived from the model unless configured.
+    endpoint: String,
+    models_url: String,
+}
+
+impl GeminiProvider {
+    pub fn new(api_key: String, endpoint: Option<String>, model: Option<String>) -> Self {
+        let model = model.unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        let endpoint = endpoint.unwrap_or_else(|| format!("{}/{}:generateContent", MODELS_URL, model));
+        // A configured endpoint looks like `<base>/models/<model>:generateContent`
+        let models_url = endpoint
+            .rfind("/models/")
+            .map(|i| endpoint[..i + "/models".len()].to_string())
+            .unwrap_or_else(|| MODELS_URL.to_string());
+        Self {
+            client: Client::new(),
+            api_key,
+            endpoint,
+            models_url,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    async fn complete(&self, request: &CompletionRequest) -> Result<CompletionResponse> {
+        let mut generation_config = json!({"maxOutputTokens": request.max_tokens});
+        if let Some(temperature) = request.temperature {
+            generation_config["temperature"] = json!(temperature);
+        }
+        if let Some(schema) = &request.json_schema {
+            generation_config["responseMimeType"] = json!("application/json");
+            generation_config["responseSchema"] = openapi_schema(&schema.schema);
+        }
+        let mut body = json!({
+            "contents": [{"role": "user", "parts": [{"text": request.prompt}]}],
+            "generationConfig": generation_config,
+        });
+        if let Some(system) = &request.system {
+            body["systemInstruction"] = json!({"parts": [{"text": system}]});
+        }
+
+        let data: Value = self
+            .client
+            .post(&self.endpoint)
+            .query(&[("key", &self.api_key)])
+            .json(&body)
+            .send_json()
+            .await?;
+        let text = data["candidates"][0]["content"]["parts"][0]["text"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Gemini response has no text part"))?;
+
+        Ok(CompletionResponse {
+            text: text.to_string(),
+            model: data["modelVersion"].as_str().map(|model| model.to_string()),
+            usage: TokenUsage::from_fields(&data["usageMetadata"], "promptTokenCount", "candidatesTokenCount"),
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let data: Value = self
+            .client
+            .get(&self.models_url)
+            .query(&[("key", &self.api_key)])
+            .send_json()
+            .await?;
+        // Names come as `models/<id>`; only models that can generate text qualify
+        Ok(data["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|m| {
+                m["supportedGenerationMethods"]
+                    .as_array()
+                    .is_some_and(|methods| methods.iter().any(|x| x == "generateContent"))
+            })
+            .filter_map(|m| m["name"].as_str())
+            .map(|name| name.trim_start_matches("models/").to_string())
+            .collect())
+    }
+}
+
+/// Gemini takes an OpenAPI 3 subset: nullable types are a `nullable` flag
+/// and `additionalProperties` is not allowed.
+fn openapi_schema(schema: &Value) -> Value {
+    match schema {
+        Value::Object(map) => {
+            let mut out = serde_json::Map::new();
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    ("additionalProperties", _) => {}
+                    ("type", Value::Array(types)) => {
+                        if let Some(t) = types.iter().find(|t| *t != "null") {
+                            out.insert("type".to_string(), t.clone());
+                        }
+                        if types.iter().any(|t| t == "null") {
+                            out.insert("nullable".to_string(), Value::Bool(true));
+                        }
+                    }
+                    _ => {
+                        out.insert(key.clone(), openapi_schema(value));
+                    }
+                }
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(openapi_schema).collect()),
+        other => other.clone(),
+    }
+}
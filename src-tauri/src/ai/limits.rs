@@ -0,0 +1,354 @@
+use super::provider::{ApiError, CompletionRequest, CompletionResponse, LlmProvider};
+use crate::types::AIConfig;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+const DEFAULT_MAX_CONCURRENCY: u32 = 4;
+const DEFAULT_REQUESTS_PER_MINUTE: u32 = 60;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+/// First retry delay when the server gives no `Retry-After`; doubled per attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longer waits, even when asked for, fail the request instead.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Limits {
+    max_concurrency: u32,
+    requests_per_minute: u32,
+    timeout: Duration,
+    max_retries: u32,
+}
+
+impl Limits {
+    fn from_config(config: &AIConfig) -> Self {
+        Self {
+            max_concurrency: config.max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY).max(1),
+            requests_per_minute: config.requests_per_minute.unwrap_or(DEFAULT_REQUESTS_PER_MINUTE).max(1),
+            timeout: Duration::from_secs(config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+        }
+    }
+}
+
+/// Allows bursts of up to a minute's worth of requests, refilling evenly.
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_minute: u32) -> Self {
+        let capacity = f64::from(per_minute);
+        Self {
+            capacity,
+            tokens: capacity,
+            per_second: capacity / 60.0,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token and returns how long to wait until it is actually
+    /// available. Tokens are reserved ahead, so waiters queue up fairly.
+    fn take(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.refilled_at = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.per_second)
+        }
+    }
+}
+
+/// Concurrency and rate state of one backend, shared by every provider
+/// talking to it so the limits hold across syncs and accounts.
+struct Gate {
+    limits: Limits,
+    permits: Semaphore,
+    bucket: Mutex<TokenBucket>,
+}
+
+fn gate(key: String, limits: &Limits) -> Arc<Gate> {
+    static GATES: OnceLock<Mutex<HashMap<String, Arc<Gate>>>> = OnceLock::new();
+    let mut gates = GATES.get_or_init(Default::default).lock().unwrap();
+    match gates.get(&key) {
+        Some(gate) if gate.limits == *limits => gate.clone(),
+        // New backend, or its limits were changed in the settings
+        _ => {
+            let gate = Arc::new(Gate {
+                limits: limits.clone(),
+                permits: Semaphore::new(limits.max_concurrency as usize),
+                bucket: Mutex::new(TokenBucket::new(limits.requests_per_minute)),
+            });
+            gates.insert(key, gate.clone());
+            gate
+        }
+    }
+}
+
+/// Wraps a backend with bounded concurrency, token-bucket rate limiting,
+/// request timeouts, and retries with exponential backoff for transient
+/// failures, honoring `Retry-After`.
+pub struct LimitedProvider {
+    inner: Box<dyn LlmProvider>,
+    gate: Arc<Gate>,
+}
+
+impl LimitedProvider {
+    pub fn new(inner: Box<dyn LlmProvider>, config: &AIConfig) -> Self {
+        let key = format!(
+            "{:?}|{}",
+            config.provider,
+            config.api_endpoint.as_deref().unwrap_or_default()
+        );
+        Self {
+            inner,
+            gate: gate(key, &Limits::from_config(config)),
+        }
+    }
+
+    /// Runs one attempt within the limits.
+    async fn attempt(&self, request: &CompletionRequest) -> Result<CompletionResponse> {
+        let wait = self.gate.bucket.lock().unwrap().take();
+        tokio::time::sleep(wait).await;
+        let _permit = self.gate.permits.acquire().await?;
+        let timeout = self.gate.limits.timeout;
+        tokio::time::timeout(timeout, self.inner.complete(request))
+            .await
+            .map_err(|_| anyhow!("AI request timed out after {} seconds", timeout.as_secs()))?
+    }
+}
+
+#[async_trait]
+impl LlmProvider for LimitedProvider {
+    async fn complete(&self, request: &CompletionRequest) -> Result<CompletionResponse> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.attempt(request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            let Some(delay) = retry_delay(&error, attempt) else {
+                return Err(error);
+            };
+            if attempt >= self.gate.limits.max_retries || delay > MAX_RETRY_DELAY {
+                return Err(error);
+            }
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let timeout = self.gate.limits.timeout;
+        tokio::time::timeout(timeout, self.inner.list_models())
+            .await
+            .map_err(|_| anyhow!("AI request timed out after {} seconds", timeout.as_secs()))?
+    }
+}
+
+/// How long to wait before retrying after `error`, or `None` if retrying
+/// will not help, e.g. for a bad API key.
+fn retry_delay(error: &anyhow::Error, attempt: u32) -> Option<Duration> {
+    let backoff = INITIAL_BACKOFF * 2u32.saturating_pow(attempt);
+    if let Some(api_error) = error.downcast_ref::<ApiError>() {
+        return api_error
+            .is_transient()
+            .then(|| api_error.retry_after.unwrap_or(backoff));
+    }
+    // Timeouts and connection failures
+    let transient = error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_timeout() || e.is_connect())
+        || error.to_string().starts_with("AI request timed out");
+    transient.then_some(backoff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+    use crate::ai::provider::parse_retry_after;
+    use crate::types::AIProvider;
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails the first `failures` requests with `status`, then answers.
+    struct Flaky {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        failures: usize,
+        requests: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Flaky {
+        async fn complete(&self, _request: &CompletionRequest) -> Result<CompletionResponse> {
+            if self.requests.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(api_error(self.status, self.retry_after));
+            }
+            Ok(CompletionResponse {
+                text: "Done".to_string(),
+                model: None,
+                usage: None,
+            })
+        }
+
+        async fn list_models(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn api_error(status: StatusCode, retry_after: Option<Duration>) -> anyhow::Error {
+        ApiError {
+            status,
+            retry_after,
+            message: "Try again".to_string(),
+        }
+        .into()
+    }
+
+    /// Limits of their own per test, as gates are shared by endpoint.
+    fn config(endpoint: &str) -> AIConfig {
+        AIConfig {
+            enabled: true,
+            provider: AIProvider::OpenAI,
+            api_key: String::new(),
+            api_endpoint: Some(endpoint.to_string()),
+            model: None,
+            auto_delete: false,
+            quarantine_days: None,
+            min_confidence: None,
+            max_concurrency: None,
+            requests_per_minute: None,
+            timeout_secs: None,
+            max_retries: Some(2),
+            local_min_confidence: None,
+            daily_digest: None,
+            digest_hour: None,
+            monthly_budget: None,
+            prices: None,
+        }
+    }
+
+    fn request() -> CompletionRequest {
+        CompletionRequest {
+            system: None,
+            prompt: "Hi".to_string(),
+            max_tokens: 10,
+            temperature: None,
+            json_schema: None,
+        }
+    }
+
+    async fn run(
+        endpoint: &str,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        failures: usize,
+    ) -> (Result<CompletionResponse>, usize) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let flaky = Flaky {
+            status,
+            retry_after,
+            failures,
+            requests: requests.clone(),
+        };
+        let provider = LimitedProvider::new(Box::new(flaky), &config(endpoint));
+        let result = provider.complete(&request()).await;
+        (result, requests.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn token_bucket_allows_a_burst_then_spaces_requests() {
+        let mut bucket = TokenBucket::new(60);
+        for _ in 0..60 {
+            assert_eq!(bucket.take(), Duration::ZERO);
+        }
+        let wait = bucket.take();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1), "{:?}", wait);
+        // Waiters queue up behind each other
+        assert!(bucket.take() > Duration::from_millis(1900));
+    }
+
+    #[test]
+    fn retries_only_transient_errors() {
+        for status in [429, 500, 503, 529] {
+            let error = api_error(StatusCode::from_u16(status).unwrap(), None);
+            assert_eq!(retry_delay(&error, 0), Some(INITIAL_BACKOFF), "{}", status);
+        }
+        for status in [400, 401, 404] {
+            let error = api_error(StatusCode::from_u16(status).unwrap(), None);
+            assert_eq!(retry_delay(&error, 0), None, "{}", status);
+        }
+        assert_eq!(retry_delay(&anyhow!("Backend unreachable"), 0), None);
+        assert_eq!(
+            retry_delay(&anyhow!("AI request timed out after 30 seconds"), 0),
+            Some(INITIAL_BACKOFF)
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_unless_the_server_says_when() {
+        let error = api_error(StatusCode::SERVICE_UNAVAILABLE, None);
+        assert_eq!(retry_delay(&error, 2), Some(INITIAL_BACKOFF * 4));
+        let error = api_error(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(7)));
+        assert_eq!(retry_delay(&error, 2), Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        let at = (chrono::Utc::now() + chrono::Duration::seconds(90)).to_rfc2822();
+        let delay = parse_retry_after(&at).unwrap();
+        assert!(delay > Duration::from_secs(85) && delay <= Duration::from_secs(90), "{:?}", delay);
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_transient_failures_until_they_pass() {
+        let (result, requests) = run("retry-pass", StatusCode::SERVICE_UNAVAILABLE, None, 2).await;
+        assert_eq!(result.unwrap().text, "Done");
+        assert_eq!(requests, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_retries() {
+        let (result, requests) = run("retry-give-up", StatusCode::TOO_MANY_REQUESTS, None, 5).await;
+        assert!(result.unwrap_err().to_string().contains("429"));
+        assert_eq!(requests, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_wait_longer_than_max_retry_delay() {
+        let too_long = Some(MAX_RETRY_DELAY + Duration::from_secs(1));
+        let (result, requests) = run("retry-too-long", StatusCode::TOO_MANY_REQUESTS, too_long, 1).await;
+        assert!(result.is_err());
+        assert_eq!(requests, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_failures_are_not_retried() {
+        let (result, requests) = run("retry-permanent", StatusCode::UNAUTHORIZED, None, 1).await;
+        assert!(result.is_err());
+        assert_eq!(requests, 1);
+
+        let mock = MockProvider::failing();
+        let mock_requests = mock.requests();
+        let provider = LimitedProvider::new(Box::new(mock), &config("retry-mock"));
+        let error = provider.complete(&request()).await.unwrap_err();
+        assert_eq!(error.to_string(), "Backend unreachable");
+        assert_eq!(mock_requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,207 @@
+use super::preview;
+use crate::types::{Category, CategoryCorrection, Email, LocalModel, TrainingReport};
+use std::collections::HashMap;
+
+/// Emails the model must have learned from before its answers are used.
+const MIN_EXAMPLES: u32 = 20;
+/// Bytes of the body the model looks at.
+const BODY_PREVIEW: usize = 2000;
+/// When measuring accuracy, every fifth correction is held out.
+const HOLD_OUT_EVERY: usize = 5;
+
+/// What the model looks at: the words of the subject and body, the
+/// sender's address and domain, and whether there are attachments.
+pub fn features(email: &Email) -> Vec<String> {
+    let mut features = sender_and_subject(&email.from.address, &email.subject);
+    features.extend(words(preview(&email.body, BODY_PREVIEW)).map(|w| format!("body:{}", w)));
+    if email.attachments.as_ref().is_some_and(|a| !a.is_empty()) {
+        features.push("attachment".to_string());
+    }
+    features
+}
+
+/// Features of a correction whose email is gone, from what it recorded.
+pub fn correction_features(correction: &CategoryCorrection) -> Vec<String> {
+    sender_and_subject(&correction.sender, &correction.subject)
+}
+
+fn sender_and_subject(sender: &str, subject: &str) -> Vec<String> {
+    let sender = sender.to_lowercase();
+    let mut features = vec![format!("from:{}", sender)];
+    if let Some((_, domain)) = sender.rsplit_once('@') {
+        features.push(format!("domain:{}", domain));
+    }
+    features.extend(words(subject).map(|w| format!("subject:{}", w)));
+    features
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| (2..=24).contains(&w.chars().count()))
+        .map(|w| w.to_lowercase())
+}
+
+/// Learns one more email.
+pub fn train(model: &mut LocalModel, features: &[String], category: &Category) {
+    let id = category.id().to_string();
+    *model.documents.entry(id.clone()).or_default() += 1;
+    for feature in features {
+        *model
+            .features
+            .entry(feature.clone())
+            .or_default()
+            .entry(id.clone())
+            .or_default() += 1;
+    }
+    *model.totals.entry(id).or_default() += features.len() as u64;
+    model.trained_at = Some(chrono::Utc::now().to_rfc3339());
+}
+
+/// The most likely category and its probability, once the model has
+/// learned enough emails of more than one category.
+pub fn predict(model: &LocalModel, features: &[String]) -> Option<(Category, f32)> {
+    let examples: u32 = model.documents.values().sum();
+    if examples < MIN_EXAMPLES || model.documents.len() < 2 {
+        return None;
+    }
+    most_likely(model, features)
+}
+
+/// Multinomial naive Bayes with add-one smoothing. Features the model has
+/// never seen say nothing and are skipped.
+fn most_likely(model: &LocalModel, features: &[String]) -> Option<(Category, f32)> {
+    let examples: u32 = model.documents.values().sum();
+    let vocabulary = model.features.len() as f64;
+    let known: Vec<&HashMap<String, u32>> =
+        features.iter().filter_map(|f| model.features.get(f)).collect();
+    let scores: Vec<(&String, f64)> = model
+        .documents
+        .iter()
+        .map(|(category, &documents)| {
+            let total = model.totals.get(category).copied().unwrap_or_default() as f64;
+            let prior = (f64::from(documents) / f64::from(examples)).ln();
+            let likelihood: f64 = known
+                .iter()
+                .map(|counts| {
+                    let count = counts.get(category).copied().unwrap_or_default();
+                    ((f64::from(count) + 1.0) / (total + vocabulary)).ln()
+                })
+                .sum();
+            (category, prior + likelihood)
+        })
+        .collect();
+
+    let (category, best) = scores.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let sum: f64 = scores.iter().map(|(_, score)| (score - best).exp()).sum();
+    Some((Category::from((*category).clone()), (1.0 / sum) as f32))
+}
+
+/// Trains a new model on all examples. Its accuracy is measured first, by
+/// training on four fifths of them and classifying the rest.
+pub fn retrain(examples: &[(Vec<String>, Category)]) -> (LocalModel, TrainingReport) {
+    let is_held_out = |i: usize| i % HOLD_OUT_EVERY == HOLD_OUT_EVERY - 1;
+    let mut trial = LocalModel::default();
+    for (i, (features, category)) in examples.iter().enumerate() {
+        if !is_held_out(i) {
+            train(&mut trial, features, category);
+        }
+    }
+    let held_out: Vec<&(Vec<String>, Category)> = examples
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| is_held_out(*i))
+        .map(|(_, example)| example)
+        .collect();
+    let correct = held_out
+        .iter()
+        .filter(|(features, category)| {
+            most_likely(&trial, features).is_some_and(|(predicted, _)| &predicted == category)
+        })
+        .count();
+    let accuracy = (!held_out.is_empty()).then(|| correct as f32 / held_out.len() as f32);
+
+    let mut model = LocalModel::default();
+    for (features, category) in examples {
+        train(&mut model, features, category);
+    }
+    let trained_at = chrono::Utc::now().to_rfc3339();
+    model.trained_at = Some(trained_at.clone());
+    model.accuracy = accuracy;
+    let report = TrainingReport {
+        examples: examples.len(),
+        held_out: held_out.len(),
+        accuracy,
+        trained_at,
+    };
+    (model, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example(i: usize) -> (Vec<String>, Category) {
+        if i.is_multiple_of(2) {
+            (vec!["subject:sale".to_string()], Category::Marketing)
+        } else {
+            (vec!["subject:meeting".to_string()], Category::Important)
+        }
+    }
+
+    fn model(examples: usize, categories: usize) -> LocalModel {
+        let mut model = LocalModel::default();
+        for i in 0..examples {
+            let (features, category) = example(i * (categories - 1));
+            train(&mut model, &features, &category);
+        }
+        model
+    }
+
+    #[test]
+    fn retrain_holds_out_every_fifth_example() {
+        let examples: Vec<_> = (0..10).map(example).collect();
+        let (model, report) = retrain(&examples);
+
+        assert_eq!(report.examples, 10);
+        assert_eq!(report.held_out, 2);
+        assert_eq!(report.accuracy, Some(1.0));
+        assert_eq!(model.accuracy, Some(1.0));
+        assert_eq!(model.documents.values().sum::<u32>(), 10);
+        assert_eq!(model.trained_at.as_deref(), Some(report.trained_at.as_str()));
+    }
+
+    #[test]
+    fn retrain_without_held_out_examples_reports_no_accuracy() {
+        let examples: Vec<_> = (0..4).map(example).collect();
+        let (model, report) = retrain(&examples);
+
+        assert_eq!(report.held_out, 0);
+        assert_eq!(report.accuracy, None);
+        assert_eq!(model.documents.values().sum::<u32>(), 4);
+    }
+
+    #[test]
+    fn predicts_only_after_enough_examples_of_two_categories() {
+        let sale = vec!["subject:sale".to_string()];
+        assert!(predict(&model(MIN_EXAMPLES as usize - 1, 2), &sale).is_none());
+        assert!(predict(&model(MIN_EXAMPLES as usize, 1), &sale).is_none());
+
+        let (category, probability) = predict(&model(MIN_EXAMPLES as usize, 2), &sale).unwrap();
+        assert_eq!(category, Category::Marketing);
+        assert!(probability > 0.5);
+    }
+
+    #[test]
+    fn probabilities_stay_between_zero_and_one() {
+        let model = model(MIN_EXAMPLES as usize, 2);
+        for features in [
+            vec!["subject:sale".to_string()],
+            vec!["subject:meeting".to_string(); 50],
+            vec!["subject:unseen".to_string()],
+            vec![],
+        ] {
+            let (_, probability) = most_likely(&model, &features).unwrap();
+            assert!((0.0..=1.0).contains(&probability), "{}", probability);
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use super::provider::{CompletionRequest, CompletionResponse, LlmProvider};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Answers every request with the same text, or fails them all, without any
+/// network access. Counts the requests it gets.
+pub struct MockProvider {
+    reply: Option<String>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl MockProvider {
+    pub fn new(reply: &str) -> Self {
+        Self {
+            reply: Some(reply.to_string()),
+            requests: Arc::default(),
+        }
+    }
+
+    /// Fails like an unreachable backend.
+    pub fn failing() -> Self {
+        Self {
+            reply: None,
+            requests: Arc::default(),
+        }
+    }
+
+    /// The number of requests made, still readable once the provider is
+    /// handed to a classifier.
+    pub fn requests(&self) -> Arc<AtomicUsize> {
+        self.requests.clone()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    async fn complete(&self, _request: &CompletionRequest) -> Result<CompletionResponse> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        let text = self.reply.clone().ok_or_else(|| anyhow!("Backend unreachable"))?;
+        Ok(CompletionResponse {
+            text,
+            model: None,
+            usage: None,
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
@@ -1,20 +1,141 @@
-use crate::types::{AIClassification, AIConfig, AIProvider, Category, Email};
-use anyhow::Result;
+mod anthropic;
+mod gemini;
+mod limits;
+pub mod local;
+#[cfg(test)]
+mod mock;
+mod ollama;
+mod openai;
+pub mod provider;
+pub mod reply;
+pub mod summary;
+pub mod usage;
+
+use crate::categories;
+use crate::types::{
+    AIClassification, AIConfig, AIUsageRecord, Category, CategoryDefinition, Email, EmailAddress,
+    LocalModel,
+};
+use anyhow::{anyhow, Result};
+use provider::{CompletionRequest, CompletionResponse, JsonSchema, LlmProvider};
 use regex::Regex;
-use reqwest::Client;
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
+
+/// Bump whenever the classification prompt or schema changes, so cached
+/// classifications made with the old one are not reused.
+const PROMPT_VERSION: u32 = 2;
+
+/// Confidence the local classifier needs when none is configured.
+const DEFAULT_LOCAL_MIN_CONFIDENCE: f32 = 0.9;
+
+/// Bytes of all bodies of a conversation together; long threads get less
+/// of each, within these bounds.
+const THREAD_PREVIEW: usize = 12_000;
+const MIN_MESSAGE_PREVIEW: usize = 500;
+const MAX_MESSAGE_PREVIEW: usize = 4000;
+
+/// The classification as the model returns it, see `classification_schema`.
+#[derive(Debug, Deserialize)]
+struct ModelClassification {
+    category: String,
+    confidence: f32,
+    reason: String,
+    verification_code: Option<String>,
+    verification_link: Option<String>,
+}
+
+/// A validated classification.
+struct Verdict {
+    category: Category,
+    confidence: Option<f32>,
+    reason: Option<String>,
+    verification_code: Option<String>,
+    verification_link: Option<String>,
+}
 
 pub struct AIClassifier {
     config: AIConfig,
-    client: Client,
+    provider: Box<dyn LlmProvider>,
+    /// What emails are sorted into, see `categories::all`.
+    categories: Vec<CategoryDefinition>,
+    /// Learned from the user's corrections; asked before the AI.
+    local_model: Option<LocalModel>,
+    /// Dollars left of the monthly budget; no requests are made once it is
+    /// spent. `None` if there is no budget.
+    budget_left: Mutex<Option<f64>>,
+    /// Usage of the requests made, until taken with `take_usage`.
+    usage: Mutex<Vec<AIUsageRecord>>,
 }
 
 impl AIClassifier {
     pub fn new(config: AIConfig) -> Self {
+        let provider = provider::from_config(&config);
+        Self::with_provider(config, provider)
+    }
+
+    /// Classifies with the given backend instead of the configured one.
+    pub fn with_provider(config: AIConfig, provider: Box<dyn LlmProvider>) -> Self {
         Self {
             config,
-            client: Client::new(),
+            provider,
+            categories: categories::all(Vec::new()),
+            local_model: None,
+            budget_left: Mutex::new(None),
+            usage: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sorts into these categories instead of only the built-in ones.
+    pub fn with_categories(mut self, categories: Vec<CategoryDefinition>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// Answers from the local model instead of asking the AI whenever it is
+    /// confident enough.
+    pub fn with_local_model(mut self, model: LocalModel) -> Self {
+        self.local_model = Some(model);
+        self
+    }
+
+    /// Stops making requests once `budget_left` dollars are spent, see
+    /// `usage::budget_left`.
+    pub fn with_budget_left(self, budget_left: Option<f64>) -> Self {
+        *self.budget_left.lock().unwrap() = budget_left;
+        self
+    }
+
+    /// Usage of the requests made since the last call, for the store.
+    pub fn take_usage(&self) -> Vec<AIUsageRecord> {
+        std::mem::take(&mut *self.usage.lock().unwrap())
+    }
+
+    /// Identifies an email's classification in the cache: a hash of its
+    /// normalized subject and body, the provider, the model and the prompt
+    /// version and categories. Identical messages, e.g. the same newsletter
+    /// sent to two accounts, share an entry.
+    pub fn cache_key(&self, email: &Email) -> String {
+        let mut hasher = Sha256::new();
+        for part in [normalize(&email.subject), normalize(&email.body)] {
+            hasher.update(part);
+            hasher.update([0]);
         }
+        hasher.update(format!(
+            "{:?}\0{}\0{}",
+            self.config.provider,
+            self.config.model.as_deref().unwrap_or_default(),
+            PROMPT_VERSION
+        ));
+        // Adding or describing a category differently changes the prompt
+        for category in &self.categories {
+            hasher.update(format!("\0{}\0{}\0{}", category.id, category.name, category.description));
+        }
+        hex::encode(hasher.finalize())
     }
 
     pub async fn classify_email(&self, email: &Email) -> Result<AIClassification> {
@@ -24,6 +145,8 @@ impl AIClassifier {
                 verification_code: None,
                 verification_link: None,
                 should_notify: false,
+                confidence: None,
+                reason: None,
             });
         }
 
@@ -31,139 +154,202 @@ impl AIClassifier {
         let verification_code = Self::extract_verification_code(&email.body);
         let verification_link = Self::extract_verification_link(&email.body);
 
-        // Then use AI to classify
-        let category = self.classify_with_ai(&email.subject, &email.body).await?;
+        // Then what the user taught us if it is sure, or else the AI, which
+        // also finds what the patterns missed
+        let verdict = match self.classify_locally(email) {
+            Some(verdict) => verdict,
+            None => self.classify_with_ai(email).await?,
+        };
 
-        let should_notify = matches!(category, Category::Important | Category::Verification);
+        let should_notify = categories::should_notify(&self.categories, &verdict.category);
 
         Ok(AIClassification {
-            category,
-            verification_code,
-            verification_link,
+            category: verdict.category,
+            verification_code: verification_code.or(verdict.verification_code),
+            verification_link: verification_link.or(verdict.verification_link),
             should_notify,
+            confidence: verdict.confidence,
+            reason: verdict.reason,
+        })
+    }
+
+    /// Classifies many emails concurrently, as far as the provider's limits
+    /// allow. Results are in the order of `emails`; `on_progress` is called
+    /// with the number done after each one finishes.
+    pub async fn classify_many(
+        self: &Arc<Self>,
+        emails: &[Email],
+        mut on_progress: impl FnMut(usize),
+    ) -> Vec<Result<AIClassification>> {
+        let mut tasks = JoinSet::new();
+        let mut positions = HashMap::new();
+        for (i, email) in emails.iter().enumerate() {
+            let classifier = self.clone();
+            let email = email.clone();
+            let task = tasks.spawn(async move { classifier.classify_email(&email).await });
+            positions.insert(task.id(), i);
+        }
+
+        let mut results: Vec<Result<AIClassification>> =
+            emails.iter().map(|_| Err(anyhow!("Classification did not finish"))).collect();
+        let mut done = 0;
+        while let Some(joined) = tasks.join_next_with_id().await {
+            let (id, result) = match joined {
+                Ok((id, result)) => (id, result),
+                Err(e) => (e.id(), Err(anyhow!("Classification task failed: {}", e))),
+            };
+            results[positions[&id]] = result;
+            done += 1;
+            on_progress(done);
+        }
+        results
+    }
+
+    fn classify_locally(&self, email: &Email) -> Option<Verdict> {
+        let model = self.local_model.as_ref()?;
+        let (category, confidence) = local::predict(model, &local::features(email))?;
+        let min_confidence = self.config.local_min_confidence.unwrap_or(DEFAULT_LOCAL_MIN_CONFIDENCE);
+        // Categories deleted since are left to the AI
+        if confidence < min_confidence || categories::find(&self.categories, &category).is_none() {
+            return None;
+        }
+        Some(Verdict {
+            category,
+            confidence: Some(confidence),
+            reason: Some("Learned from your corrections".to_string()),
+            verification_code: None,
+            verification_link: None,
         })
     }
 
-    async fn classify_with_ai(&self, subject: &str, body: &str) -> Result<Category> {
+    /// Makes a request on behalf of an account, within the budget, and
+    /// records its usage.
+    async fn complete(&self, request: &CompletionRequest, account_id: &str) -> Result<CompletionResponse> {
+        let reserved = self.reserve(request)?;
+        let response = match self.provider.complete(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.settle(reserved, 0.0);
+                return Err(e);
+            }
+        };
+        let model = response
+            .model
+            .as_deref()
+            .or(self.config.model.as_deref())
+            .unwrap_or("unknown");
+        let record = usage::record(&self.config.provider, model, Some(account_id), response.usage);
+        self.settle(reserved, usage::record_cost(&record, Some(&self.config)));
+        self.usage.lock().unwrap().push(record);
+        Ok(response)
+    }
+
+    /// Takes the most a request can cost from the budget before it is sent,
+    /// so that concurrent requests cannot overspend it together. Returns the
+    /// amount reserved.
+    fn reserve(&self, request: &CompletionRequest) -> Result<f64> {
+        let mut budget_left = self.budget_left.lock().unwrap();
+        let Some(left) = budget_left.as_mut() else {
+            return Ok(0.0);
+        };
+        // Unpriced requests would never count against the budget
+        let estimate = usage::estimate(&self.config, request).ok_or_else(|| {
+            anyhow!(
+                "No price is known for model {}; set one in the AI settings to use a monthly budget",
+                self.config.model.as_deref().unwrap_or("(default)")
+            )
+        })?;
+        if *left <= 0.0 || estimate > *left {
+            return Err(anyhow!("The monthly AI budget is used up"));
+        }
+        *left -= estimate;
+        Ok(estimate)
+    }
+
+    /// Replaces a reservation with what the request actually cost.
+    fn settle(&self, reserved: f64, cost: f64) {
+        if let Some(left) = self.budget_left.lock().unwrap().as_mut() {
+            *left += reserved - cost;
+        }
+    }
+
+    async fn classify_with_ai(&self, email: &Email) -> Result<Verdict> {
+        let categories: String = self
+            .categories
+            .iter()
+            .map(|c| format!("- {}: {}\n", c.name, c.description))
+            .collect();
         let prompt = format!(
-            "Classify this email into one of these categories: marketing, important, verification, or normal.\n\nSubject: {}\n\nBody preview: {}\n\nRespond with just the category name.",
-            subject,
-            &body[..body.len().min(500)]
+            "Classify this email into one of these categories:\n{}\n\
+             Give your confidence between 0 and 1, a short reason, and any verification code or \
+             verification link the email contains.\n\nSubject: {}\n\nBody preview: {}",
+            categories,
+            email.subject,
+            preview(&email.body, 500)
         );
 
-        let response = match self.config.provider {
-            AIProvider::OpenAI => self.call_openai_api(&prompt).await?,
-            AIProvider::Anthropic => self.call_anthropic_api(&prompt).await?,
-            AIProvider::Gemini => self.call_gemini_api(&prompt).await?,
+        let request = CompletionRequest {
+            system: None,
+            prompt,
+            max_tokens: 200,
+            temperature: Some(0.3),
+            json_schema: Some(JsonSchema {
+                name: "classification".to_string(),
+                schema: self.classification_schema(),
+            }),
         };
+        let response = self.complete(&request, &email.account_id).await?;
+
+        Ok(self.parse_structured(&response.text).unwrap_or_else(|_| Verdict {
+            category: self.parse_category(&response.text),
+            confidence: None,
+            reason: None,
+            verification_code: None,
+            verification_link: None,
+        }))
+    }
 
-        let category_str = response.to_lowercase();
-        let category = if category_str.contains("marketing") {
-            Category::Marketing
-        } else if category_str.contains("important") {
-            Category::Important
-        } else if category_str.contains("verification") {
-            Category::Verification
-        } else {
-            Category::Normal
-        };
+    /// Reads and validates the JSON reply.
+    fn parse_structured(&self, response: &str) -> Result<Verdict> {
+        let reply: ModelClassification = serde_json::from_str(json_object(response)?)?;
 
-        Ok(category)
-    }
-
-    async fn call_openai_api(&self, prompt: &str) -> Result<String> {
-        let endpoint = self.config.api_endpoint.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("https://api.openai.com/v1/chat/completions");
-
-        let model = self.config.model.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("gpt-3.5-turbo");
-
-        let response = self.client
-            .post(endpoint)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&json!({
-                "model": model,
-                "messages": [
-                    {"role": "user", "content": prompt}
-                ],
-                "max_tokens": 50,
-                "temperature": 0.3
-            }))
-            .send()
-            .await?;
-
-        let data: serde_json::Value = response.json().await?;
-        let content = data["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("normal")
-            .to_string();
-
-        Ok(content)
-    }
-
-    async fn call_anthropic_api(&self, prompt: &str) -> Result<String> {
-        let endpoint = self.config.api_endpoint.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("https://api.anthropic.com/v1/messages");
-
-        let model = self.config.model.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("claude-3-haiku-20240307");
-
-        let response = self.client
-            .post(endpoint)
-            .header("x-api-key", &self.config.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .json(&json!({
-                "model": model,
-                "messages": [
-                    {"role": "user", "content": prompt}
-                ],
-                "max_tokens": 50
-            }))
-            .send()
-            .await?;
-
-        let data: serde_json::Value = response.json().await?;
-        let content = data["content"][0]["text"]
-            .as_str()
-            .unwrap_or("normal")
-            .to_string();
-
-        Ok(content)
-    }
-
-    async fn call_gemini_api(&self, prompt: &str) -> Result<String> {
-        let model = self.config.model.as_ref()
-            .map(|s| s.as_str())
-            .unwrap_or("gemini-pro");
-
-        let endpoint = self.config.api_endpoint.as_ref()
-            .map(|s| format!("{}?key={}", s, self.config.api_key))
-            .unwrap_or_else(|| format!(
-                "https://generativelanguage.googleapis.com/v1/models/{}:generateContent?key={}",
-                model, self.config.api_key
-            ));
-
-        let response = self.client
-            .post(&endpoint)
-            .json(&json!({
-                "contents": [{
-                    "parts": [{"text": prompt}]
-                }]
-            }))
-            .send()
-            .await?;
-
-        let data: serde_json::Value = response.json().await?;
-        let content = data["candidates"][0]["content"]["parts"][0]["text"]
-            .as_str()
-            .unwrap_or("normal")
-            .to_string();
-
-        Ok(content)
+        let category = categories::by_name(&self.categories, &reply.category)
+            .ok_or_else(|| anyhow!("Unknown category {:?}", reply.category))?;
+        if !(0.0..=1.0).contains(&reply.confidence) {
+            return Err(anyhow!("Confidence {} is not between 0 and 1", reply.confidence));
+        }
+        let non_empty = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        Ok(Verdict {
+            category,
+            confidence: Some(reply.confidence),
+            reason: non_empty(Some(reply.reason)),
+            verification_code: non_empty(reply.verification_code),
+            verification_link: non_empty(reply.verification_link)
+                .filter(|link| link.starts_with("https://") || link.starts_with("http://")),
+        })
+    }
+
+    /// Fallback for replies that are not valid JSON: only a bare category
+    /// name counts, so "not marketing" is not taken for marketing.
+    fn parse_category(&self, response: &str) -> Category {
+        let word = response.trim().trim_matches(|c: char| !c.is_alphanumeric());
+        categories::by_name(&self.categories, word).unwrap_or(Category::Normal)
+    }
+
+    fn classification_schema(&self) -> Value {
+        let names: Vec<&str> = self.categories.iter().map(|c| c.name.as_str()).collect();
+        json!({
+            "type": "object",
+            "properties": {
+                "category": {"type": "string", "enum": names},
+                "confidence": {"type": "number", "description": "Between 0 and 1"},
+                "reason": {"type": "string", "description": "One short sentence"},
+                "verification_code": {"type": ["string", "null"]},
+                "verification_link": {"type": ["string", "null"]},
+            },
+            "required": ["category", "confidence", "reason", "verification_code", "verification_link"],
+            "additionalProperties": false,
+        })
     }
 
     fn extract_verification_code(text: &str) -> Option<String> {
@@ -198,3 +384,366 @@ impl AIClassifier {
         None
     }
 }
+
+/// Lowercases and collapses whitespace, so reformatting alone does not
+/// change the cache key.
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// The first `max_bytes` of `text`, cut at a character boundary.
+fn preview(text: &str, max_bytes: usize) -> &str {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// The JSON object in a reply. Tolerates code fences and text around the
+/// object, which some models add anyway.
+fn json_object(response: &str) -> Result<&str> {
+    match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => Ok(&response[start..=end]),
+        _ => Err(anyhow!("Reply contains no JSON object")),
+    }
+}
+
+/// The emails of a conversation as the AI reads them, oldest first: who
+/// wrote each one when, and as much of its body as its share of
+/// `THREAD_PREVIEW` allows.
+fn conversation(emails: &[Email]) -> String {
+    let per_message = (THREAD_PREVIEW / emails.len().max(1)).clamp(MIN_MESSAGE_PREVIEW, MAX_MESSAGE_PREVIEW);
+    emails
+        .iter()
+        .map(|email| {
+            format!(
+                "From: {}\nDate: {}\nSubject: {}\n\n{}\n\n---\n\n",
+                sender(&email.from),
+                email.date,
+                email.subject,
+                preview(&email.body, per_message)
+            )
+        })
+        .collect()
+}
+
+/// An address as the AI reads it, with the name if there is one.
+fn sender(from: &EmailAddress) -> String {
+    match &from.name {
+        Some(name) => format!("{} <{}>", name, from.address),
+        None => from.address.clone(),
+    }
+}
+
+/// Whether a classification is certain enough for automatic actions such
+/// as quarantining, given the configured minimum confidence.
+pub fn is_confident(classification: &AIClassification, config: &AIConfig) -> bool {
+    match config.min_confidence {
+        Some(min) => classification.confidence.is_some_and(|c| c >= min),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AIProvider;
+    use mock::MockProvider;
+    use std::sync::atomic::Ordering;
+
+    fn config() -> AIConfig {
+        AIConfig {
+            enabled: true,
+            provider: AIProvider::OpenAI,
+            api_key: String::new(),
+            api_endpoint: None,
+            model: Some("gpt-4o-mini".to_string()),
+            auto_delete: false,
+            quarantine_days: None,
+            min_confidence: None,
+            max_concurrency: None,
+            requests_per_minute: None,
+            timeout_secs: None,
+            max_retries: None,
+            local_min_confidence: None,
+            daily_digest: None,
+            digest_hour: None,
+            monthly_budget: None,
+            prices: None,
+        }
+    }
+
+    fn email(subject: &str, body: &str) -> Email {
+        Email {
+            id: subject.to_string(),
+            account_id: "acc".to_string(),
+            subject: subject.to_string(),
+            from: EmailAddress {
+                name: None,
+                address: "news@shop.example".to_string(),
+            },
+            to: Vec::new(),
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: "2026-01-01T00:00:00Z".to_string(),
+            body: body.to_string(),
+            html_body: None,
+            attachments: None,
+            is_read: false,
+            is_starred: false,
+            labels: None,
+            ai_classification: None,
+            folder: None,
+            message_id: None,
+            in_reply_to: None,
+            references: None,
+            remote_id: None,
+            quarantine: None,
+            trashed: None,
+            snooze: None,
+            summary: None,
+            thread_summary: None,
+        }
+    }
+
+    fn reply(category: &str, code: Option<&str>) -> String {
+        json!({
+            "category": category,
+            "confidence": 0.8,
+            "reason": "Looks like it",
+            "verification_code": code,
+            "verification_link": null,
+        })
+        .to_string()
+    }
+
+    fn classifier(provider: MockProvider) -> AIClassifier {
+        AIClassifier::with_provider(config(), Box::new(provider))
+    }
+
+    #[tokio::test]
+    async fn maps_the_reply_to_a_category() {
+        let result = classifier(MockProvider::new(&reply("Important", None)))
+            .classify_email(&email("Meeting", "Can we meet tomorrow?"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Important);
+        assert_eq!(result.confidence, Some(0.8));
+        assert_eq!(result.reason.as_deref(), Some("Looks like it"));
+        assert!(result.should_notify);
+
+        let result = classifier(MockProvider::new(&reply("marketing", None)))
+            .classify_email(&email("Sale", "50% off"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Marketing);
+        assert!(!result.should_notify);
+    }
+
+    #[tokio::test]
+    async fn maps_custom_categories_by_name() {
+        let invoices = CategoryDefinition {
+            id: "c1".to_string(),
+            name: "Invoices".to_string(),
+            description: "Bills to pay".to_string(),
+            color: None,
+            notify: true,
+            actions: Vec::new(),
+        };
+        let result = classifier(MockProvider::new(&reply("invoices", None)))
+            .with_categories(categories::all(vec![invoices]))
+            .classify_email(&email("Invoice 42", "Please pay by Friday"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Custom("c1".to_string()));
+        assert!(result.should_notify);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_a_bare_category_name() {
+        let result = classifier(MockProvider::new(" Marketing.\n"))
+            .classify_email(&email("Sale", "50% off"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Marketing);
+        assert_eq!(result.confidence, None);
+
+        let result = classifier(MockProvider::new("not marketing"))
+            .classify_email(&email("Sale", "50% off"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Normal);
+    }
+
+    #[tokio::test]
+    async fn unknown_categories_fall_back_to_normal() {
+        let result = classifier(MockProvider::new(&reply("Spam", None)))
+            .classify_email(&email("Hi", "Hello"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Normal);
+    }
+
+    #[tokio::test]
+    async fn codes_found_in_the_body_win_over_the_model() {
+        let result = classifier(MockProvider::new(&reply("Verification", Some("WRONG1"))))
+            .classify_email(&email(
+                "Sign in",
+                "Your code: 482913\nOr open https://example.com/verify?t=abc",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Verification);
+        assert_eq!(result.verification_code.as_deref(), Some("482913"));
+        assert_eq!(result.verification_link.as_deref(), Some("https://example.com/verify?t=abc"));
+
+        let result = classifier(MockProvider::new(&reply("Verification", Some("K7-QX"))))
+            .classify_email(&email("Sign in", "Type the letters shown in the app"))
+            .await
+            .unwrap();
+        assert_eq!(result.verification_code.as_deref(), Some("K7-QX"));
+    }
+
+    #[tokio::test]
+    async fn confident_local_model_answers_without_a_request() {
+        let mut model = LocalModel::default();
+        for i in 0..20 {
+            let (example, category) = match i % 2 {
+                0 => (email("Big sale today", "Discount coupon, unsubscribe"), Category::Marketing),
+                _ => (email("Project meeting", "Agenda for our team meeting"), Category::Important),
+            };
+            local::train(&mut model, &local::features(&example), &category);
+        }
+        let provider = MockProvider::failing();
+        let requests = provider.requests();
+        let classifier = classifier(provider).with_local_model(model.clone());
+
+        let result = classifier
+            .classify_email(&email("Big sale today", "Discount coupon, unsubscribe"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Marketing);
+        assert_eq!(result.reason.as_deref(), Some("Learned from your corrections"));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        assert!(classifier.take_usage().is_empty());
+
+        // Not sure enough: the AI is asked
+        let mut config = config();
+        config.local_min_confidence = Some(1.5);
+        let provider = MockProvider::new(&reply("Normal", None));
+        let requests = provider.requests();
+        let result = AIClassifier::with_provider(config, Box::new(provider))
+            .with_local_model(model)
+            .classify_email(&email("Big sale today", "Discount coupon, unsubscribe"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Normal);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn disabled_classifier_makes_no_request() {
+        let mut config = config();
+        config.enabled = false;
+        let provider = MockProvider::failing();
+        let requests = provider.requests();
+        let result = AIClassifier::with_provider(config, Box::new(provider))
+            .classify_email(&email("Hi", "Hello"))
+            .await
+            .unwrap();
+        assert_eq!(result.category, Category::Normal);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn backend_errors_are_returned() {
+        let error = classifier(MockProvider::failing())
+            .classify_email(&email("Hi", "Hello"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Backend unreachable");
+    }
+
+    #[tokio::test]
+    async fn spent_budget_stops_requests() {
+        let provider = MockProvider::new(&reply("Normal", None));
+        let requests = provider.requests();
+        let error = classifier(provider)
+            .with_budget_left(Some(0.0))
+            .classify_email(&email("Hi", "Hello"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("budget"));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn budget_needs_a_priced_model() {
+        let provider = MockProvider::new(&reply("Normal", None));
+        let requests = provider.requests();
+        let config = AIConfig {
+            model: Some("gpt-99".to_string()),
+            ..config()
+        };
+        let classifier = AIClassifier::with_provider(config, Box::new(provider));
+        let classifier = classifier.with_budget_left(Some(5.0));
+        let error = classifier.classify_email(&email("Hi", "Hello")).await.unwrap_err();
+        assert!(error.to_string().contains("No price is known for model gpt-99"));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn requests_that_could_exceed_the_budget_are_not_sent() {
+        let provider = MockProvider::new(&reply("Normal", None));
+        let requests = provider.requests();
+        // Less than 200 completion tokens of gpt-4o-mini
+        let error = classifier(provider)
+            .with_budget_left(Some(0.0001))
+            .classify_email(&email("Hi", "Hello"))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("budget"));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn reservations_are_replaced_by_the_actual_cost() {
+        let classifier = classifier(MockProvider::new(&reply("Normal", None))).with_budget_left(Some(0.01));
+        classifier.classify_email(&email("Hi", "Hello")).await.unwrap();
+        // The mock reports no usage, so nothing was spent
+        assert_eq!(*classifier.budget_left.lock().unwrap(), Some(0.01));
+
+        let classifier = AIClassifier::with_provider(config(), Box::new(MockProvider::failing()))
+            .with_budget_left(Some(0.01));
+        classifier.classify_email(&email("Hi", "Hello")).await.unwrap_err();
+        assert_eq!(*classifier.budget_left.lock().unwrap(), Some(0.01));
+    }
+
+    #[tokio::test]
+    async fn classifies_many_in_order() {
+        let classifier = Arc::new(classifier(MockProvider::new(&reply("Important", None))));
+        let emails = vec![email("One", "Hello"), email("Two", "Your code: 123456")];
+        let mut progress = Vec::new();
+        let results = classifier.classify_many(&emails, |done| progress.push(done)).await;
+
+        assert_eq!(progress, [1, 2]);
+        let results: Vec<AIClassification> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results[0].verification_code, None);
+        assert_eq!(results[1].verification_code.as_deref(), Some("123456"));
+        assert!(results.iter().all(|r| r.category == Category::Important));
+        assert_eq!(classifier.take_usage().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn classify_many_returns_each_error() {
+        let classifier = Arc::new(classifier(MockProvider::failing()));
+        let emails = vec![email("One", "Hello"), email("Two", "Hello")];
+        let results = classifier.classify_many(&emails, |_| {}).await;
+        assert_eq!(results.len(), 2);
+        for result in results {
+            assert_eq!(result.unwrap_err().to_string(), "Backend unreachable");
+        }
+    }
+}
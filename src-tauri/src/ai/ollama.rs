@@ -0,0 +1,93 @@
+use super::provider::{CompletionRequest, CompletionResponse, LlmProvider, SendJson, TokenUsage};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+const DEFAULT_URL: &str = "http://localhost:11434";
+
+/// Ollama's native API on a local or self-hosted server.
+pub struct OllamaProvider {
+    client: Client,
+    base_url: String,
+    /// `None` uses the first model the server has pulled.
+    model: Option<String>,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: Option<String>, model: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_URL.to_string());
+        Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model,
+        }
+    }
+
+    async fn model(&self) -> Result<String> {
+        if let Some(model) = &self.model {
+            return Ok(model.clone());
+        }
+        self.list_models()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Ollama at {} has no models pulled", self.base_url))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    async fn complete(&self, request: &CompletionRequest) -> Result<CompletionResponse> {
+        let mut messages = Vec::new();
+        if let Some(system) = &request.system {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        messages.push(json!({"role": "user", "content": request.prompt}));
+
+        let mut options = json!({"num_predict": request.max_tokens});
+        if let Some(temperature) = request.temperature {
+            options["temperature"] = json!(temperature);
+        }
+
+        let mut body = json!({
+            "model": self.model().await?,
+            "messages": messages,
+            "stream": false,
+            "options": options,
+        });
+        if let Some(schema) = &request.json_schema {
+            body["format"] = schema.schema.clone();
+        }
+
+        let data: Value = self
+            .client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&body)
+            .send_json()
+            .await?;
+        let text = data["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Ollama response has no message content"))?;
+
+        Ok(CompletionResponse {
+            text: text.to_string(),
+            model: data["model"].as_str().map(|model| model.to_string()),
+            usage: TokenUsage::from_fields(&data, "prompt_eval_count", "eval_count"),
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let data: Value = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .send_json()
+            .await?;
+        Ok(data["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["name"].as_str().map(|name| name.to_string()))
+            .collect())
+    }
+}
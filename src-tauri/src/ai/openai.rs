@@ -0,0 +1,125 @@
+use super::provider::{CompletionRequest, CompletionResponse, LlmProvider, SendJson, TokenUsage};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+
+const DEFAULT_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
+const DEFAULT_MODELS_URL: &str = "https://api.openai.com/v1/models";
+pub(super) const DEFAULT_MODEL: &str = "gpt-4o-mini";
+/// llama.cpp server's default address.
+const DEFAULT_COMPATIBLE_URL: &str = "http://localhost:8080/v1";
+
+/// OpenAI's Chat Completions API, or a self-hosted server speaking it.
+pub struct OpenAiProvider {
+    client: Client,
+    api_key: Option<String>,
+    chat_url: String,
+    models_url: String,
+    /// `None` uses the first model the server lists.
+    model: Option<String>,
+}
+
+impl OpenAiProvider {
+    pub fn new(api_key: String, endpoint: Option<String>, model: Option<String>) -> Self {
+        let chat_url = endpoint.unwrap_or_else(|| DEFAULT_ENDPOINT.to_string());
+        let models_url = chat_url
+            .strip_suffix("/chat/completions")
+            .map(|base| format!("{}/models", base))
+            .unwrap_or_else(|| DEFAULT_MODELS_URL.to_string());
+        Self {
+            client: Client::new(),
+            api_key: Some(api_key),
+            chat_url,
+            models_url,
+            model: Some(model.unwrap_or_else(|| DEFAULT_MODEL.to_string())),
+        }
+    }
+
+    /// An OpenAI-compatible server such as llama.cpp server or vLLM, given
+    /// its base URL (e.g. `http://localhost:8000/v1`). No API key is needed
+    /// unless the server asks for one.
+    pub fn compatible(api_key: String, base_url: Option<String>, model: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_COMPATIBLE_URL.to_string());
+        let base_url = base_url.trim_end_matches('/');
+        Self {
+            client: Client::new(),
+            api_key: (!api_key.is_empty()).then_some(api_key),
+            chat_url: format!("{}/chat/completions", base_url),
+            models_url: format!("{}/models", base_url),
+            model,
+        }
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.header("Authorization", format!("Bearer {}", key)),
+            None => request,
+        }
+    }
+
+    async fn model(&self) -> Result<String> {
+        if let Some(model) = &self.model {
+            return Ok(model.clone());
+        }
+        self.list_models()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Server at {} has no models", self.models_url))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn complete(&self, request: &CompletionRequest) -> Result<CompletionResponse> {
+        let mut messages = Vec::new();
+        if let Some(system) = &request.system {
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        messages.push(json!({"role": "user", "content": request.prompt}));
+
+        let mut body = json!({
+            "model": self.model().await?,
+            "messages": messages,
+            "max_tokens": request.max_tokens,
+        });
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(schema) = &request.json_schema {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": {"name": schema.name, "schema": schema.schema, "strict": true},
+            });
+        }
+
+        let data: Value = self
+            .authorize(self.client.post(&self.chat_url))
+            .json(&body)
+            .send_json()
+            .await?;
+        let text = data["choices"][0]["message"]["content"]
+            .as_str()
+            .ok_or_else(|| anyhow!("OpenAI response has no message content"))?;
+
+        Ok(CompletionResponse {
+            text: text.to_string(),
+            model: data["model"].as_str().map(|model| model.to_string()),
+            usage: TokenUsage::from_fields(&data["usage"], "prompt_tokens", "completion_tokens"),
+        })
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let data: Value = self
+            .authorize(self.client.get(&self.models_url))
+            .send_json()
+            .await?;
+        Ok(data["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m["id"].as_str().map(|id| id.to_string()))
+            .collect())
+    }
+}
@@ -0,0 +1,170 @@
+use super::{
+    anthropic::AnthropicProvider, gemini::GeminiProvider, limits::LimitedProvider,
+    ollama::OllamaProvider, openai::OpenAiProvider,
+};
+use crate::types::{AIConfig, AIProvider};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+/// A single-turn completion: an optional system prompt and the user's prompt.
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub system: Option<String>,
+    pub prompt: String,
+    pub max_tokens: u32,
+    pub temperature: Option<f32>,
+    /// Constrains the reply to JSON matching this schema, using each
+    /// backend's structured output or tool calling.
+    pub json_schema: Option<JsonSchema>,
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonSchema {
+    pub name: String,
+    /// A JSON Schema object. Nullable fields use `"type": [..., "null"]`.
+    pub schema: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompletionResponse {
+    /// The reply; the JSON document when a schema was requested.
+    pub text: String,
+    /// The model that answered, as the backend reports it.
+    pub model: Option<String>,
+    /// `None` if the backend does not report usage.
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Reads the counts from the given fields of a reply's usage object.
+    pub fn from_fields(usage: &Value, prompt: &str, completion: &str) -> Option<Self> {
+        Some(Self {
+            prompt_tokens: usage[prompt].as_u64()?,
+            completion_tokens: usage[completion].as_u64().unwrap_or_default(),
+        })
+    }
+}
+
+/// A large language model backend. Implementations translate the
+/// completion model to their API and fail on HTTP or API errors.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    async fn complete(&self, request: &CompletionRequest) -> Result<CompletionResponse>;
+
+    /// Models the backend offers, for picking one in the settings. Also
+    /// serves as a cheap check that the backend is reachable.
+    async fn list_models(&self) -> Result<Vec<String>>;
+}
+
+/// An error status returned by a backend's API.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// How long the server asked us to wait before trying again.
+    pub retry_after: Option<Duration>,
+    pub message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AI request failed with {}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ApiError {
+    /// Rate limits, overload and server errors may go away on their own.
+    pub fn is_transient(&self) -> bool {
+        self.status == StatusCode::TOO_MANY_REQUESTS
+            || self.status.is_server_error()
+            // Anthropic's "overloaded"
+            || self.status.as_u16() == 529
+    }
+}
+
+#[async_trait]
+pub trait SendJson {
+    /// Sends the request and reads the JSON reply, failing with an
+    /// `ApiError` on error statuses.
+    async fn send_json(self) -> Result<Value>;
+}
+
+#[async_trait]
+impl SendJson for RequestBuilder {
+    async fn send_json(self) -> Result<Value> {
+        let response = self.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        // APIs put their message in different places; fall back to the raw body
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|data| {
+                data["error"]["message"]
+                    .as_str()
+                    .or_else(|| data["error"].as_str())
+                    .map(|m| m.to_string())
+            })
+            .unwrap_or_else(|| body.chars().take(200).collect());
+        Err(ApiError {
+            status,
+            retry_after,
+            message,
+        }
+        .into())
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+pub(super) fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
+
+/// The model a backend uses when none is configured. Local backends pick
+/// one of the models they have.
+pub fn default_model(provider: &AIProvider) -> Option<&'static str> {
+    match provider {
+        AIProvider::OpenAI => Some(super::openai::DEFAULT_MODEL),
+        AIProvider::Anthropic => Some(super::anthropic::DEFAULT_MODEL),
+        AIProvider::Gemini => Some(super::gemini::DEFAULT_MODEL),
+        AIProvider::Ollama | AIProvider::OpenAICompatible => None,
+    }
+}
+
+/// The backend configured in the AI settings, with its concurrency, rate,
+/// timeout and retry limits applied.
+pub fn from_config(config: &AIConfig) -> Box<dyn LlmProvider> {
+    let endpoint = config.api_endpoint.clone();
+    let model = config.model.clone();
+    let api_key = config.api_key.clone();
+    let provider: Box<dyn LlmProvider> = match config.provider {
+        AIProvider::OpenAI => Box::new(OpenAiProvider::new(api_key, endpoint, model)),
+        AIProvider::Anthropic => Box::new(AnthropicProvider::new(api_key, endpoint, model)),
+        AIProvider::Gemini => Box::new(GeminiProvider::new(api_key, endpoint, model)),
+        AIProvider::Ollama => Box::new(OllamaProvider::new(endpoint, model)),
+        AIProvider::OpenAICompatible => Box::new(OpenAiProvider::compatible(api_key, endpoint, model)),
+    };
+    Box::new(LimitedProvider::new(provider, config))
+}
@@ -0,0 +1,70 @@
+use super::provider::CompletionRequest;
+use super::{conversation, sender, AIClassifier};
+use crate::types::{Email, EmailAddress};
+use anyhow::{anyhow, Result};
+
+/// Earlier emails from the sender, outside the thread, listed by subject.
+const SENDER_HISTORY: usize = 5;
+
+impl AIClassifier {
+    /// Writes the text of a reply to the newest email of `thread` (oldest
+    /// first) on behalf of `author`, in the thread's language and following
+    /// the user's `instructions` if given. `history` is earlier mail from
+    /// the same sender, newest first. The signature is not included.
+    pub async fn draft_reply(
+        &self,
+        thread: &[Email],
+        history: &[Email],
+        author: &EmailAddress,
+        instructions: Option<&str>,
+    ) -> Result<String> {
+        let original = thread.last().ok_or_else(|| anyhow!("Nothing to reply to"))?;
+        let earlier: String = history
+            .iter()
+            .take(SENDER_HISTORY)
+            .map(|email| format!("- {} ({})\n", email.subject, email.date))
+            .collect();
+
+        let mut prompt = format!(
+            "Write a reply from {} to the last email of this thread, from {}.\n\n{}",
+            sender(author),
+            sender(&original.from),
+            conversation(thread)
+        );
+        if !earlier.is_empty() {
+            prompt.push_str(&format!("Earlier emails from {}:\n{}\n", original.from.address, earlier));
+        }
+        match instructions.map(str::trim).filter(|i| !i.is_empty()) {
+            Some(instructions) => prompt.push_str(&format!("The reply should: {}\n", instructions)),
+            None => prompt.push_str("Answer what the sender asks or says, briefly.\n"),
+        }
+
+        let request = CompletionRequest {
+            system: Some(
+                "You draft email replies for the user to review and edit. Write in the language the thread \
+                 is written in, matching its tone. Reply with the body of the email only: no subject, no \
+                 quoted text and no signature, which is added separately. Never invent facts, dates or \
+                 commitments the user has not given; leave a placeholder in square brackets instead."
+                    .to_string(),
+            ),
+            prompt,
+            max_tokens: 800,
+            temperature: Some(0.5),
+            json_schema: None,
+        };
+        let response = self.complete(&request, &original.account_id).await?;
+        let text = strip_fence(response.text.trim());
+        if text.is_empty() {
+            return Err(anyhow!("The AI returned an empty reply"));
+        }
+        Ok(text.to_string())
+    }
+}
+
+/// Some models wrap the text in a Markdown code block anyway.
+fn strip_fence(text: &str) -> &str {
+    text.strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|inner| inner.split_once('\n').map_or(inner, |(_, body)| body).trim())
+        .unwrap_or(text)
+}
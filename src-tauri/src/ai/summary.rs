@@ -0,0 +1,191 @@
+use super::provider::{CompletionRequest, JsonSchema};
+use super::{conversation, json_object, sender, AIClassifier};
+use crate::types::{Deadline, DigestEntry, Email, EmailAccount, EmailAddress, EmailSummary};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+/// The summary as the model returns it, see `summary_schema`.
+#[derive(Debug, Deserialize)]
+struct ModelSummary {
+    summary: String,
+    action_items: Vec<String>,
+    deadlines: Vec<ModelDeadline>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelDeadline {
+    description: String,
+    due: String,
+}
+
+impl AIClassifier {
+    /// Summarizes the emails of one conversation, oldest first: a few
+    /// sentences, what the reader needs to do, and by when.
+    pub async fn summarize(&self, emails: &[Email]) -> Result<EmailSummary> {
+        let first = emails.first().ok_or_else(|| anyhow!("Nothing to summarize"))?;
+        let request = CompletionRequest {
+            system: Some(
+                "You summarize emails for a busy reader. Always write in the language the emails are written in."
+                    .to_string(),
+            ),
+            prompt: format!(
+                "Summarize this {} in two or three sentences. List what the reader is asked to do as \
+                 action items, and any deadlines with the date or time as the email states it.\n\n{}",
+                if emails.len() > 1 { "email thread" } else { "email" },
+                conversation(emails)
+            ),
+            max_tokens: 500,
+            temperature: Some(0.3),
+            json_schema: Some(JsonSchema {
+                name: "summary".to_string(),
+                schema: summary_schema(),
+            }),
+        };
+        let response = self.complete(&request, &first.account_id).await?;
+
+        // Replies that are not valid JSON are taken as the summary itself
+        let reply = parse_summary(&response.text).unwrap_or_else(|_| ModelSummary {
+            summary: response.text.trim().to_string(),
+            action_items: Vec::new(),
+            deadlines: Vec::new(),
+        });
+        Ok(EmailSummary {
+            summary: reply.summary.trim().to_string(),
+            action_items: reply.action_items,
+            deadlines: reply
+                .deadlines
+                .into_iter()
+                .map(|d| Deadline {
+                    description: d.description,
+                    due: d.due,
+                })
+                .collect(),
+            email_ids: emails.iter().map(|e| e.id.clone()).collect(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+}
+
+fn parse_summary(response: &str) -> Result<ModelSummary> {
+    let reply: ModelSummary = serde_json::from_str(json_object(response)?)?;
+    if reply.summary.trim().is_empty() {
+        return Err(anyhow!("Reply has an empty summary"));
+    }
+    Ok(reply)
+}
+
+fn summary_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "summary": {"type": "string"},
+            "action_items": {"type": "array", "items": {"type": "string"}},
+            "deadlines": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "description": {"type": "string"},
+                        "due": {"type": "string"},
+                    },
+                    "required": ["description", "due"],
+                    "additionalProperties": false,
+                },
+            },
+        },
+        "required": ["summary", "action_items", "deadlines"],
+        "additionalProperties": false,
+    })
+}
+
+/// The emails of `email`'s conversation in its account, oldest first: all
+/// linked to it through Message-ID, In-Reply-To and References. `emails`
+/// are newest first, as stored.
+pub fn thread(emails: &[Email], email: &Email) -> Vec<Email> {
+    let ids_of = |e: &Email| -> Vec<String> {
+        e.message_id
+            .iter()
+            .chain(e.in_reply_to.iter())
+            .chain(e.references.iter().flatten())
+            .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .collect()
+    };
+    let candidates: Vec<&Email> = emails
+        .iter()
+        .filter(|e| e.account_id == email.account_id && e.trashed.is_none())
+        .collect();
+
+    let mut ids: HashSet<String> = ids_of(email).into_iter().collect();
+    let mut members: HashSet<&str> = HashSet::from([email.id.as_str()]);
+    loop {
+        let mut grew = false;
+        for candidate in &candidates {
+            if members.contains(candidate.id.as_str()) {
+                continue;
+            }
+            let own = ids_of(candidate);
+            if own.iter().any(|id| ids.contains(id)) {
+                members.insert(&candidate.id);
+                ids.extend(own);
+                grew = true;
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+    emails
+        .iter()
+        .rev()
+        .filter(|e| members.contains(e.id.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// A local message listing the summaries of an account's unread important
+/// mail, for its inbox. It exists only in MailHub, not on the server.
+pub fn digest_message(account: &EmailAccount, entries: &[DigestEntry]) -> Email {
+    let mut body = format!("{} unread important emails\n", entries.len());
+    for entry in entries {
+        body.push_str(&format!("\n{} ({})\n{}\n", entry.subject, sender(&entry.from), entry.summary));
+        for item in &entry.action_items {
+            body.push_str(&format!("- {}\n", item));
+        }
+    }
+    Email {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id: account.id.clone(),
+        subject: format!("Daily digest: {} unread important emails", entries.len()),
+        from: EmailAddress {
+            name: Some("MailHub".to_string()),
+            address: "digest@mailhub.app".to_string(),
+        },
+        to: vec![EmailAddress {
+            name: account.display_name.clone(),
+            address: account.email.clone(),
+        }],
+        cc: None,
+        bcc: None,
+        reply_to: None,
+        date: chrono::Utc::now().to_rfc2822(),
+        body,
+        html_body: None,
+        attachments: None,
+        is_read: false,
+        is_starred: false,
+        labels: None,
+        ai_classification: None,
+        folder: None,
+        message_id: Some(format!("{}@mailhub.app", uuid::Uuid::new_v4())),
+        in_reply_to: None,
+        references: None,
+        remote_id: None,
+        quarantine: None,
+        trashed: None,
+        snooze: None,
+        summary: None,
+        thread_summary: None,
+    }
+}
@@ -0,0 +1,179 @@
+use super::provider::{self, CompletionRequest, TokenUsage};
+use crate::types::{AIConfig, AIProvider, AIUsageRecord, AIUsageReport, AIUsageSummary};
+
+/// Published prices in US dollars per million prompt and completion tokens.
+/// Costs computed from them are estimates; `AIConfig::prices` corrects them.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("gemini-pro", 0.50, 1.50),
+    ("gemini-1.5-flash", 0.075, 0.30),
+    ("gemini-1.5-pro", 1.25, 5.00),
+];
+
+/// Rough size of a token in English text, for estimating a prompt's
+/// tokens before sending it.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Usage of a single request made today.
+pub fn record(provider: &AIProvider, model: &str, account_id: Option<&str>, usage: Option<TokenUsage>) -> AIUsageRecord {
+    let usage = usage.unwrap_or_default();
+    AIUsageRecord {
+        date: chrono::Utc::now().format("%Y-%m-%d").to_string(),
+        provider: provider.clone(),
+        model: model.to_string(),
+        account_id: account_id.map(|id| id.to_string()),
+        requests: 1,
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+    }
+}
+
+/// Prompt and completion price per million tokens. Configured prices win
+/// over the built-in ones; local backends are free unless configured.
+fn price(config: Option<&AIConfig>, provider: &AIProvider, model: &str) -> Option<(f64, f64)> {
+    let configured = config
+        .and_then(|c| c.prices.as_ref())
+        .into_iter()
+        .flatten()
+        .map(|p| (p.model.as_str(), p.prompt_per_million, p.completion_per_million));
+    if let Some(price) = longest_match(configured, model) {
+        return Some(price);
+    }
+    match provider {
+        AIProvider::Ollama | AIProvider::OpenAICompatible => Some((0.0, 0.0)),
+        AIProvider::OpenAI | AIProvider::Anthropic | AIProvider::Gemini => {
+            longest_match(PRICES.iter().copied(), model)
+        }
+    }
+}
+
+/// Whether the cost of the configured model is known, which a monthly
+/// budget needs: built in, configured, or a local backend.
+pub fn is_priced(config: &AIConfig) -> bool {
+    price(Some(config), &config.provider, configured_model(config)).is_some()
+}
+
+/// Most a request to the configured model can cost: its prompt at about
+/// four characters per token, and the longest reply it allows. `None` if
+/// the model's price is unknown.
+pub fn estimate(config: &AIConfig, request: &CompletionRequest) -> Option<f64> {
+    let prompt_chars = request.prompt.len() + request.system.as_ref().map_or(0, |s| s.len());
+    let prompt_tokens = prompt_chars.div_ceil(CHARS_PER_TOKEN) as u64;
+    cost(
+        Some(config),
+        &config.provider,
+        configured_model(config),
+        prompt_tokens,
+        request.max_tokens as u64,
+    )
+}
+
+fn configured_model(config: &AIConfig) -> &str {
+    config
+        .model
+        .as_deref()
+        .or(provider::default_model(&config.provider))
+        .unwrap_or_default()
+}
+
+fn longest_match<'a>(
+    prices: impl Iterator<Item = (&'a str, f64, f64)>,
+    model: &str,
+) -> Option<(f64, f64)> {
+    prices
+        .filter(|(prefix, ..)| model.starts_with(prefix))
+        .max_by_key(|(prefix, ..)| prefix.len())
+        .map(|(_, prompt, completion)| (prompt, completion))
+}
+
+fn cost(config: Option<&AIConfig>, provider: &AIProvider, model: &str, prompt: u64, completion: u64) -> Option<f64> {
+    let (prompt_price, completion_price) = price(config, provider, model)?;
+    Some((prompt as f64 * prompt_price + completion as f64 * completion_price) / 1_000_000.0)
+}
+
+/// Estimated cost of a record; zero if the model's price is unknown, which
+/// only happens without a budget, see `is_priced`.
+pub fn record_cost(record: &AIUsageRecord, config: Option<&AIConfig>) -> f64 {
+    cost(config, &record.provider, &record.model, record.prompt_tokens, record.completion_tokens).unwrap_or_default()
+}
+
+/// Estimated cost of the current month (UTC) so far. Usage of models
+/// without a known price counts as free.
+pub fn spent_this_month(records: &[AIUsageRecord], config: Option<&AIConfig>) -> f64 {
+    let month = chrono::Utc::now().format("%Y-%m").to_string();
+    records
+        .iter()
+        .filter(|r| r.date.starts_with(&month))
+        .map(|r| record_cost(r, config))
+        .sum()
+}
+
+/// What is left of the monthly budget, possibly negative; `None` if there
+/// is no budget.
+pub fn budget_left(records: &[AIUsageRecord], config: &AIConfig) -> Option<f64> {
+    config
+        .monthly_budget
+        .map(|budget| budget - spent_this_month(records, Some(config)))
+}
+
+/// Daily and monthly usage per provider, model and account, with costs.
+pub fn report(records: &[AIUsageRecord], config: Option<&AIConfig>) -> AIUsageReport {
+    let summarize = |period: &str, record: &AIUsageRecord| AIUsageSummary {
+        period: period.to_string(),
+        provider: record.provider.clone(),
+        model: record.model.clone(),
+        account_id: record.account_id.clone(),
+        requests: record.requests,
+        prompt_tokens: record.prompt_tokens,
+        completion_tokens: record.completion_tokens,
+        estimated_cost: None,
+    };
+
+    let mut daily: Vec<AIUsageSummary> = records.iter().map(|r| summarize(&r.date, r)).collect();
+    let mut monthly: Vec<AIUsageSummary> = Vec::new();
+    for record in records {
+        let month = record.date.get(..7).unwrap_or(&record.date);
+        let existing = monthly.iter_mut().find(|m| {
+            m.period == month
+                && m.provider == record.provider
+                && m.model == record.model
+                && m.account_id == record.account_id
+        });
+        match existing {
+            Some(existing) => {
+                existing.requests += record.requests;
+                existing.prompt_tokens += record.prompt_tokens;
+                existing.completion_tokens += record.completion_tokens;
+            }
+            None => monthly.push(summarize(month, record)),
+        }
+    }
+    for summary in daily.iter_mut().chain(monthly.iter_mut()) {
+        summary.estimated_cost = cost(
+            config,
+            &summary.provider,
+            &summary.model,
+            summary.prompt_tokens,
+            summary.completion_tokens,
+        );
+    }
+    daily.sort_by(|a, b| b.period.cmp(&a.period));
+    monthly.sort_by(|a, b| b.period.cmp(&a.period));
+
+    let monthly_budget = config.and_then(|c| c.monthly_budget);
+    let spent_this_month = spent_this_month(records, config);
+    AIUsageReport {
+        daily,
+        monthly,
+        monthly_budget,
+        spent_this_month,
+        budget_exceeded: monthly_budget.is_some_and(|budget| spent_this_month >= budget),
+    }
+}
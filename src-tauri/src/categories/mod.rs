@@ -0,0 +1,134 @@
+use crate::types::{Category, CategoryDefinition, Rule, RuleCondition};
+use anyhow::{anyhow, Result};
+
+impl Category {
+    pub fn id(&self) -> &str {
+        match self {
+            Category::Marketing => "marketing",
+            Category::Important => "important",
+            Category::Verification => "verification",
+            Category::Normal => "normal",
+            Category::Custom(id) => id,
+        }
+    }
+}
+
+impl From<String> for Category {
+    fn from(id: String) -> Self {
+        match id.as_str() {
+            "marketing" => Category::Marketing,
+            "important" => Category::Important,
+            "verification" => Category::Verification,
+            "normal" => Category::Normal,
+            _ => Category::Custom(id),
+        }
+    }
+}
+
+impl From<Category> for String {
+    fn from(category: Category) -> Self {
+        category.id().to_string()
+    }
+}
+
+fn builtin(category: Category, description: &str, color: &str, notify: bool) -> CategoryDefinition {
+    CategoryDefinition {
+        id: category.id().to_string(),
+        name: category.id().to_string(),
+        description: description.to_string(),
+        color: Some(color.to_string()),
+        notify,
+        actions: Vec::new(),
+    }
+}
+
+/// The built-in categories and the user's, with normal, the catch-all,
+/// last.
+pub fn all(custom: Vec<CategoryDefinition>) -> Vec<CategoryDefinition> {
+    let mut categories = vec![
+        builtin(
+            Category::Marketing,
+            "Promotions, newsletters, advertising and other bulk mail",
+            "#f59e0b",
+            false,
+        ),
+        builtin(
+            Category::Important,
+            "Personal or work mail that needs the reader's attention",
+            "#ef4444",
+            true,
+        ),
+        builtin(
+            Category::Verification,
+            "Sign-in codes, address confirmations and password resets",
+            "#3b82f6",
+            true,
+        ),
+    ];
+    categories.extend(custom);
+    categories.push(builtin(Category::Normal, "Anything else", "#6b7280", false));
+    categories
+}
+
+pub fn is_builtin(id: &str) -> bool {
+    !matches!(Category::from(id.to_string()), Category::Custom(_))
+}
+
+pub fn find<'a>(categories: &'a [CategoryDefinition], category: &Category) -> Option<&'a CategoryDefinition> {
+    categories.iter().find(|c| c.id == category.id())
+}
+
+/// The category with this name, as the AI answers, ignoring case.
+pub fn by_name(categories: &[CategoryDefinition], name: &str) -> Option<Category> {
+    let name = name.trim();
+    categories
+        .iter()
+        .find(|c| c.name.eq_ignore_ascii_case(name))
+        .map(|c| Category::from(c.id.clone()))
+}
+
+/// Whether emails of this category are notified about; not for categories
+/// that were deleted since.
+pub fn should_notify(categories: &[CategoryDefinition], category: &Category) -> bool {
+    find(categories, category).is_some_and(|c| c.notify)
+}
+
+/// The categories' actions as rules, to run before the user's.
+pub fn rules(categories: &[CategoryDefinition]) -> Vec<Rule> {
+    categories
+        .iter()
+        .filter(|c| !c.actions.is_empty())
+        .map(|c| Rule {
+            id: format!("category:{}", c.id),
+            name: c.name.clone(),
+            enabled: true,
+            match_all: true,
+            conditions: vec![RuleCondition::Category {
+                category: Category::from(c.id.clone()),
+            }],
+            actions: c.actions.clone(),
+            stop_processing: false,
+        })
+        .collect()
+}
+
+/// Checks a user-defined category against the others: it needs a name and
+/// a description, and its name must be unique so the AI's answer is.
+pub fn validate(category: &CategoryDefinition, custom: &[CategoryDefinition]) -> Result<()> {
+    if category.name.trim().is_empty() {
+        return Err(anyhow!("Category needs a name"));
+    }
+    if category.description.trim().is_empty() {
+        return Err(anyhow!("Category \"{}\" needs a description", category.name));
+    }
+    if is_builtin(&category.id) {
+        return Err(anyhow!("Built-in categories cannot be changed"));
+    }
+    let taken = all(custom.to_vec())
+        .iter()
+        .any(|c| c.id != category.id && c.name.eq_ignore_ascii_case(category.name.trim()));
+    if taken {
+        return Err(anyhow!("There already is a category named \"{}\"", category.name));
+    }
+    Ok(())
+}
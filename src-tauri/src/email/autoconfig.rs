@@ -0,0 +1,735 @@
+use super::net;
+use crate::storage::LegacyAccountConfig;
+use crate::types::{
+    AccountConfig, AccountSuggestion, AuthMethod, ConfigSource, Provider, SecurityMode, ServerConfig,
+    ServerProtocol, ServerSettings,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hickory_resolver::TokioResolver;
+use std::time::Duration;
+
+/// Fetches autoconfig documents over HTTP. Returns `Ok(None)` when the
+/// server has no document, so discovery can move on to the next source.
+#[async_trait]
+pub trait HttpFetcher: Send + Sync {
+    async fn get(&self, url: &str) -> Result<Option<String>>;
+    async fn post_xml(&self, url: &str, body: &str) -> Result<Option<String>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+#[async_trait]
+pub trait SrvResolver: Send + Sync {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>>;
+}
+
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+}
+
+impl ReqwestFetcher {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpFetcher for ReqwestFetcher {
+    async fn get(&self, url: &str) -> Result<Option<String>> {
+        let response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+        Ok(Some(response.text().await?))
+    }
+
+    async fn post_xml(&self, url: &str, body: &str) -> Result<Option<String>> {
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "text/xml")
+            .body(body.to_string())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+        Ok(Some(response.text().await?))
+    }
+}
+
+/// Resolves SRV records with the operating system's DNS configuration.
+pub struct SystemSrvResolver;
+
+#[async_trait]
+impl SrvResolver for SystemSrvResolver {
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        let resolver = TokioResolver::builder_tokio()?.build();
+        let Ok(lookup) = resolver.srv_lookup(name).await else {
+            return Ok(Vec::new());
+        };
+        Ok(lookup
+            .iter()
+            .filter(|srv| !srv.target().is_root())
+            .map(|srv| SrvRecord {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_utf8().trim_end_matches('.').to_string(),
+            })
+            .collect())
+    }
+}
+
+/// Proposes server settings for an email address. Sources are tried in
+/// order: built-in provider presets, Mozilla ISPDB-style autoconfig,
+/// Microsoft Autodiscover and finally RFC 6186 SRV records.
+pub struct Discovery<H: HttpFetcher, S: SrvResolver> {
+    http: H,
+    dns: S,
+}
+
+impl Discovery<ReqwestFetcher, SystemSrvResolver> {
+    pub fn system() -> Self {
+        Self::new(ReqwestFetcher::new(), SystemSrvResolver)
+    }
+}
+
+impl<H: HttpFetcher, S: SrvResolver> Discovery<H, S> {
+    pub fn new(http: H, dns: S) -> Self {
+        Self { http, dns }
+    }
+
+    pub async fn discover(&self, email: &str) -> Result<Option<AccountSuggestion>> {
+        let (local, domain) = email
+            .rsplit_once('@')
+            .filter(|(l, d)| !l.is_empty() && d.contains('.'))
+            .ok_or_else(|| anyhow!("{} is not a valid email address", email))?;
+        let domain = domain.to_lowercase();
+
+        if let Some(suggestion) = preset(email, &domain) {
+            return Ok(Some(suggestion));
+        }
+
+        let servers = self.autoconfig(email, local, &domain).await;
+        if let Some(suggestion) = servers.into_suggestion(email, ConfigSource::Autoconfig) {
+            return Ok(Some(suggestion));
+        }
+        let servers = self.autodiscover(email, &domain).await;
+        if let Some(suggestion) = servers.into_suggestion(email, ConfigSource::Autodiscover) {
+            return Ok(Some(suggestion));
+        }
+        Ok(self.srv(email, &domain).await.into_suggestion(email, ConfigSource::Srv))
+    }
+
+    async fn autoconfig(&self, email: &str, local: &str, domain: &str) -> Servers {
+        let urls = [
+            with_address(&format!("https://autoconfig.{}/mail/config-v1.1.xml", domain), email),
+            format!("https://{}/.well-known/autoconfig/mail/config-v1.1.xml", domain),
+            with_address(&format!("http://autoconfig.{}/mail/config-v1.1.xml", domain), email),
+            format!("https://autoconfig.thunderbird.net/v1.1/{}", domain),
+        ];
+        for url in urls {
+            let Ok(Some(xml)) = self.http.get(&url).await else {
+                continue;
+            };
+            match parse_autoconfig(&xml, email, local, domain) {
+                Ok(servers) if !servers.0.is_empty() => return servers,
+                _ => continue,
+            }
+        }
+        Servers::default()
+    }
+
+    async fn autodiscover(&self, email: &str, domain: &str) -> Servers {
+        let request = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<Autodiscover xmlns="http://schemas.microsoft.com/exchange/autodiscover/outlook/requestschema/2006">
+  <Request>
+    <EMailAddress>{}</EMailAddress>
+    <AcceptableResponseSchema>http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a</AcceptableResponseSchema>
+  </Request>
+</Autodiscover>"#,
+            email
+        );
+        let urls = [
+            format!("https://autodiscover.{}/autodiscover/autodiscover.xml", domain),
+            format!("https://{}/autodiscover/autodiscover.xml", domain),
+        ];
+        for url in urls {
+            let Ok(Some(xml)) = self.http.post_xml(&url, &request).await else {
+                continue;
+            };
+            match parse_autodiscover(&xml, email) {
+                Ok(servers) if !servers.0.is_empty() => return servers,
+                _ => continue,
+            }
+        }
+        Servers::default()
+    }
+
+    async fn srv(&self, email: &str, domain: &str) -> Servers {
+        const SERVICES: [(&str, ServerProtocol, SecurityMode); 7] = [
+            ("_jmap._tcp", ServerProtocol::Jmap, SecurityMode::Tls),
+            ("_imaps._tcp", ServerProtocol::Imap, SecurityMode::Tls),
+            ("_imap._tcp", ServerProtocol::Imap, SecurityMode::StartTls),
+            ("_pop3s._tcp", ServerProtocol::Pop3, SecurityMode::Tls),
+            ("_pop3._tcp", ServerProtocol::Pop3, SecurityMode::StartTls),
+            ("_submissions._tcp", ServerProtocol::Smtp, SecurityMode::Tls),
+            ("_submission._tcp", ServerProtocol::Smtp, SecurityMode::StartTls),
+        ];
+
+        let mut servers = Servers::default();
+        for (service, protocol, security) in SERVICES {
+            let Ok(mut records) = self.dns.lookup_srv(&format!("{}.{}", service, domain)).await else {
+                continue;
+            };
+            records.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+            for record in records {
+                servers.push(ServerSettings {
+                    protocol,
+                    host: record.target,
+                    port: record.port,
+                    security,
+                    username: Some(email.to_string()),
+                });
+            }
+        }
+        servers
+    }
+}
+
+/// `url` with the address as its percent-encoded `emailaddress` parameter,
+/// so `+`, `&` or `#` in the local part survive.
+fn with_address(url: &str, email: &str) -> String {
+    match reqwest::Url::parse_with_params(url, &[("emailaddress", email)]) {
+        Ok(url) => url.to_string(),
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Incoming and outgoing candidates, in order of preference.
+#[derive(Debug, Default)]
+struct Servers(Vec<ServerSettings>, Vec<ServerSettings>);
+
+impl Servers {
+    fn push(&mut self, server: ServerSettings) {
+        if server.protocol == ServerProtocol::Smtp {
+            self.1.push(server);
+        } else {
+            self.0.push(server);
+        }
+    }
+
+    /// A suggestion can only be made when at least one incoming server was found.
+    fn into_suggestion(self, email: &str, source: ConfigSource) -> Option<AccountSuggestion> {
+        if self.0.is_empty() {
+            return None;
+        }
+        Some(AccountSuggestion {
+            email: email.to_string(),
+            provider: provider_for_hosts(&self.0),
+            source,
+            incoming: self.0,
+            outgoing: self.1,
+        })
+    }
+}
+
+
+/// Parses a Mozilla ISPDB `clientConfig` document.
+fn parse_autoconfig(xml: &str, email: &str, local: &str, domain: &str) -> Result<Servers> {
+    let document = roxmltree::Document::parse(xml)?;
+    let mut servers = Servers::default();
+
+    for node in document
+        .descendants()
+        .filter(|n| n.has_tag_name("incomingServer") || n.has_tag_name("outgoingServer"))
+    {
+        let protocol = match node.attribute("type") {
+            Some("imap") => ServerProtocol::Imap,
+            Some("pop3") => ServerProtocol::Pop3,
+            Some("smtp") => ServerProtocol::Smtp,
+            Some("jmap") => ServerProtocol::Jmap,
+            _ => continue,
+        };
+        let child = |name: &str| {
+            node.children()
+                .find(|c| c.has_tag_name(name))
+                .and_then(|c| c.text())
+                .map(|t| t.trim().to_string())
+        };
+        let (Some(host), Some(port)) = (child("hostname"), child("port").and_then(|p| p.parse().ok())) else {
+            continue;
+        };
+        let security = match child("socketType").as_deref() {
+            Some("SSL") => SecurityMode::Tls,
+            Some("STARTTLS") => SecurityMode::StartTls,
+            _ => SecurityMode::None,
+        };
+        let username = child("username").map(|u| {
+            u.replace("%EMAILADDRESS%", email)
+                .replace("%EMAILLOCALPART%", local)
+                .replace("%EMAILDOMAIN%", domain)
+        });
+        servers.push(ServerSettings {
+            protocol,
+            host: host.replace("%EMAILDOMAIN%", domain),
+            port,
+            security,
+            username,
+        });
+    }
+    Ok(servers)
+}
+
+/// Parses a Microsoft Autodiscover (POX) response.
+fn parse_autodiscover(xml: &str, email: &str) -> Result<Servers> {
+    let document = roxmltree::Document::parse(xml)?;
+    let mut servers = Servers::default();
+
+    for node in document.descendants().filter(|n| n.has_tag_name("Protocol")) {
+        let child = |name: &str| {
+            node.children()
+                .find(|c| c.tag_name().name() == name)
+                .and_then(|c| c.text())
+                .map(|t| t.trim().to_string())
+        };
+        let protocol = match child("Type").as_deref() {
+            Some("IMAP") => ServerProtocol::Imap,
+            Some("POP3") => ServerProtocol::Pop3,
+            Some("SMTP") => ServerProtocol::Smtp,
+            _ => continue,
+        };
+        let (Some(host), Some(port)) = (child("Server"), child("Port").and_then(|p| p.parse().ok())) else {
+            continue;
+        };
+        let security = match (child("Encryption").as_deref(), child("SSL").as_deref()) {
+            (Some("SSL"), _) => SecurityMode::Tls,
+            (Some("TLS"), _) => SecurityMode::StartTls,
+            (Some("None"), _) | (None, Some("off")) => SecurityMode::None,
+            // Without an explicit encryption mode the port decides
+            _ if matches!(port, 465 | 993 | 995) => SecurityMode::Tls,
+            _ => SecurityMode::StartTls,
+        };
+        servers.push(ServerSettings {
+            protocol,
+            host,
+            port,
+            security,
+            username: child("LoginName").or_else(|| Some(email.to_string())),
+        });
+    }
+    Ok(servers)
+}
+
+fn provider_for_hosts(servers: &[ServerSettings]) -> Provider {
+    for server in servers {
+        let host = server.host.to_lowercase();
+        if host.ends_with("gmail.com") || host.ends_with("google.com") {
+            return Provider::Gmail;
+        }
+        if host.ends_with("office365.com") || host.ends_with("outlook.com") {
+            return Provider::Outlook;
+        }
+    }
+    Provider::Other
+}
+
+/// Converts an account config saved before incoming and outgoing servers
+/// were split. The old host becomes the incoming server. Known providers
+/// also get their SMTP server, with the same credentials.
+pub fn migrate_legacy_config(legacy: LegacyAccountConfig, email: &str) -> AccountConfig {
+    let auth = if legacy.oauth_token.is_some() {
+        AuthMethod::OAuth2
+    } else {
+        AuthMethod::Password
+    };
+    let outgoing = known_provider(email)
+        .and_then(|known| known.outgoing.into_iter().next())
+        .map(|smtp| ServerConfig {
+            host: Some(smtp.host),
+            port: Some(smtp.port),
+            username: legacy.username.clone(),
+            password: legacy.password.clone(),
+            security: smtp.security,
+            auth,
+        });
+    AccountConfig {
+        incoming: ServerConfig {
+            host: legacy.host,
+            port: legacy.port,
+            username: legacy.username,
+            password: legacy.password,
+            security: legacy.port.map(net::security_for_port).unwrap_or_default(),
+            auth,
+        },
+        outgoing,
+        sieve: None,
+        oauth_token: legacy.oauth_token,
+        refresh_token: legacy.refresh_token,
+        path: legacy.path,
+    }
+}
+
+/// Looks up the settings of a well-known provider for an address, without
+/// any network lookup.
+pub fn known_provider(email: &str) -> Option<AccountSuggestion> {
+    let (_, domain) = email.rsplit_once('@')?;
+    preset(email, &domain.to_lowercase())
+}
+
+/// Settings for well-known providers, available without any network lookup.
+fn preset(email: &str, domain: &str) -> Option<AccountSuggestion> {
+    use SecurityMode::{StartTls, Tls};
+    use ServerProtocol::{Imap, Jmap, Pop3, Smtp};
+
+    type Preset = (Provider, &'static [(ServerProtocol, &'static str, u16, SecurityMode)]);
+    let (provider, servers): Preset = match domain {
+        "gmail.com" | "googlemail.com" => (
+            Provider::Gmail,
+            &[
+                (Imap, "imap.gmail.com", 993, Tls),
+                (Pop3, "pop.gmail.com", 995, Tls),
+                (Smtp, "smtp.gmail.com", 465, Tls),
+                (Smtp, "smtp.gmail.com", 587, StartTls),
+            ],
+        ),
+        "outlook.com" | "hotmail.com" | "live.com" | "msn.com" => (
+            Provider::Outlook,
+            &[
+                (Imap, "outlook.office365.com", 993, Tls),
+                (Pop3, "outlook.office365.com", 995, Tls),
+                (Smtp, "smtp-mail.outlook.com", 587, StartTls),
+            ],
+        ),
+        "yahoo.com" | "ymail.com" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.mail.yahoo.com", 993, Tls),
+                (Pop3, "pop.mail.yahoo.com", 995, Tls),
+                (Smtp, "smtp.mail.yahoo.com", 465, Tls),
+            ],
+        ),
+        "icloud.com" | "me.com" | "mac.com" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.mail.me.com", 993, Tls),
+                (Smtp, "smtp.mail.me.com", 587, StartTls),
+            ],
+        ),
+        "fastmail.com" | "fastmail.fm" => (
+            Provider::Other,
+            &[
+                (Jmap, "api.fastmail.com", 443, Tls),
+                (Imap, "imap.fastmail.com", 993, Tls),
+                (Pop3, "pop.fastmail.com", 995, Tls),
+                (Smtp, "smtp.fastmail.com", 465, Tls),
+            ],
+        ),
+        "aol.com" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.aol.com", 993, Tls),
+                (Pop3, "pop.aol.com", 995, Tls),
+                (Smtp, "smtp.aol.com", 465, Tls),
+            ],
+        ),
+        "gmx.com" | "gmx.net" | "gmx.de" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.gmx.com", 993, Tls),
+                (Pop3, "pop.gmx.com", 995, Tls),
+                (Smtp, "mail.gmx.com", 587, StartTls),
+            ],
+        ),
+        "zoho.com" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.zoho.com", 993, Tls),
+                (Pop3, "pop.zoho.com", 995, Tls),
+                (Smtp, "smtp.zoho.com", 465, Tls),
+            ],
+        ),
+        "qq.com" | "foxmail.com" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.qq.com", 993, Tls),
+                (Pop3, "pop.qq.com", 995, Tls),
+                (Smtp, "smtp.qq.com", 465, Tls),
+            ],
+        ),
+        "163.com" => (
+            Provider::Other,
+            &[
+                (Imap, "imap.163.com", 993, Tls),
+                (Pop3, "pop.163.com", 995, Tls),
+                (Smtp, "smtp.163.com", 465, Tls),
+            ],
+        ),
+        _ => return None,
+    };
+
+    let mut found = Servers::default();
+    for &(protocol, host, port, security) in servers {
+        found.push(ServerSettings {
+            protocol,
+            host: host.to_string(),
+            port,
+            security,
+            username: Some(email.to_string()),
+        });
+    }
+    Some(AccountSuggestion {
+        email: email.to_string(),
+        provider,
+        source: ConfigSource::Preset,
+        incoming: found.0,
+        outgoing: found.1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const AUTOCONFIG: &str = r#"<?xml version="1.0"?>
+<clientConfig version="1.1">
+  <emailProvider id="example.org">
+    <domain>example.org</domain>
+    <incomingServer type="imap">
+      <hostname>imap.%EMAILDOMAIN%</hostname>
+      <port>993</port>
+      <socketType>SSL</socketType>
+      <username>%EMAILLOCALPART%</username>
+    </incomingServer>
+    <incomingServer type="pop3">
+      <hostname>pop.example.org</hostname>
+      <port>110</port>
+      <socketType>STARTTLS</socketType>
+      <username>%EMAILADDRESS%</username>
+    </incomingServer>
+    <incomingServer type="exchange">
+      <hostname>ews.example.org</hostname>
+      <port>443</port>
+    </incomingServer>
+    <outgoingServer type="smtp">
+      <hostname>smtp.example.org</hostname>
+      <port>587</port>
+      <socketType>STARTTLS</socketType>
+    </outgoingServer>
+  </emailProvider>
+</clientConfig>"#;
+
+    const AUTODISCOVER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<Autodiscover xmlns="http://schemas.microsoft.com/exchange/autodiscover/responseschema/2006">
+  <Response xmlns="http://schemas.microsoft.com/exchange/autodiscover/outlook/responseschema/2006a">
+    <Account>
+      <Protocol>
+        <Type>IMAP</Type>
+        <Server>mail.example.net</Server>
+        <Port>993</Port>
+        <LoginName>jane</LoginName>
+        <SSL>on</SSL>
+      </Protocol>
+      <Protocol>
+        <Type>SMTP</Type>
+        <Server>mail.example.net</Server>
+        <Port>587</Port>
+        <Encryption>TLS</Encryption>
+      </Protocol>
+      <Protocol>
+        <Type>EXCH</Type>
+        <Server>exchange.example.net</Server>
+      </Protocol>
+    </Account>
+  </Response>
+</Autodiscover>"#;
+
+    /// Serves documents by URL and records every URL asked for.
+    #[derive(Default)]
+    struct FakeHttp {
+        documents: HashMap<String, String>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl HttpFetcher for FakeHttp {
+        async fn get(&self, url: &str) -> Result<Option<String>> {
+            self.requested.lock().unwrap().push(url.to_string());
+            Ok(self.documents.get(url).cloned())
+        }
+
+        async fn post_xml(&self, url: &str, body: &str) -> Result<Option<String>> {
+            assert!(body.contains("<EMailAddress>"));
+            self.get(url).await
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeDns(HashMap<String, Vec<SrvRecord>>);
+
+    #[async_trait]
+    impl SrvResolver for FakeDns {
+        async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+            Ok(self.0.get(name).cloned().unwrap_or_default())
+        }
+    }
+
+    fn srv(priority: u16, weight: u16, target: &str, port: u16) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target: target.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_autoconfig() {
+        let servers = parse_autoconfig(AUTOCONFIG, "jane@example.org", "jane", "example.org").unwrap();
+        let [imap, pop] = servers.0.as_slice() else {
+            panic!("expected two incoming servers, got {:?}", servers.0);
+        };
+        assert_eq!(imap.protocol, ServerProtocol::Imap);
+        assert_eq!(imap.host, "imap.example.org");
+        assert_eq!(imap.port, 993);
+        assert_eq!(imap.security, SecurityMode::Tls);
+        assert_eq!(imap.username.as_deref(), Some("jane"));
+        assert_eq!(pop.protocol, ServerProtocol::Pop3);
+        assert_eq!(pop.security, SecurityMode::StartTls);
+        assert_eq!(pop.username.as_deref(), Some("jane@example.org"));
+
+        let [smtp] = servers.1.as_slice() else {
+            panic!("expected one outgoing server, got {:?}", servers.1);
+        };
+        assert_eq!(smtp.host, "smtp.example.org");
+        assert_eq!(smtp.port, 587);
+        assert_eq!(smtp.username, None);
+    }
+
+    #[test]
+    fn parses_autodiscover() {
+        let servers = parse_autodiscover(AUTODISCOVER, "jane@example.net").unwrap();
+        let [imap] = servers.0.as_slice() else {
+            panic!("expected one incoming server, got {:?}", servers.0);
+        };
+        assert_eq!(imap.host, "mail.example.net");
+        assert_eq!(imap.security, SecurityMode::Tls);
+        assert_eq!(imap.username.as_deref(), Some("jane"));
+
+        let [smtp] = servers.1.as_slice() else {
+            panic!("expected one outgoing server, got {:?}", servers.1);
+        };
+        assert_eq!(smtp.security, SecurityMode::StartTls);
+        assert_eq!(smtp.username.as_deref(), Some("jane@example.net"));
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert!(parse_autoconfig("<clientConfig>", "a@b.c", "a", "b.c").is_err());
+        assert!(parse_autodiscover("not xml", "a@b.c").is_err());
+    }
+
+    #[tokio::test]
+    async fn presets_need_no_lookup() {
+        let discovery = Discovery::new(FakeHttp::default(), FakeDns::default());
+        let suggestion = discovery.discover("someone@Gmail.com").await.unwrap().unwrap();
+        assert!(matches!(suggestion.source, ConfigSource::Preset));
+        assert_eq!(suggestion.incoming[0].host, "imap.gmail.com");
+        assert!(discovery.http.requested.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn autoconfig_url_encodes_the_address() {
+        let url = "https://autoconfig.example.org/mail/config-v1.1.xml?emailaddress=jane%2Bnews%26co%40example.org";
+        let http = FakeHttp {
+            documents: HashMap::from([(url.to_string(), AUTOCONFIG.to_string())]),
+            ..Default::default()
+        };
+        let discovery = Discovery::new(http, FakeDns::default());
+        let suggestion = discovery.discover("jane+news&co@example.org").await.unwrap().unwrap();
+        assert!(matches!(suggestion.source, ConfigSource::Autoconfig));
+        assert_eq!(suggestion.incoming[0].username.as_deref(), Some("jane+news&co"));
+        assert_eq!(discovery.http.requested.lock().unwrap().as_slice(), [url]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_autodiscover() {
+        let http = FakeHttp {
+            documents: HashMap::from([(
+                "https://example.net/autodiscover/autodiscover.xml".to_string(),
+                AUTODISCOVER.to_string(),
+            )]),
+            ..Default::default()
+        };
+        let discovery = Discovery::new(http, FakeDns::default());
+        let suggestion = discovery.discover("jane@example.net").await.unwrap().unwrap();
+        assert!(matches!(suggestion.source, ConfigSource::Autodiscover));
+        assert_eq!(suggestion.incoming[0].host, "mail.example.net");
+        // All four autoconfig locations and the first autodiscover one were tried first
+        assert_eq!(discovery.http.requested.lock().unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn orders_srv_records_by_priority_then_weight() {
+        let dns = FakeDns(HashMap::from([
+            (
+                "_imaps._tcp.example.com".to_string(),
+                vec![
+                    srv(20, 0, "backup.example.com", 993),
+                    srv(10, 10, "light.example.com", 993),
+                    srv(10, 60, "heavy.example.com", 993),
+                ],
+            ),
+            ("_imap._tcp.example.com".to_string(), vec![srv(0, 0, "plain.example.com", 143)]),
+            ("_submission._tcp.example.com".to_string(), vec![srv(0, 0, "smtp.example.com", 587)]),
+        ]));
+        let discovery = Discovery::new(FakeHttp::default(), dns);
+        let suggestion = discovery.discover("joe@example.com").await.unwrap().unwrap();
+
+        assert!(matches!(suggestion.source, ConfigSource::Srv));
+        let hosts: Vec<(&str, SecurityMode)> = suggestion
+            .incoming
+            .iter()
+            .map(|s| (s.host.as_str(), s.security))
+            .collect();
+        // Implicit TLS services come before STARTTLS ones, whatever their priority
+        assert_eq!(
+            hosts,
+            [
+                ("heavy.example.com", SecurityMode::Tls),
+                ("light.example.com", SecurityMode::Tls),
+                ("backup.example.com", SecurityMode::Tls),
+                ("plain.example.com", SecurityMode::StartTls),
+            ]
+        );
+        assert_eq!(suggestion.outgoing[0].host, "smtp.example.com");
+        assert_eq!(suggestion.outgoing[0].username.as_deref(), Some("joe@example.com"));
+    }
+
+    #[tokio::test]
+    async fn finds_nothing_without_incoming_servers() {
+        let dns = FakeDns(HashMap::from([(
+            "_submission._tcp.example.com".to_string(),
+            vec![srv(0, 0, "smtp.example.com", 587)],
+        )]));
+        let discovery = Discovery::new(FakeHttp::default(), dns);
+        assert!(discovery.discover("joe@example.com").await.unwrap().is_none());
+        assert!(discovery.discover("not-an-address").await.is_err());
+    }
+}
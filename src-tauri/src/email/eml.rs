@@ -0,0 +1,112 @@
+use super::mime;
+use crate::storage::Store;
+use crate::types::ImportProgress;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Imports `.eml` files into `account_id`. The file contents are kept as the
+/// message source, so exporting the message again yields the identical bytes.
+pub fn import(
+    store: &Store,
+    paths: &[PathBuf],
+    account_id: &str,
+    folder: Option<&str>,
+) -> Result<ImportProgress> {
+    let mut progress = ImportProgress::default();
+    let mut seen = super::stored_import_keys(store, account_id)?;
+    let mut imported = Vec::new();
+
+    for path in paths {
+        progress.processed += 1;
+        let Ok(raw) = fs::read(path) else {
+            progress.failed += 1;
+            continue;
+        };
+        progress.bytes_read += raw.len() as u64;
+        progress.total_bytes += raw.len() as u64;
+
+        let Ok(mut email) = mime::parse_email(&raw, account_id) else {
+            progress.failed += 1;
+            continue;
+        };
+        if !seen.insert(super::import_key(&email, &raw)) {
+            progress.duplicates += 1;
+            continue;
+        }
+
+        email.folder = folder.map(|f| f.to_string());
+        store.save_raw(&email.id, &raw)?;
+        imported.push(email);
+        progress.imported += 1;
+    }
+
+    super::add_imported(store, imported)?;
+    Ok(progress)
+}
+
+/// Writes the source of a stored email to `path`.
+pub fn export(store: &Store, id: &str, path: &Path) -> Result<()> {
+    let email = store
+        .get_email(id)?
+        .ok_or_else(|| anyhow!("Email {} not found", id))?;
+    fs::write(path, super::raw_source(store, &email)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::autoconfig;
+
+    const RAW: &[u8] = b"Message-ID: <a@example.com>\r\nFrom: Ann <ann@example.com>\r\nSubject: Hi\r\n\r\nHello\r\n";
+
+    #[test]
+    fn exports_the_imported_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("in.eml");
+        fs::write(&source, RAW).unwrap();
+        let store = Store::new(dir.path().join("data"), autoconfig::migrate_legacy_config).unwrap();
+
+        let progress = import(&store, &[source], "acc", Some("Archive")).unwrap();
+        assert_eq!((progress.processed, progress.imported), (1, 1));
+        assert_eq!(progress.total_bytes, RAW.len() as u64);
+
+        let email = store.get_emails().unwrap().remove(0);
+        assert_eq!(email.folder.as_deref(), Some("Archive"));
+        let target = dir.path().join("out.eml");
+        export(&store, &email.id, &target).unwrap();
+        assert_eq!(fs::read(target).unwrap(), RAW);
+    }
+
+    #[test]
+    fn skips_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.eml");
+        let copy = dir.path().join("copy.eml");
+        fs::write(&first, RAW).unwrap();
+        fs::write(&copy, RAW).unwrap();
+        let store = Store::new(dir.path().join("data"), autoconfig::migrate_legacy_config).unwrap();
+
+        let progress = import(&store, &[first.clone(), copy], "acc", None).unwrap();
+        assert_eq!((progress.imported, progress.duplicates), (1, 1));
+        let again = import(&store, &[first], "acc", None).unwrap();
+        assert_eq!((again.imported, again.duplicates), (0, 1));
+        assert_eq!(store.get_emails().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn counts_unparsable_and_unreadable_files_as_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.eml");
+        let good = dir.path().join("good.eml");
+        fs::write(&empty, b"").unwrap();
+        fs::write(&good, RAW).unwrap();
+        let store = Store::new(dir.path().join("data"), autoconfig::migrate_legacy_config).unwrap();
+
+        let paths = [empty, dir.path().join("missing.eml"), good];
+        let progress = import(&store, &paths, "acc", None).unwrap();
+        assert_eq!(progress.processed, 3);
+        assert_eq!((progress.imported, progress.failed), (1, 2));
+    }
+}
@@ -0,0 +1,551 @@
+use super::net::{self, Connection};
+use super::{autoconfig, jmap, maildir, smtp, Credentials};
+use crate::storage::INBOX;
+use crate::types::{
+    EmailAccount, HealthReport, HealthStage, Protocol, SecurityMode, ServerCheck, ServerConfig,
+    ServerProtocol, StageResult,
+};
+use anyhow::{anyhow, Result};
+use std::time::Instant;
+
+/// One server of an account and how to reach it.
+struct Target {
+    protocol: ServerProtocol,
+    host: String,
+    port: u16,
+    security: SecurityMode,
+    /// Resolved lazily so that missing credentials fail the auth stage.
+    credentials: Result<Credentials>,
+}
+
+/// Connects to every server of an account step by step (DNS, TCP, TLS,
+/// authentication, folders) and reports where it fails.
+pub async fn check_account(account: &EmailAccount) -> HealthReport {
+    let mut report = HealthReport {
+        account_id: account.id.clone(),
+        ok: true,
+        checked_at: chrono::Utc::now().to_rfc3339(),
+        servers: Vec::new(),
+        error: None,
+    };
+
+    match account.protocol {
+        Protocol::Local => {}
+        Protocol::Maildir => {
+            let folders = maildir::account_root(account).and_then(|root| maildir::folder_names(&root));
+            if let Err(e) = folders {
+                report.error = Some(e.to_string());
+            }
+        }
+        Protocol::Jmap => report.servers.push(check_jmap(account).await),
+        Protocol::Imap | Protocol::Pop3 | Protocol::OAuth2 => match targets(account) {
+            Ok(targets) => {
+                for target in &targets {
+                    report.servers.push(check_server(target).await);
+                }
+            }
+            Err(e) => report.error = Some(e.to_string()),
+        },
+    }
+
+    report.ok = report.error.is_none() && report.servers.iter().all(|s| s.failed_stage.is_none());
+    report
+}
+
+/// The incoming server falls back to the provider's known settings when no
+/// host is configured, e.g. for OAuth2 accounts.
+fn targets(account: &EmailAccount) -> Result<Vec<Target>> {
+    let config = &account.config;
+    let protocol = match account.protocol {
+        Protocol::Pop3 => ServerProtocol::Pop3,
+        _ => ServerProtocol::Imap,
+    };
+
+    let incoming = match &config.incoming.host {
+        Some(host) => Target {
+            protocol,
+            host: host.clone(),
+            port: config.incoming.port.unwrap_or(match (protocol, config.incoming.security) {
+                (ServerProtocol::Pop3, SecurityMode::Tls) => 995,
+                (ServerProtocol::Pop3, _) => 110,
+                (_, SecurityMode::Tls) => 993,
+                _ => 143,
+            }),
+            security: config.incoming.security,
+            credentials: Credentials::for_server(account, &config.incoming),
+        },
+        None => autoconfig::known_provider(&account.email)
+            .iter()
+            .flat_map(|k| &k.incoming)
+            .find(|s| s.protocol == protocol)
+            .map(|s| Target {
+                protocol,
+                host: s.host.clone(),
+                port: s.port,
+                security: s.security,
+                credentials: Credentials::for_server(account, &config.incoming),
+            })
+            .ok_or_else(|| anyhow!("Account {} has no server configured", account.id))?,
+    };
+
+    let mut targets = vec![incoming];
+    if let Some(outgoing) = &config.outgoing {
+        targets.push(smtp_target(account, outgoing)?);
+    }
+    for identity in account.identities.iter().flatten() {
+        if let Some(smtp) = &identity.smtp {
+            targets.push(smtp_target(account, smtp)?);
+        }
+    }
+    Ok(targets)
+}
+
+fn smtp_target(account: &EmailAccount, server: &ServerConfig) -> Result<Target> {
+    let host = server
+        .host
+        .clone()
+        .ok_or_else(|| anyhow!("Account {} has no SMTP server configured", account.id))?;
+    Ok(Target {
+        protocol: ServerProtocol::Smtp,
+        host,
+        port: server.port.unwrap_or(match server.security {
+            SecurityMode::Tls => 465,
+            _ => 587,
+        }),
+        security: server.security,
+        credentials: Credentials::for_server(account, server),
+    })
+}
+
+/// Collects stage results for one server.
+struct Probe {
+    check: ServerCheck,
+    started: Instant,
+}
+
+impl Probe {
+    fn new(target: &Target) -> Self {
+        Self {
+            check: ServerCheck {
+                protocol: target.protocol,
+                host: target.host.clone(),
+                port: target.port,
+                security: target.security,
+                stages: Vec::new(),
+                failed_stage: None,
+                folders: None,
+            },
+            started: Instant::now(),
+        }
+    }
+
+    /// Records a stage that ran since the previous one and returns its value
+    /// if it succeeded, so that callers can stop at the first failure.
+    fn record<T>(
+        &mut self,
+        stage: HealthStage,
+        result: Result<(T, String)>,
+        responses: Vec<String>,
+    ) -> Option<T> {
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        self.started = Instant::now();
+
+        let (value, detail) = match result {
+            Ok((value, detail)) => (Some(value), detail),
+            Err(e) => (None, e.to_string()),
+        };
+        if value.is_none() {
+            self.check.failed_stage = Some(stage);
+        }
+        self.check.stages.push(StageResult {
+            stage,
+            ok: value.is_some(),
+            detail,
+            server_response: (!responses.is_empty()).then(|| responses.join("\n")),
+            duration_ms,
+        });
+        value
+    }
+}
+
+async fn check_server(target: &Target) -> ServerCheck {
+    let mut probe = Probe::new(target);
+    run_stages(&mut probe, target).await;
+    probe.check
+}
+
+async fn run_stages(probe: &mut Probe, target: &Target) -> Option<()> {
+    let host = target.host.as_str();
+    let protocol = target.protocol;
+
+    let addrs = net::resolve(host, target.port).await.map(|addrs| {
+        let ips: Vec<String> = addrs.iter().map(|a| a.ip().to_string()).collect();
+        let detail = format!("Resolved to {}", ips.join(", "));
+        (addrs, detail)
+    });
+    let addrs = probe.record(HealthStage::Dns, addrs, Vec::new())?;
+
+    let stream = net::connect(&addrs).await;
+    let stream = probe.record(
+        HealthStage::Connect,
+        stream.map(|s| {
+            let detail = match s.peer_addr() {
+                Ok(addr) => format!("Connected to {}", addr),
+                Err(_) => "Connected".to_string(),
+            };
+            (s, detail)
+        }),
+        Vec::new(),
+    )?;
+
+    let mut conn = match target.security {
+        SecurityMode::Tls => {
+            let conn = Connection::tls(stream, host).await;
+            probe.record(
+                HealthStage::Tls,
+                conn.map(|c| (c, "TLS established".to_string())),
+                Vec::new(),
+            )?
+        }
+        SecurityMode::StartTls | SecurityMode::None => Connection::plain(stream, host),
+    };
+
+    let result = greet(&mut conn, protocol).await;
+    let responses = conn.take_responses();
+    probe.record(
+        HealthStage::Greeting,
+        result.map(|()| ((), "Server is ready".to_string())),
+        responses,
+    )?;
+
+    if target.security == SecurityMode::StartTls {
+        if let Err(e) = request_tls(&mut conn, protocol).await {
+            let responses = conn.take_responses();
+            return probe.record(HealthStage::Tls, Err(e), responses);
+        }
+        let responses = conn.take_responses();
+        let upgraded = match conn.start_tls().await {
+            Ok(mut conn) => match protocol {
+                ServerProtocol::Smtp => smtp::command(&mut conn, "EHLO localhost", 250)
+                    .await
+                    .map(|_| conn),
+                _ => Ok(conn),
+            },
+            Err(e) => Err(e),
+        };
+        conn = probe.record(
+            HealthStage::Tls,
+            upgraded.map(|c| (c, "STARTTLS negotiated".to_string())),
+            responses,
+        )?;
+    }
+
+    let result = match &target.credentials {
+        Ok(credentials) => authenticate(&mut conn, protocol, credentials).await,
+        Err(e) => Err(anyhow!("{}", e)),
+    };
+    let responses = conn.take_responses();
+    probe.record(HealthStage::Auth, result.map(|d| ((), d)), responses)?;
+
+    if protocol != ServerProtocol::Smtp {
+        let result = list_folders(&mut conn, protocol).await;
+        let responses = conn.take_responses();
+        let folders = probe.record(HealthStage::Folders, result, responses)?;
+        probe.check.folders = Some(folders);
+    }
+
+    let _ = logout(&mut conn, protocol).await;
+    Some(())
+}
+
+async fn check_jmap(account: &EmailAccount) -> ServerCheck {
+    let server = &account.config.incoming;
+    let mut probe = Probe::new(&Target {
+        protocol: ServerProtocol::Jmap,
+        host: server.host.clone().unwrap_or_default(),
+        port: server.port.unwrap_or(443),
+        security: SecurityMode::Tls,
+        credentials: Credentials::for_server(account, server),
+    });
+
+    let client = jmap::JmapClient::connect(account)
+        .await
+        .map(|c| (c, "Session established".to_string()));
+    if let Some(client) = probe.record(HealthStage::Auth, client, Vec::new()) {
+        let folders = client
+            .folder_names()
+            .await
+            .map(|f| (f.clone(), format!("{} folders", f.len())));
+        probe.check.folders = probe.record(HealthStage::Folders, folders, Vec::new());
+    }
+    probe.check
+}
+
+/// Reads the server greeting; SMTP clients also introduce themselves.
+async fn greet(conn: &mut Connection, protocol: ServerProtocol) -> Result<()> {
+    match protocol {
+        ServerProtocol::Imap => {
+            let line = conn.read_line().await?;
+            if !line.starts_with("* OK") && !line.starts_with("* PREAUTH") {
+                return Err(anyhow!("Unexpected IMAP greeting"));
+            }
+        }
+        ServerProtocol::Pop3 => {
+            let line = conn.read_line().await?;
+            if !line.starts_with("+OK") {
+                return Err(anyhow!("Unexpected POP3 greeting"));
+            }
+        }
+        ServerProtocol::Smtp => {
+            smtp::reply(conn, 220).await?;
+            smtp::command(conn, "EHLO localhost", 250).await?;
+        }
+        ServerProtocol::Jmap => {}
+    }
+    Ok(())
+}
+
+async fn request_tls(conn: &mut Connection, protocol: ServerProtocol) -> Result<()> {
+    match protocol {
+        ServerProtocol::Imap => imap_command(conn, "a1", "STARTTLS").await.map(|_| ()),
+        ServerProtocol::Pop3 => pop3_command(conn, "STLS").await.map(|_| ()),
+        ServerProtocol::Smtp => smtp::command(conn, "STARTTLS", 220).await.map(|_| ()),
+        ServerProtocol::Jmap => Ok(()),
+    }
+}
+
+async fn authenticate(
+    conn: &mut Connection,
+    protocol: ServerProtocol,
+    credentials: &Credentials,
+) -> Result<String> {
+    match (protocol, credentials) {
+        (_, Credentials::None) => return Ok("No authentication configured".to_string()),
+        (ServerProtocol::Smtp, _) => smtp::authenticate(conn, credentials).await?,
+        (ServerProtocol::Imap, Credentials::Password { username, password }) => {
+            let command = format!("LOGIN {} {}", imap_quote(username), imap_quote(password));
+            imap_command(conn, "a2", &command).await?;
+        }
+        (ServerProtocol::Imap, Credentials::OAuth2 { .. }) => {
+            let xoauth2 = credentials.xoauth2().unwrap_or_default();
+            imap_command(conn, "a2", &format!("AUTHENTICATE XOAUTH2 {}", xoauth2)).await?;
+        }
+        (ServerProtocol::Pop3, Credentials::Password { username, password }) => {
+            pop3_command(conn, &format!("USER {}", username)).await?;
+            pop3_command(conn, &format!("PASS {}", password)).await?;
+        }
+        _ => return Err(anyhow!("Authentication method is not supported for {:?}", protocol)),
+    }
+    Ok("Authenticated".to_string())
+}
+
+async fn list_folders(conn: &mut Connection, protocol: ServerProtocol) -> Result<(Vec<String>, String)> {
+    match protocol {
+        ServerProtocol::Imap => {
+            let lines = imap_command(conn, "a3", "LIST \"\" \"*\"").await?;
+            let folders: Vec<String> = lines.iter().filter_map(|l| list_name(l)).collect();
+            let detail = format!("{} folders", folders.len());
+            Ok((folders, detail))
+        }
+        ServerProtocol::Pop3 => {
+            // POP3 only knows the inbox
+            let line = pop3_command(conn, "STAT").await?;
+            let count = line.split_whitespace().nth(1).unwrap_or("0");
+            Ok((vec![INBOX.to_string()], format!("{} messages in {}", count, INBOX)))
+        }
+        _ => Ok((Vec::new(), String::new())),
+    }
+}
+
+async fn logout(conn: &mut Connection, protocol: ServerProtocol) -> Result<()> {
+    match protocol {
+        ServerProtocol::Imap => conn.write_line("a4 LOGOUT").await,
+        ServerProtocol::Pop3 | ServerProtocol::Smtp => conn.write_line("QUIT").await,
+        ServerProtocol::Jmap => Ok(()),
+    }
+}
+
+/// Sends a tagged IMAP command and returns the untagged lines of its response.
+async fn imap_command(conn: &mut Connection, tag: &str, command: &str) -> Result<Vec<String>> {
+    conn.write_line(&format!("{} {}", tag, command)).await?;
+    let mut untagged = Vec::new();
+    loop {
+        let line = conn.read_line().await?;
+        if let Some(status) = line.strip_prefix(tag).and_then(|l| l.strip_prefix(' ')) {
+            if status.starts_with("OK") {
+                return Ok(untagged);
+            }
+            return Err(anyhow!("Server rejected {}: {}", net::verb(command), status));
+        }
+        if line.starts_with('+') {
+            // A failed SASL exchange sends its error as a challenge that must be answered
+            conn.write_line("").await?;
+            continue;
+        }
+        untagged.push(line);
+    }
+}
+
+async fn pop3_command(conn: &mut Connection, command: &str) -> Result<String> {
+    conn.write_line(command).await?;
+    let line = conn.read_line().await?;
+    if line.starts_with("+OK") {
+        Ok(line)
+    } else {
+        Err(anyhow!("Server rejected {}: {}", net::verb(command), line))
+    }
+}
+
+fn imap_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Extracts the mailbox name from an untagged `* LIST (flags) "/" name` line.
+fn list_name(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("* LIST ")?;
+    let rest = rest[rest.find(')')? + 1..].trim_start();
+    let rest = match rest.strip_prefix("NIL") {
+        Some(rest) => rest,
+        None => {
+            let delimiter = rest.strip_prefix('"')?;
+            &delimiter[delimiter.find('"')? + 1..]
+        }
+    };
+    let name = rest.trim();
+    Some(match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => name.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A plain-text server that sends `greeting` and answers each command
+    /// line with `answer(command)` until the client logs out.
+    async fn server(greeting: &'static str, answer: fn(&str) -> &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(format!("{}\r\n", greeting).as_bytes()).await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.ends_with("LOGOUT") || line == "QUIT" {
+                    break;
+                }
+                write.write_all(format!("{}\r\n", answer(&line)).as_bytes()).await.unwrap();
+            }
+        });
+        port
+    }
+
+    fn target(protocol: ServerProtocol, port: u16) -> Target {
+        Target {
+            protocol,
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SecurityMode::None,
+            credentials: Ok(Credentials::Password {
+                username: "me".to_string(),
+                password: "se\"cret".to_string(),
+            }),
+        }
+    }
+
+    fn stages(check: &ServerCheck) -> Vec<(HealthStage, bool)> {
+        check.stages.iter().map(|s| (s.stage, s.ok)).collect()
+    }
+
+    fn imap(command: &str) -> &'static str {
+        match command {
+            r#"a2 LOGIN "me" "se\"cret""# => "a2 OK Logged in",
+            r#"a3 LIST "" "*""# => concat!(
+                "* LIST (\\HasNoChildren) \"/\" INBOX\r\n",
+                "* LIST (\\HasChildren) \".\" \"Work.Projects\"\r\n",
+                "* LIST (\\Noselect) NIL \"Say \\\"hi\\\"\"\r\n",
+                "a3 OK LIST completed",
+            ),
+            _ => "a9 BAD Unexpected command",
+        }
+    }
+
+    #[test]
+    fn list_names_with_quoted_and_nil_delimiters() {
+        assert_eq!(list_name(r#"* LIST (\HasNoChildren) "/" INBOX"#).as_deref(), Some("INBOX"));
+        assert_eq!(list_name(r#"* LIST () "." "Work.Projects""#).as_deref(), Some("Work.Projects"));
+        assert_eq!(list_name(r#"* LIST (\Noselect) NIL "a \"b\" \\ c""#).as_deref(), Some(r#"a "b" \ c"#));
+        assert_eq!(list_name("* LIST () NIL Archive").as_deref(), Some("Archive"));
+        assert_eq!(list_name("* STATUS INBOX (MESSAGES 1)"), None);
+    }
+
+    #[test]
+    fn quotes_imap_strings() {
+        assert_eq!(imap_quote("plain"), "\"plain\"");
+        assert_eq!(imap_quote(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+
+    #[tokio::test]
+    async fn imap_check_lists_folders() {
+        let port = server("* OK IMAP ready", imap).await;
+        let check = check_server(&target(ServerProtocol::Imap, port)).await;
+
+        assert_eq!(check.failed_stage, None);
+        assert_eq!(
+            stages(&check),
+            [
+                (HealthStage::Dns, true),
+                (HealthStage::Connect, true),
+                (HealthStage::Greeting, true),
+                (HealthStage::Auth, true),
+                (HealthStage::Folders, true),
+            ]
+        );
+        assert_eq!(check.folders.unwrap(), ["INBOX", "Work.Projects", r#"Say "hi""#]);
+    }
+
+    #[tokio::test]
+    async fn unexpected_greeting_fails_the_greeting_stage() {
+        let port = server("* BYE Too many connections", imap).await;
+        let check = check_server(&target(ServerProtocol::Imap, port)).await;
+
+        assert_eq!(check.failed_stage, Some(HealthStage::Greeting));
+        let greeting = check.stages.last().unwrap();
+        assert_eq!(greeting.stage, HealthStage::Greeting);
+        assert_eq!(greeting.server_response.as_deref(), Some("* BYE Too many connections"));
+    }
+
+    #[tokio::test]
+    async fn pop3_check_counts_messages() {
+        let port = server("+OK POP3 ready", |command| match command {
+            "STAT" => "+OK 3 1200",
+            _ => "+OK",
+        })
+        .await;
+        let check = check_server(&target(ServerProtocol::Pop3, port)).await;
+
+        assert_eq!(check.failed_stage, None);
+        assert_eq!(check.folders.unwrap(), [INBOX]);
+        assert_eq!(check.stages.last().unwrap().detail, format!("3 messages in {}", INBOX));
+    }
+
+    #[tokio::test]
+    async fn rejected_pop3_login_fails_the_auth_stage() {
+        let port = server("+OK POP3 ready", |command| match net::verb(command) {
+            "PASS" => "-ERR Invalid login",
+            _ => "+OK",
+        })
+        .await;
+        let check = check_server(&target(ServerProtocol::Pop3, port)).await;
+
+        assert_eq!(check.failed_stage, Some(HealthStage::Auth));
+        let auth = check.stages.last().unwrap();
+        assert_eq!((auth.stage, auth.ok), (HealthStage::Auth, false));
+        assert_eq!(auth.detail, "Server rejected PASS: -ERR Invalid login");
+        assert!(auth.server_response.as_deref().unwrap().ends_with("-ERR Invalid login"));
+    }
+}
@@ -0,0 +1,1141 @@
+use super::{Credentials, FetchResult};
+use crate::storage::INBOX;
+use crate::types::{Attachment, Email, EmailAccount, EmailAddress, RemoteFlags};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+const CORE_CAPABILITY: &str = "urn:ietf:params:jmap:core";
+const MAIL_CAPABILITY: &str = "urn:ietf:params:jmap:mail";
+const SUBMISSION_CAPABILITY: &str = "urn:ietf:params:jmap:submission";
+
+/// Ids requested per Email/query page and per Email/get call.
+const PAGE_SIZE: usize = 256;
+const MAX_CHANGES: usize = 500;
+
+const EMAIL_PROPERTIES: &[&str] = &[
+    "id",
+    "blobId",
+    "mailboxIds",
+    "keywords",
+    "messageId",
+    "inReplyTo",
+    "references",
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "replyTo",
+    "subject",
+    "receivedAt",
+    "sentAt",
+    "textBody",
+    "htmlBody",
+    "bodyValues",
+    "attachments",
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Session {
+    api_url: String,
+    download_url: Option<String>,
+    upload_url: Option<String>,
+    event_source_url: Option<String>,
+    primary_accounts: HashMap<String, String>,
+}
+
+enum Auth {
+    Basic(String, String),
+    Bearer(String),
+}
+
+pub struct JmapClient {
+    http: Client,
+    auth: Auth,
+    session: Session,
+    account_id: String,
+}
+
+/// Mailbox id to folder name; the inbox maps to `None` like everywhere else.
+type Mailboxes = HashMap<String, Option<String>>;
+
+impl JmapClient {
+    /// Discovers the JMAP session for an account and authenticates against it.
+    pub async fn connect(account: &EmailAccount) -> Result<Self> {
+        let auth = match Credentials::for_server(account, &account.config.incoming)? {
+            Credentials::Password { username, password } => Auth::Basic(username, password),
+            Credentials::OAuth2 { token, .. } => Auth::Bearer(token),
+            Credentials::None => return Err(anyhow!("JMAP account {} has no credentials", account.id)),
+        };
+
+        let http = Client::new();
+        let session: Session = authorize(http.get(session_url(account)?), &auth)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let account_id = session
+            .primary_accounts
+            .get(MAIL_CAPABILITY)
+            .cloned()
+            .ok_or_else(|| anyhow!("JMAP server has no mail account"))?;
+
+        Ok(Self {
+            http,
+            auth,
+            session,
+            account_id,
+        })
+    }
+
+    /// Sends a batch of method calls and returns the raw `[name, arguments, id]` responses.
+    async fn call_raw(&self, method_calls: Value) -> Result<Vec<Value>> {
+        let request = json!({
+            "using": [CORE_CAPABILITY, MAIL_CAPABILITY, SUBMISSION_CAPABILITY],
+            "methodCalls": method_calls,
+        });
+        let data: Value = authorize(self.http.post(&self.session.api_url), &self.auth)
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        data["methodResponses"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| anyhow!("Malformed JMAP response"))
+    }
+
+    /// Sends a batch of method calls and returns the arguments of each
+    /// response, failing on the first method-level error.
+    async fn call(&self, method_calls: Value) -> Result<Vec<Value>> {
+        let responses = self.call_raw(method_calls).await?;
+        for response in &responses {
+            if response[0] == "error" {
+                return Err(anyhow!(
+                    "JMAP call {} failed: {}",
+                    response[2],
+                    response[1]["type"].as_str().unwrap_or("unknown error")
+                ));
+            }
+        }
+        Ok(responses.into_iter().map(|r| r[1].clone()).collect())
+    }
+
+    async fn mailboxes(&self) -> Result<Mailboxes> {
+        let responses = self
+            .call(json!([[
+                "Mailbox/get",
+                {"accountId": self.account_id, "ids": null, "properties": ["name", "role"]},
+                "m"
+            ]]))
+            .await?;
+
+        Ok(responses[0]["list"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|mailbox| {
+                let id = mailbox["id"].as_str()?.to_string();
+                let folder = match mailbox["role"].as_str() {
+                    Some("inbox") => None,
+                    _ => mailbox["name"].as_str().map(|s| s.to_string()),
+                };
+                Some((id, folder))
+            })
+            .collect())
+    }
+
+    /// Names of all mailboxes, the inbox included.
+    pub async fn folder_names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self
+            .mailboxes()
+            .await?
+            .into_values()
+            .map(|folder| folder.unwrap_or_else(|| INBOX.to_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    async fn mailbox_with_role(&self, role: &str) -> Result<Option<String>> {
+        let responses = self
+            .call(json!([[
+                "Mailbox/query",
+                {"accountId": self.account_id, "filter": {"role": role}},
+                "q"
+            ]]))
+            .await?;
+        Ok(responses[0]["ids"][0].as_str().map(|s| s.to_string()))
+    }
+
+    /// Current Email state, used as the starting point for later Email/changes calls.
+    async fn email_state(&self) -> Result<String> {
+        let responses = self
+            .call(json!([["Email/get", {"accountId": self.account_id, "ids": []}, "s"]]))
+            .await?;
+        responses[0]["state"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| anyhow!("JMAP server returned no Email state"))
+    }
+
+    async fn query_all_ids(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        loop {
+            let responses = self
+                .call(json!([[
+                    "Email/query",
+                    {
+                        "accountId": self.account_id,
+                        "sort": [{"property": "receivedAt", "isAscending": false}],
+                        "position": ids.len(),
+                        "limit": PAGE_SIZE,
+                        "calculateTotal": true,
+                    },
+                    "q"
+                ]]))
+                .await?;
+            let page: Vec<String> = responses[0]["ids"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|id| id.as_str().map(|s| s.to_string()))
+                .collect();
+            let total = responses[0]["total"].as_u64().unwrap_or(0) as usize;
+            let done = page.is_empty();
+            ids.extend(page);
+            if done || ids.len() >= total {
+                return Ok(ids);
+            }
+        }
+    }
+
+    /// Downloads a blob, e.g. a message's RFC 822 source.
+    async fn download(&self, blob_id: &str) -> Result<Vec<u8>> {
+        let url = self
+            .session
+            .download_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("JMAP server has no download URL"))?
+            .replace("{accountId}", &self.account_id)
+            .replace("{blobId}", blob_id)
+            .replace("{name}", "message.eml")
+            .replace("{type}", "message/rfc822");
+        let data = authorize(self.http.get(&url), &self.auth)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(data.to_vec())
+    }
+
+    /// Uploads a blob, e.g. an attachment, and returns its blob id.
+    async fn upload(&self, data: Vec<u8>, mime_type: &str) -> Result<String> {
+        let url = self
+            .session
+            .upload_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("JMAP server has no upload URL"))?
+            .replace("{accountId}", &self.account_id);
+        let response: Value = authorize(self.http.post(&url), &self.auth)
+            .header(reqwest::header::CONTENT_TYPE, mime_type)
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        response["blobId"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| anyhow!("JMAP server returned no blob id"))
+    }
+
+    /// Fetches messages together with their original source, by email id.
+    async fn get_emails(
+        &self,
+        account: &EmailAccount,
+        ids: &[String],
+        mailboxes: &Mailboxes,
+    ) -> Result<(Vec<Email>, HashMap<String, Vec<u8>>)> {
+        let mut emails = Vec::new();
+        let mut sources = HashMap::new();
+        for chunk in ids.chunks(PAGE_SIZE) {
+            let responses = self
+                .call(json!([[
+                    "Email/get",
+                    {
+                        "accountId": self.account_id,
+                        "ids": chunk,
+                        "properties": EMAIL_PROPERTIES,
+                        "fetchTextBodyValues": true,
+                        "fetchHTMLBodyValues": true,
+                    },
+                    "g"
+                ]]))
+                .await?;
+            for value in responses[0]["list"].as_array().into_iter().flatten() {
+                let email = to_email(value, &account.id, mailboxes);
+                // The download URL is required by RFC 8620, but not every server has one
+                let blob_id = value["blobId"].as_str().filter(|_| self.session.download_url.is_some());
+                if let Some(blob_id) = blob_id {
+                    sources.insert(email.id.clone(), self.download(blob_id).await?);
+                }
+                emails.push(email);
+            }
+        }
+        Ok((emails, sources))
+    }
+
+    async fn get_flags(&self, ids: &[String]) -> Result<Vec<RemoteFlags>> {
+        let mut flags = Vec::new();
+        for chunk in ids.chunks(PAGE_SIZE) {
+            let responses = self
+                .call(json!([[
+                    "Email/get",
+                    {"accountId": self.account_id, "ids": chunk, "properties": ["id", "keywords"]},
+                    "f"
+                ]]))
+                .await?;
+            flags.extend(
+                responses[0]["list"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|value| {
+                        Some(RemoteFlags {
+                            remote_id: value["id"].as_str()?.to_string(),
+                            is_read: has_keyword(value, "$seen"),
+                            is_starred: has_keyword(value, "$flagged"),
+                        })
+                    }),
+            );
+        }
+        Ok(flags)
+    }
+
+    /// Lists the whole mailbox, downloading only messages that are not stored yet.
+    async fn fetch_all(
+        &self,
+        account: &EmailAccount,
+        known: &HashSet<String>,
+        mailboxes: &Mailboxes,
+    ) -> Result<FetchResult> {
+        let state = self.email_state().await?;
+        let ids = self.query_all_ids().await?;
+        let present: HashSet<&String> = ids.iter().collect();
+
+        let (known_ids, new_ids): (Vec<String>, Vec<String>) =
+            ids.iter().cloned().partition(|id| known.contains(id));
+
+        let (emails, sources) = self.get_emails(account, &new_ids, mailboxes).await?;
+        Ok(FetchResult {
+            emails,
+            sources,
+            flag_updates: self.get_flags(&known_ids).await?,
+            removed: known.iter().filter(|id| !present.contains(id)).cloned().collect(),
+            sync_state: Some(state),
+        })
+    }
+
+    /// Applies Email/changes since `since_state`. Returns `None` when the
+    /// server can no longer calculate changes from that state.
+    async fn fetch_changes(
+        &self,
+        account: &EmailAccount,
+        known: &HashSet<String>,
+        since_state: &str,
+        mailboxes: &Mailboxes,
+    ) -> Result<Option<FetchResult>> {
+        let mut state = since_state.to_string();
+        let mut changed = Vec::new();
+        let mut destroyed = Vec::new();
+
+        loop {
+            let responses = self
+                .call_raw(json!([[
+                    "Email/changes",
+                    {"accountId": self.account_id, "sinceState": state, "maxChanges": MAX_CHANGES},
+                    "c"
+                ]]))
+                .await?;
+            let response = &responses[0];
+            if response[0] == "error" {
+                if response[1]["type"] == "cannotCalculateChanges" {
+                    return Ok(None);
+                }
+                return Err(anyhow!(
+                    "JMAP Email/changes failed: {}",
+                    response[1]["type"].as_str().unwrap_or("unknown error")
+                ));
+            }
+
+            let args = &response[1];
+            for key in ["created", "updated"] {
+                changed.extend(strings(&args[key]));
+            }
+            destroyed.extend(strings(&args["destroyed"]));
+            state = args["newState"].as_str().unwrap_or(&state).to_string();
+
+            if !args["hasMoreChanges"].as_bool().unwrap_or(false) {
+                break;
+            }
+        }
+
+        changed.retain(|id| !destroyed.contains(id));
+        changed.sort();
+        changed.dedup();
+        let (known_ids, new_ids): (Vec<String>, Vec<String>) =
+            changed.into_iter().partition(|id| known.contains(id));
+
+        let (emails, sources) = self.get_emails(account, &new_ids, mailboxes).await?;
+        Ok(Some(FetchResult {
+            emails,
+            sources,
+            flag_updates: self.get_flags(&known_ids).await?,
+            removed: destroyed.into_iter().filter(|id| known.contains(id)).collect(),
+            sync_state: Some(state),
+        }))
+    }
+}
+
+/// Syncs a JMAP account, using Email/changes when a previous state is known
+/// and falling back to a full listing otherwise.
+pub async fn fetch(
+    account: &EmailAccount,
+    known: &HashSet<String>,
+    since_state: Option<&str>,
+) -> Result<FetchResult> {
+    let client = JmapClient::connect(account).await?;
+    let mailboxes = client.mailboxes().await?;
+
+    if let Some(state) = since_state {
+        if let Some(result) = client.fetch_changes(account, known, state, &mailboxes).await? {
+            return Ok(result);
+        }
+    }
+    client.fetch_all(account, known, &mailboxes).await
+}
+
+/// Sends a message with Email/set and EmailSubmission/set, uploading its
+/// attachments first.
+pub async fn send(account: &EmailAccount, email: &Email) -> Result<()> {
+    let client = JmapClient::connect(account).await?;
+
+    let responses = client
+        .call(json!([["Identity/get", {"accountId": client.account_id, "ids": null}, "i"]]))
+        .await?;
+    let identities = responses[0]["list"].as_array().cloned().unwrap_or_default();
+    let identity = identities
+        .iter()
+        .find(|i| i["email"].as_str() == Some(email.from.address.as_str()))
+        .or_else(|| identities.first())
+        .ok_or_else(|| anyhow!("JMAP account has no sending identity"))?;
+
+    let mailbox = match client.mailbox_with_role("sent").await? {
+        Some(id) => id,
+        None => client
+            .mailbox_with_role("drafts")
+            .await?
+            .ok_or_else(|| anyhow!("JMAP account has no Sent or Drafts mailbox"))?,
+    };
+
+    let from_name = email
+        .from
+        .name
+        .clone()
+        .or_else(|| identity["name"].as_str().map(|s| s.to_string()));
+    let mut draft = json!({
+        "mailboxIds": {mailbox: true},
+        "keywords": {"$seen": true},
+        "from": [{"name": from_name, "email": email.from.address}],
+        "to": address_values(&email.to),
+        "subject": email.subject,
+        "bodyValues": {"body": {"value": email.body}},
+        "textBody": [{"partId": "body", "type": "text/plain"}],
+    });
+    if let Some(html) = &email.html_body {
+        draft["bodyValues"]["html"] = json!({"value": html});
+        draft["htmlBody"] = json!([{"partId": "html", "type": "text/html"}]);
+    }
+    let mut attachments = Vec::new();
+    for attachment in email.attachments.iter().flatten() {
+        // Attachments without content are only listed, like in the MIME export
+        let Some(content) = &attachment.content else {
+            continue;
+        };
+        let data = BASE64.decode(content)?;
+        let blob_id = client.upload(data, &attachment.mime_type).await?;
+        attachments.push(json!({
+            "blobId": blob_id,
+            "type": attachment.mime_type,
+            "name": attachment.filename,
+            "disposition": "attachment",
+        }));
+    }
+    if !attachments.is_empty() {
+        draft["attachments"] = json!(attachments);
+    }
+    if let Some(cc) = &email.cc {
+        draft["cc"] = address_values(cc);
+    }
+    if let Some(bcc) = &email.bcc {
+        draft["bcc"] = address_values(bcc);
+    }
+    if let Some(reply_to) = &email.reply_to {
+        draft["replyTo"] = address_values(reply_to);
+    }
+    if let Some(in_reply_to) = &email.in_reply_to {
+        draft["inReplyTo"] = json!([in_reply_to]);
+    }
+    if let Some(references) = &email.references {
+        draft["references"] = json!(references);
+    }
+
+    let responses = client
+        .call(json!([
+            [
+                "Email/set",
+                {
+                    "accountId": client.account_id,
+                    "create": {
+                        "draft": draft
+                    }
+                },
+                "e"
+            ],
+            [
+                "EmailSubmission/set",
+                {
+                    "accountId": client.account_id,
+                    "create": {
+                        "submission": {"emailId": "#draft", "identityId": identity["id"]}
+                    }
+                },
+                "s"
+            ]
+        ]))
+        .await?;
+
+    for response in &responses {
+        if let Some(errors) = response["notCreated"].as_object() {
+            if let Some((_, error)) = errors.iter().next() {
+                return Err(anyhow!(
+                    "JMAP send failed: {}",
+                    error["description"]
+                        .as_str()
+                        .or_else(|| error["type"].as_str())
+                        .unwrap_or("unknown error")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Permanently deletes messages with Email/set destroy.
+pub async fn destroy(account: &EmailAccount, remote_ids: &[String]) -> Result<()> {
+    if remote_ids.is_empty() {
+        return Ok(());
+    }
+    let client = JmapClient::connect(account).await?;
+    let responses = client
+        .call(json!([[
+            "Email/set",
+            {"accountId": client.account_id, "destroy": remote_ids},
+            "d"
+        ]]))
+        .await?;
+    if let Some((id, error)) = responses[0]["notDestroyed"]
+        .as_object()
+        .and_then(|errors| errors.iter().next())
+    {
+        // Messages already gone on the server count as deleted
+        if error["type"] != "notFound" {
+            return Err(anyhow!(
+                "JMAP could not delete {}: {}",
+                id,
+                error["description"]
+                    .as_str()
+                    .or_else(|| error["type"].as_str())
+                    .unwrap_or("unknown error")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Writes read/starred state back as `$seen`/`$flagged` keywords, with one
+/// Email/set call per page of messages.
+pub async fn set_flags(account: &EmailAccount, updates: &[RemoteFlags]) -> Result<()> {
+    if updates.is_empty() {
+        return Ok(());
+    }
+    let client = JmapClient::connect(account).await?;
+    for chunk in updates.chunks(PAGE_SIZE) {
+        // A null keyword patch removes the keyword
+        let patches: serde_json::Map<String, Value> = chunk
+            .iter()
+            .map(|update| {
+                let patch = json!({
+                    "keywords/$seen": update.is_read.then_some(true),
+                    "keywords/$flagged": update.is_starred.then_some(true),
+                });
+                (update.remote_id.clone(), patch)
+            })
+            .collect();
+        let responses = client
+            .call(json!([[
+                "Email/set",
+                {"accountId": client.account_id, "update": patches},
+                "u"
+            ]]))
+            .await?;
+        if let Some((id, error)) = responses[0]["notUpdated"]
+            .as_object()
+            .and_then(|errors| errors.iter().next())
+        {
+            // Messages deleted on the server meanwhile are dropped by the next sync
+            if error["type"] != "notFound" {
+                return Err(anyhow!(
+                    "JMAP could not update {}: {}",
+                    id,
+                    error["description"]
+                        .as_str()
+                        .or_else(|| error["type"].as_str())
+                        .unwrap_or("unknown error")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Subscribes to the account's EventSource push channel and calls
+/// `on_change` for every state change, and `on_error` whenever the channel
+/// breaks. Reconnects until the task is aborted.
+pub async fn listen(
+    account: EmailAccount,
+    on_change: impl Fn() + Send + Sync + 'static,
+    on_error: impl Fn(anyhow::Error) + Send + Sync + 'static,
+) {
+    loop {
+        if let Err(e) = listen_once(&account, &on_change).await {
+            on_error(e);
+        }
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    }
+}
+
+async fn listen_once(account: &EmailAccount, on_change: &(impl Fn() + Send + Sync)) -> Result<()> {
+    let client = JmapClient::connect(account).await?;
+    let url = client
+        .session
+        .event_source_url
+        .as_ref()
+        .ok_or_else(|| anyhow!("JMAP server does not support push"))?
+        .replace("{types}", "Email,Mailbox")
+        .replace("{closeafter}", "no")
+        .replace("{ping}", "60");
+
+    let mut response = authorize(client.http.get(&url), &client.auth)
+        .header("Accept", "text/event-stream")
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let mut buffer = String::new();
+    let mut event = String::new();
+    while let Some(chunk) = response.chunk().await? {
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            let line = line.trim_end();
+            if line.is_empty() {
+                // A blank line dispatches the event collected so far
+                if event == "state" {
+                    on_change();
+                }
+                event.clear();
+            } else if let Some(name) = line.strip_prefix("event:") {
+                event = name.trim().to_string();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn authorize(request: RequestBuilder, auth: &Auth) -> RequestBuilder {
+    match auth {
+        Auth::Basic(username, password) => request.basic_auth(username, Some(password)),
+        Auth::Bearer(token) => request.bearer_auth(token),
+    }
+}
+
+/// The session resource lives at `/.well-known/jmap` unless the host is
+/// already configured as a full session URL.
+fn session_url(account: &EmailAccount) -> Result<String> {
+    let server = &account.config.incoming;
+    let host = server
+        .host
+        .as_deref()
+        .ok_or_else(|| anyhow!("JMAP account {} has no host configured", account.id))?;
+
+    if host.starts_with("http://") || host.starts_with("https://") {
+        let mut url = Url::parse(host)?;
+        if url.path() == "/" {
+            url.set_path("/.well-known/jmap");
+        }
+        return Ok(url.to_string());
+    }
+
+    Ok(match server.port {
+        Some(port) => format!("https://{}:{}/.well-known/jmap", host, port),
+        None => format!("https://{}/.well-known/jmap", host),
+    })
+}
+
+fn address_values(addresses: &[EmailAddress]) -> Value {
+    addresses
+        .iter()
+        .map(|a| json!({"name": a.name, "email": a.address}))
+        .collect()
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str().map(|s| s.to_string()))
+        .collect()
+}
+
+fn has_keyword(email: &Value, keyword: &str) -> bool {
+    email["keywords"][keyword].as_bool().unwrap_or(false)
+}
+
+fn addresses(value: &Value) -> Vec<EmailAddress> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|a| {
+            Some(EmailAddress {
+                name: a["name"].as_str().filter(|n| !n.is_empty()).map(|n| n.to_string()),
+                address: a["email"].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Joins the body values referenced by a `textBody`/`htmlBody` part list.
+fn body_text(email: &Value, parts: &str) -> Option<String> {
+    let text: Vec<&str> = email[parts]
+        .as_array()?
+        .iter()
+        .filter_map(|part| part["partId"].as_str())
+        .filter_map(|id| email["bodyValues"][id]["value"].as_str())
+        .collect();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.join("\n"))
+    }
+}
+
+fn to_email(value: &Value, account_id: &str, mailboxes: &Mailboxes) -> Email {
+    let date = value["sentAt"]
+        .as_str()
+        .or_else(|| value["receivedAt"].as_str())
+        .and_then(|d| chrono::DateTime::parse_from_rfc3339(d).ok())
+        .map(|d| d.to_rfc2822())
+        .unwrap_or_else(|| chrono::Utc::now().to_rfc2822());
+
+    let folder = value["mailboxIds"]
+        .as_object()
+        .and_then(|ids| ids.keys().find_map(|id| mailboxes.get(id)))
+        .cloned()
+        .flatten();
+
+    let html_body = body_text(value, "htmlBody")
+        .filter(|_| value["htmlBody"][0]["type"] == "text/html");
+
+    let attachments: Vec<Attachment> = value["attachments"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|a| Attachment {
+            id: a["blobId"].as_str().unwrap_or_default().to_string(),
+            filename: a["name"].as_str().unwrap_or("attachment").to_string(),
+            mime_type: a["type"].as_str().unwrap_or("application/octet-stream").to_string(),
+            size: a["size"].as_u64().unwrap_or(0),
+            content: None,
+        })
+        .collect();
+
+    let cc = addresses(&value["cc"]);
+    let bcc = addresses(&value["bcc"]);
+    let reply_to = addresses(&value["replyTo"]);
+    let references = strings(&value["references"]);
+
+    Email {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id: account_id.to_string(),
+        subject: value["subject"].as_str().unwrap_or_default().to_string(),
+        from: addresses(&value["from"])
+            .into_iter()
+            .next()
+            .unwrap_or(EmailAddress {
+                name: None,
+                address: String::new(),
+            }),
+        to: addresses(&value["to"]),
+        cc: (!cc.is_empty()).then_some(cc),
+        bcc: (!bcc.is_empty()).then_some(bcc),
+        reply_to: (!reply_to.is_empty()).then_some(reply_to),
+        date,
+        body: body_text(value, "textBody").unwrap_or_default(),
+        html_body,
+        attachments: (!attachments.is_empty()).then_some(attachments),
+        is_read: has_keyword(value, "$seen"),
+        is_starred: has_keyword(value, "$flagged"),
+        labels: None,
+        ai_classification: None,
+        folder,
+        message_id: value["messageId"][0].as_str().map(|s| s.to_string()),
+        in_reply_to: value["inReplyTo"][0].as_str().map(|s| s.to_string()),
+        references: (!references.is_empty()).then_some(references),
+        remote_id: value["id"].as_str().map(|s| s.to_string()),
+        quarantine: None,
+        trashed: None,
+        snooze: None,
+        summary: None,
+        thread_summary: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountConfig, AuthMethod, Protocol, SecurityMode, ServerConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    const RAW: &str = "From: Ann <ann@example.com>\r\nTo: me@example.com\r\nSubject: Hello\r\nX-Custom: kept\r\n\r\nHi there\r\n";
+
+    /// Answers each method call of a JMAP API request with
+    /// `handler(name, arguments)`, which returns the response name and arguments.
+    async fn server(handler: impl Fn(&str, &Value) -> (String, Value) + Send + Sync + 'static) -> (MockServer, EmailAccount) {
+        let server = MockServer::start().await;
+        let uri = server.uri();
+        Mock::given(method("GET"))
+            .and(path("/.well-known/jmap"))
+            .and(header("authorization", "Basic bWU6c2VjcmV0"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "apiUrl": format!("{}/api", uri),
+                "downloadUrl": format!("{}/download/{{accountId}}/{{blobId}}/{{name}}?accept={{type}}", uri),
+                "uploadUrl": format!("{}/upload/{{accountId}}/", uri),
+                "eventSourceUrl": format!("{}/events?types={{types}}&closeafter={{closeafter}}&ping={{ping}}", uri),
+                "primaryAccounts": {MAIL_CAPABILITY: "A1"},
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api"))
+            .respond_with(move |request: &Request| {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                let responses: Vec<Value> = body["methodCalls"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|call| {
+                        let (name, args) = handler(call[0].as_str().unwrap(), &call[1]);
+                        json!([name, args, call[2]])
+                    })
+                    .collect();
+                ResponseTemplate::new(200).set_body_json(json!({"methodResponses": responses}))
+            })
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/download/A1/B1/message.eml"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(RAW.as_bytes()))
+            .mount(&server)
+            .await;
+
+        let account = EmailAccount {
+            id: "acc".to_string(),
+            name: "Test".to_string(),
+            email: "me@example.com".to_string(),
+            display_name: None,
+            tags: None,
+            protocol: Protocol::Jmap,
+            provider: None,
+            config: AccountConfig {
+                incoming: ServerConfig {
+                    host: Some(uri),
+                    port: None,
+                    username: Some("me".to_string()),
+                    password: Some("secret".to_string()),
+                    security: SecurityMode::None,
+                    auth: AuthMethod::Password,
+                },
+                outgoing: None,
+                sieve: None,
+                oauth_token: None,
+                refresh_token: None,
+                path: None,
+            },
+            identities: None,
+            signature_id: None,
+        };
+        (server, account)
+    }
+
+    fn ok(name: &str, args: Value) -> (String, Value) {
+        (name.to_string(), args)
+    }
+
+    fn mailbox_list() -> Value {
+        json!({"list": [
+            {"id": "M1", "name": "Inbox", "role": "inbox"},
+            {"id": "M2", "name": "Archive", "role": null},
+        ]})
+    }
+
+    fn message(id: &str) -> Value {
+        json!({
+            "id": id,
+            "blobId": "B1",
+            "mailboxIds": {"M2": true},
+            "keywords": {"$seen": true},
+            "messageId": ["m1@example.com"],
+            "from": [{"name": "Ann", "email": "ann@example.com"}],
+            "to": [{"name": null, "email": "me@example.com"}],
+            "subject": "Hello",
+            "sentAt": "2024-05-01T10:00:00Z",
+            "textBody": [{"partId": "1", "type": "text/plain"}],
+            "htmlBody": [{"partId": "2", "type": "text/html"}],
+            "bodyValues": {"1": {"value": "Hi there"}, "2": {"value": "<p>Hi there</p>"}},
+            "attachments": [],
+        })
+    }
+
+    /// A server with one message `E1` and Email state `s2`.
+    fn mailbox(name: &str, args: &Value) -> (String, Value) {
+        match name {
+            "Mailbox/get" => ok(name, mailbox_list()),
+            "Email/get" if args["ids"] == json!([]) => ok(name, json!({"state": "s2", "list": []})),
+            "Email/get" if args["properties"] == json!(["id", "keywords"]) => ok(
+                name,
+                json!({"list": [{"id": "E0", "keywords": {"$flagged": true}}]}),
+            ),
+            "Email/get" => ok(
+                name,
+                json!({"list": args["ids"].as_array().unwrap().iter().map(|id| message(id.as_str().unwrap())).collect::<Vec<_>>()}),
+            ),
+            "Email/query" => ok(name, json!({"ids": ["E1", "E0"], "total": 2})),
+            _ => ("error".to_string(), json!({"type": "unknownMethod"})),
+        }
+    }
+
+    #[tokio::test]
+    async fn discovers_session_and_mailboxes() {
+        let (_server, account) = server(mailbox).await;
+        let client = JmapClient::connect(&account).await.unwrap();
+        assert_eq!(client.account_id, "A1");
+        assert_eq!(client.folder_names().await.unwrap(), vec!["Archive", INBOX]);
+    }
+
+    #[tokio::test]
+    async fn full_sync_gets_new_messages_with_their_source() {
+        let (_server, account) = server(mailbox).await;
+        let known = HashSet::from(["E0".to_string(), "E9".to_string()]);
+        let result = fetch(&account, &known, None).await.unwrap();
+
+        assert_eq!(result.sync_state.as_deref(), Some("s2"));
+        assert_eq!(result.removed, vec!["E9"]);
+        assert_eq!(result.flag_updates.len(), 1);
+        assert!(result.flag_updates[0].is_starred && !result.flag_updates[0].is_read);
+
+        let [email] = result.emails.as_slice() else {
+            panic!("expected one new email, got {:?}", result.emails);
+        };
+        assert_eq!(email.remote_id.as_deref(), Some("E1"));
+        assert_eq!(email.subject, "Hello");
+        assert_eq!(email.from.name.as_deref(), Some("Ann"));
+        assert_eq!(email.body, "Hi there");
+        assert_eq!(email.html_body.as_deref(), Some("<p>Hi there</p>"));
+        assert_eq!(email.folder.as_deref(), Some("Archive"));
+        assert!(email.is_read && !email.is_starred);
+        assert_eq!(result.sources.get(&email.id).map(Vec::as_slice), Some(RAW.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn delta_sync_applies_changes() {
+        let (_server, account) = server(|name, args| match name {
+            "Email/changes" => {
+                assert_eq!(args["sinceState"], "s1");
+                ok(name, json!({
+                    "newState": "s3",
+                    "hasMoreChanges": false,
+                    "created": ["E1"],
+                    "updated": ["E0", "E5"],
+                    "destroyed": ["E5", "E7"],
+                }))
+            }
+            _ => mailbox(name, args),
+        })
+        .await;
+        let known = HashSet::from(["E0".to_string(), "E7".to_string()]);
+        let result = fetch(&account, &known, Some("s1")).await.unwrap();
+
+        assert_eq!(result.sync_state.as_deref(), Some("s3"));
+        assert_eq!(result.emails.len(), 1);
+        assert_eq!(result.emails[0].remote_id.as_deref(), Some("E1"));
+        assert_eq!(result.flag_updates.len(), 1);
+        assert_eq!(result.removed, vec!["E7"]);
+    }
+
+    #[tokio::test]
+    async fn delta_sync_falls_back_to_full_sync() {
+        let (_server, account) = server(|name, args| match name {
+            "Email/changes" => ("error".to_string(), json!({"type": "cannotCalculateChanges"})),
+            _ => mailbox(name, args),
+        })
+        .await;
+        let result = fetch(&account, &HashSet::new(), Some("s0")).await.unwrap();
+        assert_eq!(result.sync_state.as_deref(), Some("s2"));
+        assert_eq!(result.emails.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn delta_sync_reports_other_errors() {
+        let (_server, account) = server(|name, args| match name {
+            "Email/changes" => ("error".to_string(), json!({"type": "serverFail"})),
+            _ => mailbox(name, args),
+        })
+        .await;
+        let error = fetch(&account, &HashSet::new(), Some("s0")).await.unwrap_err();
+        assert!(error.to_string().contains("serverFail"));
+    }
+
+    fn sending(name: &str, args: &Value) -> (String, Value) {
+        match name {
+            "Identity/get" => ok(name, json!({"list": [
+                {"id": "I1", "email": "other@example.com", "name": "Other"},
+                {"id": "I2", "email": "me@example.com", "name": "Me"},
+            ]})),
+            "Mailbox/query" if args["filter"]["role"] == "sent" => ok(name, json!({"ids": ["M3"]})),
+            "Email/set" => ok(name, json!({"created": {"draft": {"id": "E2"}}})),
+            "EmailSubmission/set" => ok(name, json!({"created": {"submission": {"id": "S1"}}})),
+            _ => ("error".to_string(), json!({"type": "unknownMethod"})),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_with_email_submission() {
+        let (server, account) = server(sending).await;
+        let email = crate::email::compose(&account, None, "you@example.com", "Hi", "Body", None);
+        send(&account, &email).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body: Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+        let calls = &body["methodCalls"];
+        assert_eq!(calls[0][0], "Email/set");
+        let draft = &calls[0][1]["create"]["draft"];
+        assert_eq!(draft["mailboxIds"], json!({"M3": true}));
+        assert_eq!(draft["to"][0]["email"], "you@example.com");
+        assert_eq!(draft["bodyValues"]["body"]["value"], "Body");
+        assert_eq!(calls[1][0], "EmailSubmission/set");
+        assert_eq!(
+            calls[1][1]["create"]["submission"],
+            json!({"emailId": "#draft", "identityId": "I2"})
+        );
+    }
+
+    #[tokio::test]
+    async fn forwards_attachments_as_uploaded_blobs() {
+        let (server, account) = server(sending).await;
+        Mock::given(method("POST"))
+            .and(path("/upload/A1/"))
+            .and(header("content-type", "application/pdf"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(json!({
+                "accountId": "A1", "blobId": "U1", "type": "application/pdf", "size": 9,
+            })))
+            .mount(&server)
+            .await;
+
+        let original = crate::email::mime::parse_email(RAW.as_bytes(), "acc").unwrap();
+        let attachment = Attachment {
+            id: "a1".to_string(),
+            filename: "report.pdf".to_string(),
+            mime_type: "application/pdf".to_string(),
+            size: 9,
+            content: Some(BASE64.encode("%PDF-1.4\n")),
+        };
+        let email = crate::email::forward(&account, &original, Some(vec![attachment]), "you@example.com");
+        send(&account, &email).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let upload = requests.iter().find(|r| r.url.path() == "/upload/A1/").unwrap();
+        assert_eq!(upload.body, b"%PDF-1.4\n");
+        let body: Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+        assert_eq!(
+            body["methodCalls"][0][1]["create"]["draft"]["attachments"],
+            json!([{"blobId": "U1", "type": "application/pdf", "name": "report.pdf", "disposition": "attachment"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_rejected_submissions() {
+        let (_server, account) = server(|name, args| match name {
+            "EmailSubmission/set" => ok(name, json!({"notCreated": {
+                "submission": {"type": "forbiddenFrom", "description": "Not allowed to send as this address"}
+            }})),
+            _ => sending(name, args),
+        })
+        .await;
+        let email = crate::email::compose(&account, None, "you@example.com", "Hi", "Body", None);
+        let error = send(&account, &email).await.unwrap_err();
+        assert!(error.to_string().contains("Not allowed to send as this address"));
+    }
+
+    #[tokio::test]
+    async fn push_calls_back_for_state_events() {
+        let (server, account) = server(mailbox).await;
+        Mock::given(method("GET"))
+            .and(path("/events"))
+            .and(query_param("types", "Email,Mailbox"))
+            .and(query_param("closeafter", "no"))
+            .and(header("accept", "text/event-stream"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                "event: state\ndata: {\"changed\":{}}\n\n: comment\n\nevent: ping\ndata: {}\n\n\
+                 event: state\r\ndata: {\"changed\":{}}\r\n\r\nevent: state\ndata: {}\n",
+                "text/event-stream",
+            ))
+            .mount(&server)
+            .await;
+
+        let changes = AtomicUsize::new(0);
+        listen_once(&account, &|| {
+            changes.fetch_add(1, Ordering::SeqCst);
+        })
+        .await
+        .unwrap();
+        // The last event is never completed by a blank line
+        assert_eq!(changes.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,462 @@
+use super::{mime, FetchResult};
+use crate::storage::{Store, INBOX};
+use crate::types::{EmailAccount, EmailQuery, ImportProgress, RemoteFlags};
+use anyhow::{anyhow, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PROGRESS_INTERVAL: usize = 100;
+
+/// A message file found in one of the `new`/`cur` directories of a Maildir.
+pub struct MaildirEntry {
+    pub path: PathBuf,
+    /// `None` for the top-level (INBOX) Maildir, otherwise the Maildir++ folder name.
+    pub folder: Option<String>,
+    pub unique: String,
+    pub flags: String,
+}
+
+impl MaildirEntry {
+    fn has_flag(&self, flag: char) -> bool {
+        self.flags.contains(flag)
+    }
+}
+
+/// Splits a Maildir file name into its unique part and its info flags.
+/// Windows tools write `!` instead of `:` as the info separator.
+fn split_name(name: &str) -> (String, String) {
+    for separator in [":2,", "!2,"] {
+        if let Some((unique, flags)) = name.split_once(separator) {
+            return (unique.to_string(), flags.to_string());
+        }
+    }
+    (name.to_string(), String::new())
+}
+
+/// Lists the top-level Maildir and all Maildir++ subfolders (`.Sent`, `.Archive.2024`, ...).
+fn folders(root: &Path) -> Result<Vec<(Option<String>, PathBuf)>> {
+    let mut folders = vec![(None, root.to_path_buf())];
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(folder) = name.strip_prefix('.') {
+            if !folder.is_empty() && entry.path().join("cur").is_dir() {
+                folders.push((Some(folder.replace('.', "/")), entry.path()));
+            }
+        }
+    }
+    Ok(folders)
+}
+
+/// Names of all folders of a Maildir, the inbox included.
+pub fn folder_names(root: &Path) -> Result<Vec<String>> {
+    if !root.join("cur").is_dir() {
+        return Err(anyhow!("{} is not a Maildir", root.display()));
+    }
+    Ok(folders(root)?
+        .into_iter()
+        .map(|(folder, _)| folder.unwrap_or_else(|| INBOX.to_string()))
+        .collect())
+}
+
+/// Lists every message in `new` and `cur` of all folders. Files in `tmp` are
+/// still being delivered and are ignored.
+pub fn scan(root: &Path) -> Result<Vec<MaildirEntry>> {
+    if !root.join("cur").is_dir() {
+        return Err(anyhow!("{} is not a Maildir", root.display()));
+    }
+
+    let mut entries = Vec::new();
+    for (folder, dir) in folders(root)? {
+        for sub in ["new", "cur"] {
+            let Ok(read_dir) = fs::read_dir(dir.join(sub)) else {
+                continue;
+            };
+            for file in read_dir {
+                let file = file?;
+                if !file.file_type()?.is_file() {
+                    continue;
+                }
+                let name = file.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') {
+                    continue;
+                }
+                let (unique, flags) = split_name(&name);
+                entries.push(MaildirEntry {
+                    path: file.path(),
+                    folder: folder.clone(),
+                    unique,
+                    flags,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads a Maildir account. Only messages not in `known` are parsed; known
+/// ones report their current flags, and known ones that disappeared or were
+/// marked trashed (`T`) are reported as removed.
+pub fn fetch(account: &EmailAccount, known: &HashSet<String>) -> Result<FetchResult> {
+    let root = account_root(account)?;
+    let mut result = FetchResult::default();
+    let mut present = HashSet::new();
+
+    for entry in scan(&root)? {
+        if entry.has_flag('T') {
+            continue;
+        }
+        present.insert(entry.unique.clone());
+
+        if known.contains(&entry.unique) {
+            result.flag_updates.push(RemoteFlags {
+                remote_id: entry.unique.clone(),
+                is_read: entry.has_flag('S'),
+                is_starred: entry.has_flag('F'),
+            });
+            continue;
+        }
+
+        let raw = fs::read(&entry.path)?;
+        let Ok(mut email) = mime::parse_email(&raw, &account.id) else {
+            continue;
+        };
+        email.folder = entry.folder.clone();
+        email.is_read = entry.has_flag('S');
+        email.is_starred = entry.has_flag('F');
+        email.remote_id = Some(entry.unique);
+        result.emails.push(email);
+    }
+
+    result.removed = known.difference(&present).cloned().collect();
+    Ok(result)
+}
+
+/// Writes flag changes back by renaming the message files, as the Maildir
+/// spec requires, with a single scan of the Maildir. Messages still in `new`
+/// are moved to `cur`; messages that no longer exist are skipped. Flags this
+/// client does not manage (e.g. `R`, `P`, `T`) are preserved.
+pub fn set_flags_many(root: &Path, updates: &[RemoteFlags]) -> Result<()> {
+    let entries: HashMap<String, MaildirEntry> = scan(root)?
+        .into_iter()
+        .map(|e| (e.unique.clone(), e))
+        .collect();
+    for update in updates {
+        if let Some(entry) = entries.get(&update.remote_id) {
+            rename_with_flags(entry, update.is_read, update.is_starred)?;
+        }
+    }
+    Ok(())
+}
+
+fn rename_with_flags(entry: &MaildirEntry, is_read: bool, is_starred: bool) -> Result<()> {
+    let mut flags: Vec<char> = entry.flags.chars().filter(|c| !matches!(c, 'S' | 'F')).collect();
+    if is_read {
+        flags.push('S');
+    }
+    if is_starred {
+        flags.push('F');
+    }
+    flags.sort_unstable();
+    flags.dedup();
+
+    let folder_dir = entry
+        .path
+        .parent()
+        .and_then(|p| p.parent())
+        .ok_or_else(|| anyhow!("Invalid Maildir path {}", entry.path.display()))?;
+    let flags: String = flags.into_iter().collect();
+    let target = folder_dir
+        .join("cur")
+        .join(format!("{}:2,{}", entry.unique, flags));
+
+    if target != entry.path {
+        fs::rename(&entry.path, &target)?;
+    }
+    Ok(())
+}
+
+/// Removes a message file for good, as expunging a trashed message does.
+pub fn expunge(root: &Path, unique: &str) -> Result<()> {
+    if let Some(entry) = scan(root)?.into_iter().find(|e| e.unique == unique) {
+        fs::remove_file(entry.path)?;
+    }
+    Ok(())
+}
+
+/// Watches all folders of a Maildir and calls `on_change` whenever a message
+/// is delivered, renamed or removed. The watch stops when the returned
+/// watcher is dropped.
+pub fn watch(root: &Path, on_change: impl Fn() + Send + 'static) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        // Deliveries are written to tmp first and only count once moved to new
+        let relevant = event.paths.iter().any(|p| {
+            p.parent()
+                .and_then(|d| d.file_name())
+                .is_some_and(|d| d == "new" || d == "cur")
+        });
+        if relevant && !event.kind.is_access() {
+            on_change();
+        }
+    })?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+/// Imports every message of a Maildir into `account_id`, keeping its folder
+/// and read and starred flags. Trashed messages and messages already stored
+/// for that account are skipped, as for mbox imports.
+pub fn import(
+    store: &Store,
+    root: &Path,
+    account_id: &str,
+    mut on_progress: impl FnMut(&ImportProgress),
+) -> Result<ImportProgress> {
+    let entries: Vec<MaildirEntry> = scan(root)?.into_iter().filter(|e| !e.has_flag('T')).collect();
+    let mut progress = ImportProgress::default();
+    for entry in &entries {
+        progress.total_bytes += fs::metadata(&entry.path)?.len();
+    }
+    let mut seen = super::stored_import_keys(store, account_id)?;
+    let mut imported = Vec::new();
+
+    for entry in entries {
+        let raw = fs::read(&entry.path)?;
+        progress.processed += 1;
+        progress.bytes_read += raw.len() as u64;
+
+        match mime::parse_email(&raw, account_id) {
+            Ok(mut email) => {
+                if seen.insert(super::import_key(&email, &raw)) {
+                    email.folder = entry.folder.clone();
+                    email.is_read = entry.has_flag('S');
+                    email.is_starred = entry.has_flag('F');
+                    store.save_raw(&email.id, &raw)?;
+                    imported.push(email);
+                    progress.imported += 1;
+                } else {
+                    progress.duplicates += 1;
+                }
+            }
+            Err(_) => progress.failed += 1,
+        }
+        if progress.processed.is_multiple_of(PROGRESS_INTERVAL) {
+            on_progress(&progress);
+        }
+    }
+
+    super::add_imported(store, imported)?;
+    on_progress(&progress);
+    Ok(progress)
+}
+
+/// Writes all emails matching `query` to a Maildir at `root`, created if
+/// missing, with each folder as a Maildir++ subfolder. Returns how many were
+/// written; writing the same emails again replaces their files.
+pub fn export(store: &Store, root: &Path, query: &EmailQuery) -> Result<usize> {
+    let emails = store.query_emails(query)?;
+    for email in &emails {
+        let dir = match email.folder.as_deref() {
+            None | Some(INBOX) => root.to_path_buf(),
+            Some(folder) => root.join(format!(".{}", folder.replace('/', "."))),
+        };
+        for sub in ["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+
+        let mut flags = String::new();
+        if email.is_starred {
+            flags.push('F');
+        }
+        if email.is_read {
+            flags.push('S');
+        }
+        let date = chrono::DateTime::parse_from_rfc2822(&email.date)
+            .map_or(0, |d| d.timestamp());
+        let unique = format!("{}.{}.mailhub", date, email.id);
+        // Written to tmp first, so readers never see a partial message
+        let tmp = dir.join("tmp").join(&unique);
+        fs::write(&tmp, super::raw_source(store, email)?)?;
+        fs::rename(&tmp, dir.join("cur").join(format!("{}:2,{}", unique, flags)))?;
+    }
+    Ok(emails.len())
+}
+
+pub fn account_root(account: &EmailAccount) -> Result<PathBuf> {
+    account
+        .config
+        .path
+        .as_ref()
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("Maildir account {} has no path configured", account.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::autoconfig;
+    use crate::types::{AccountConfig, AuthMethod, Protocol, SecurityMode, ServerConfig};
+
+    fn deliver(dir: &Path, name: &str, raw: &str) {
+        for sub in ["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join(name), raw).unwrap();
+    }
+
+    fn account(root: &Path) -> EmailAccount {
+        EmailAccount {
+            id: "acc".to_string(),
+            name: "Local".to_string(),
+            email: "me@example.com".to_string(),
+            display_name: None,
+            tags: None,
+            protocol: Protocol::Maildir,
+            provider: None,
+            config: AccountConfig {
+                incoming: ServerConfig {
+                    host: None,
+                    port: None,
+                    username: None,
+                    password: None,
+                    security: SecurityMode::None,
+                    auth: AuthMethod::Password,
+                },
+                outgoing: None,
+                sieve: None,
+                oauth_token: None,
+                refresh_token: None,
+                path: Some(root.to_string_lossy().to_string()),
+            },
+            identities: None,
+            signature_id: None,
+        }
+    }
+
+    fn file_names(root: &Path, sub: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(root.join(sub))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn fetch_maps_flags_and_reports_trashed_as_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        deliver(root, "new/1.a.host", "Subject: New\n\nA\n");
+        deliver(root, "cur/2.b.host:2,FS", "Subject: Starred\n\nB\n");
+        deliver(root, "cur/3.c.host:2,ST", "Subject: Trashed\n\nC\n");
+        deliver(root, "cur/4.d.host:2,S", "Subject: Known\n\nD\n");
+
+        let known = HashSet::from(["3.c.host".to_string(), "4.d.host".to_string(), "5.gone".to_string()]);
+        let result = fetch(&account(root), &known).unwrap();
+
+        let mut emails: Vec<(String, bool, bool)> = result
+            .emails
+            .iter()
+            .map(|e| (e.remote_id.clone().unwrap(), e.is_read, e.is_starred))
+            .collect();
+        emails.sort();
+        assert_eq!(
+            emails,
+            [("1.a.host".to_string(), false, false), ("2.b.host".to_string(), true, true)]
+        );
+        let [update] = result.flag_updates.as_slice() else {
+            panic!("expected one flag update, got {:?}", result.flag_updates);
+        };
+        assert_eq!(update.remote_id, "4.d.host");
+        assert!(update.is_read && !update.is_starred);
+        let mut removed = result.removed;
+        removed.sort();
+        assert_eq!(removed, ["3.c.host", "5.gone"]);
+    }
+
+    fn flags(remote_id: &str, is_read: bool, is_starred: bool) -> RemoteFlags {
+        RemoteFlags {
+            remote_id: remote_id.to_string(),
+            is_read,
+            is_starred,
+        }
+    }
+
+    #[test]
+    fn set_flags_moves_new_to_cur_and_keeps_other_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        deliver(root, "new/1.a.host", "Subject: New\n\nA\n");
+        deliver(root, "cur/2.b.host:2,FRS", "Subject: Replied\n\nB\n");
+
+        let updates = [
+            flags("1.a.host", true, false),
+            flags("2.b.host", false, false),
+            flags("9.missing", true, true),
+        ];
+        set_flags_many(root, &updates).unwrap();
+        assert!(file_names(root, "new").is_empty());
+        assert_eq!(file_names(root, "cur"), ["1.a.host:2,S", "2.b.host:2,R"]);
+
+        set_flags_many(root, &[flags("1.a.host", true, true)]).unwrap();
+        assert_eq!(file_names(root, "cur"), ["1.a.host:2,FS", "2.b.host:2,R"]);
+    }
+
+    #[test]
+    fn expunge_removes_the_message_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        deliver(root, "cur/1.a.host:2,ST", "Subject: Trashed\n\nA\n");
+        deliver(root, "cur/2.b.host:2,S", "Subject: Kept\n\nB\n");
+
+        expunge(root, "1.a.host").unwrap();
+        expunge(root, "9.missing").unwrap();
+        assert_eq!(file_names(root, "cur"), ["2.b.host:2,S"]);
+    }
+
+    #[test]
+    fn exported_mail_imports_with_folders_and_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        deliver(&source, "new/1.a.host", "Message-ID: <a@x>\nSubject: New\n\nA\n");
+        deliver(&source, "cur/2.b.host:2,FS", "Subject: Starred, no Message-ID\n\nB\n");
+        deliver(&source.join(".Archive.2024"), "cur/3.c.host:2,S", "Message-ID: <c@x>\nSubject: Old\n\nC\n");
+        deliver(&source, "cur/4.d.host:2,ST", "Message-ID: <d@x>\nSubject: Trashed\n\nD\n");
+        let store = Store::new(dir.path().join("data"), autoconfig::migrate_legacy_config).unwrap();
+
+        let progress = import(&store, &source, "acc", |_| {}).unwrap();
+        assert_eq!((progress.processed, progress.imported), (3, 3));
+        let again = import(&store, &source, "acc", |_| {}).unwrap();
+        assert_eq!((again.imported, again.duplicates), (0, 3));
+
+        let target = dir.path().join("target");
+        let query = EmailQuery {
+            account_id: Some("acc".to_string()),
+            folder: None,
+            label: None,
+            search: None,
+        };
+        assert_eq!(export(&store, &target, &query).unwrap(), 3);
+        assert_eq!(folder_names(&target).unwrap(), [INBOX, "Archive/2024"]);
+
+        let mut exported: Vec<(Option<String>, String)> =
+            scan(&target).unwrap().into_iter().map(|e| (e.folder, e.flags)).collect();
+        exported.sort();
+        assert_eq!(
+            exported,
+            [
+                (None, String::new()),
+                (None, "FS".to_string()),
+                (Some("Archive/2024".to_string()), "S".to_string()),
+            ]
+        );
+        let starred = scan(&target).unwrap().into_iter().find(|e| e.flags == "FS").unwrap();
+        let raw = fs::read_to_string(starred.path).unwrap();
+        assert_eq!(raw, "Subject: Starred, no Message-ID\n\nB\n");
+    }
+}
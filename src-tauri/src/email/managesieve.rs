@@ -0,0 +1,315 @@
+use super::net::{self, Connection};
+use super::{autoconfig, Credentials};
+use crate::types::{EmailAccount, RemoteSieveScript, SecurityMode, ServerConfig};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+/// Standard ManageSieve port (RFC 5804).
+const PORT: u16 = 4190;
+
+/// A logged-in ManageSieve session.
+pub struct Session {
+    conn: Connection,
+}
+
+/// A word of a server response.
+enum Word {
+    Atom(String),
+    String(String),
+}
+
+impl Word {
+    fn text(&self) -> &str {
+        match self {
+            Word::Atom(text) | Word::String(text) => text,
+        }
+    }
+
+    fn into_text(self) -> String {
+        match self {
+            Word::Atom(text) | Word::String(text) => text,
+        }
+    }
+}
+
+/// The account's ManageSieve server: the configured one, or else the host
+/// of the incoming server on the standard port, with the same login.
+pub fn server(account: &EmailAccount) -> Result<ServerConfig> {
+    if let Some(sieve) = account.config.sieve.as_ref().filter(|s| s.host.is_some()) {
+        return Ok(sieve.clone());
+    }
+    let incoming = &account.config.incoming;
+    let host = incoming
+        .host
+        .clone()
+        .or_else(|| {
+            autoconfig::known_provider(&account.email)
+                .and_then(|known| known.incoming.into_iter().next())
+                .map(|s| s.host)
+        })
+        .ok_or_else(|| anyhow!("Account {} has no server configured", account.id))?;
+    Ok(ServerConfig {
+        host: Some(host),
+        port: Some(PORT),
+        security: SecurityMode::StartTls,
+        ..incoming.clone()
+    })
+}
+
+/// Connects and logs in, upgrading with STARTTLS unless the server is
+/// configured for implicit TLS or plain text.
+pub async fn connect(account: &EmailAccount) -> Result<Session> {
+    let server = server(account)?;
+    let credentials = Credentials::for_server(account, &server)?;
+    let host = server
+        .host
+        .as_deref()
+        .ok_or_else(|| anyhow!("No ManageSieve server configured"))?;
+    let port = server.port.unwrap_or(PORT);
+
+    let stream = net::connect(&net::resolve(host, port).await?).await?;
+    let mut conn = match server.security {
+        SecurityMode::Tls => Connection::tls(stream, host).await?,
+        SecurityMode::StartTls | SecurityMode::None => Connection::plain(stream, host),
+    };
+    // The greeting lists the server's capabilities
+    response(&mut conn).await?;
+
+    if server.security == SecurityMode::StartTls {
+        conn.write_line("STARTTLS").await?;
+        response(&mut conn)
+            .await
+            .map_err(|e| anyhow!("Server rejected STARTTLS: {}", e))?;
+        conn = conn.start_tls().await?;
+        response(&mut conn).await?;
+    }
+
+    let mut session = Session { conn };
+    session.authenticate(&credentials).await?;
+    Ok(session)
+}
+
+impl Session {
+    async fn authenticate(&mut self, credentials: &Credentials) -> Result<()> {
+        let (mechanism, initial) = match credentials {
+            Credentials::Password { username, password } => {
+                ("PLAIN", BASE64.encode(format!("\0{}\0{}", username, password)))
+            }
+            Credentials::OAuth2 { .. } => ("XOAUTH2", credentials.xoauth2().unwrap_or_default()),
+            Credentials::None => return Ok(()),
+        };
+        self.conn
+            .write_line(&format!("AUTHENTICATE \"{}\" \"{}\"", mechanism, initial))
+            .await?;
+        loop {
+            let words = read_words(&mut self.conn).await?;
+            match status(&words) {
+                Some(result) => {
+                    return result.map_err(|e| anyhow!("Server rejected AUTHENTICATE: {}", e))
+                }
+                // A challenge, e.g. XOAUTH2 error details; an empty answer ends the exchange
+                None => self.conn.write_line("\"\"").await?,
+            }
+        }
+    }
+
+    pub async fn list_scripts(&mut self) -> Result<Vec<RemoteSieveScript>> {
+        let lines = self.command("LISTSCRIPTS").await?;
+        Ok(lines
+            .into_iter()
+            .filter_map(|words| {
+                let mut words = words.into_iter();
+                let name = words.next()?.into_text();
+                let active = words.next().is_some_and(|w| w.text().eq_ignore_ascii_case("ACTIVE"));
+                Some(RemoteSieveScript { name, active })
+            })
+            .collect())
+    }
+
+    pub async fn get_script(&mut self, name: &str) -> Result<String> {
+        let lines = self.command(&format!("GETSCRIPT {}", string(name))).await?;
+        lines
+            .into_iter()
+            .flatten()
+            .next()
+            .map(|w| w.into_text().replace("\r\n", "\n"))
+            .ok_or_else(|| anyhow!("Server returned no script"))
+    }
+
+    /// Uploads a script; the server checks it and rejects invalid ones.
+    pub async fn put_script(&mut self, name: &str, content: &str) -> Result<()> {
+        let content = content.replace("\r\n", "\n").replace('\n', "\r\n");
+        self.command(&format!("PUTSCRIPT {} {}", string(name), string(&content)))
+            .await?;
+        Ok(())
+    }
+
+    /// Makes `name` the active script, or deactivates all scripts for `None`.
+    pub async fn set_active(&mut self, name: Option<&str>) -> Result<()> {
+        self.command(&format!("SETACTIVE {}", string(name.unwrap_or_default())))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn logout(mut self) {
+        let _ = self.command("LOGOUT").await;
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Vec<Vec<Word>>> {
+        self.conn.write_line(command).await?;
+        response(&mut self.conn)
+            .await
+            .map_err(|e| anyhow!("Server rejected {}: {}", net::verb(command), e))
+    }
+}
+
+/// A quoted string, or a literal for text that cannot be quoted.
+fn string(value: &str) -> String {
+    if value.contains(['\r', '\n']) || value.len() > 1024 {
+        format!("{{{}+}}\r\n{}", value.len(), value)
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Reads the data lines of a response up to its final OK.
+async fn response(conn: &mut Connection) -> Result<Vec<Vec<Word>>> {
+    let mut lines = Vec::new();
+    loop {
+        let words = read_words(conn).await?;
+        match status(&words) {
+            Some(result) => return result.map(|()| lines),
+            None => lines.push(words),
+        }
+    }
+}
+
+/// `Some` for a final OK, NO or BYE line, failing with the server's
+/// message for the latter two.
+fn status(words: &[Word]) -> Option<Result<()>> {
+    let Some(Word::Atom(first)) = words.first() else {
+        return None;
+    };
+    match first.to_ascii_uppercase().as_str() {
+        "OK" => Some(Ok(())),
+        "NO" | "BYE" => {
+            let message: Vec<&str> = words[1..].iter().map(Word::text).collect();
+            Some(Err(anyhow!("{} {}", first, message.join(" "))))
+        }
+        _ => None,
+    }
+}
+
+/// Reads one response line, including any literals it contains.
+async fn read_words(conn: &mut Connection) -> Result<Vec<Word>> {
+    let mut words = Vec::new();
+    let mut line = conn.read_line().await?;
+    loop {
+        let (text, literal) = split_literal(&line);
+        parse_words(text, &mut words)?;
+        let Some(len) = literal else {
+            return Ok(words);
+        };
+        let data = conn.read_exact(len).await?;
+        words.push(Word::String(String::from_utf8_lossy(&data).into_owned()));
+        line = conn.read_line().await?;
+    }
+}
+
+/// Splits a trailing `{n}` or `{n+}` literal announcement off a line.
+fn split_literal(line: &str) -> (&str, Option<usize>) {
+    if let (Some(start), true) = (line.rfind('{'), line.ends_with('}')) {
+        if let Ok(len) = line[start + 1..line.len() - 1].trim_end_matches('+').parse() {
+            return (&line[..start], Some(len));
+        }
+    }
+    (line, None)
+}
+
+fn parse_words(text: &str, words: &mut Vec<Word>) -> Result<()> {
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some(c) => value.push(c),
+                        None => return Err(anyhow!("Unterminated string in server response")),
+                    }
+                }
+                words.push(Word::String(value));
+            }
+            // Response codes such as (QUOTA) are kept as one word
+            '(' => {
+                let code: String = chars.by_ref().take_while(|&c| c != ')').collect();
+                words.push(Word::Atom(format!("{})", code)));
+            }
+            _ => words.push(Word::Atom(chars.by_ref().take_while(|&c| c != ' ').collect())),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parsed words as `(is_string, text)`.
+    fn words(text: &str) -> Vec<(bool, String)> {
+        let mut words = Vec::new();
+        parse_words(text, &mut words).unwrap();
+        words
+            .into_iter()
+            .map(|w| (matches!(w, Word::String(_)), w.into_text()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_atoms_strings_and_escapes() {
+        assert_eq!(
+            words(r#""vacation"  ACTIVE "say \"hi\" \\ bye""#),
+            [
+                (true, "vacation".to_string()),
+                (false, "ACTIVE".to_string()),
+                (true, r#"say "hi" \ bye"#.to_string()),
+            ]
+        );
+        let mut words = Vec::new();
+        assert!(parse_words(r#""unterminated"#, &mut words).is_err());
+    }
+
+    #[test]
+    fn keeps_response_codes_as_one_word() {
+        let parsed = words(r#"NO (QUOTA/MAXSIZE) "Script too large""#);
+        assert_eq!(parsed[1], (false, "(QUOTA/MAXSIZE)".to_string()));
+        let error = status(&parsed.into_iter().map(|(_, t)| Word::Atom(t)).collect::<Vec<_>>())
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "NO (QUOTA/MAXSIZE) Script too large");
+    }
+
+    #[test]
+    fn splits_literal_announcements() {
+        assert_eq!(split_literal(r#""script" {12}"#), (r#""script" "#, Some(12)));
+        assert_eq!(split_literal("{7+}"), ("", Some(7)));
+        assert_eq!(split_literal(r#""{not a literal}" ACTIVE"#), (r#""{not a literal}" ACTIVE"#, None));
+        assert_eq!(split_literal("{many}"), ("{many}", None));
+    }
+
+    #[test]
+    fn quotes_short_single_line_strings() {
+        assert_eq!(string(r#"my "best" \ script"#), r#""my \"best\" \\ script""#);
+        assert_eq!(string("a\r\nb"), "{4+}\r\na\r\nb");
+        let long = "x".repeat(1025);
+        assert_eq!(string(&long), format!("{{1025+}}\r\n{}", long));
+        assert!(string(&"x".repeat(1024)).starts_with('"'));
+    }
+}
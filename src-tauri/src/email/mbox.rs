@@ -0,0 +1,321 @@
+use super::mime;
+use crate::storage::Store;
+use crate::types::{Email, EmailQuery, ImportProgress, MboxFormat};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const PROGRESS_INTERVAL: usize = 100;
+
+/// Streams the messages of an mbox file one at a time, undoing the
+/// From-line escaping of the given variant.
+pub struct MboxReader<R: BufRead> {
+    reader: R,
+    format: MboxFormat,
+    /// The `From ` separator line that starts the next message, if already read.
+    pending_separator: bool,
+    bytes_read: u64,
+    done: bool,
+}
+
+impl<R: BufRead> MboxReader<R> {
+    pub fn new(reader: R, format: MboxFormat) -> Self {
+        Self {
+            reader,
+            format,
+            pending_separator: false,
+            bytes_read: 0,
+            done: false,
+        }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn read_line(&mut self, line: &mut Vec<u8>) -> Result<bool> {
+        line.clear();
+        let n = self.reader.read_until(b'\n', line)?;
+        self.bytes_read += n as u64;
+        Ok(n > 0)
+    }
+
+    fn unescape(&self, line: &mut Vec<u8>) {
+        let quoted = line.iter().take_while(|&&b| b == b'>').count();
+        if quoted == 0 || !line[quoted..].starts_with(b"From ") {
+            return;
+        }
+        match self.format {
+            MboxFormat::Mboxrd => {
+                line.remove(0);
+            }
+            MboxFormat::Mboxo if quoted == 1 => {
+                line.remove(0);
+            }
+            MboxFormat::Mboxo => {}
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for MboxReader<R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut line = Vec::new();
+
+        // Skip anything before the first separator line.
+        while !self.pending_separator {
+            match self.read_line(&mut line) {
+                Ok(true) if line.starts_with(b"From ") => self.pending_separator = true,
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.pending_separator = false;
+
+        let mut message = Vec::new();
+        let mut previous_blank = false;
+        loop {
+            match self.read_line(&mut line) {
+                Ok(true) => {
+                    if previous_blank && line.starts_with(b"From ") {
+                        self.pending_separator = true;
+                        break;
+                    }
+                    previous_blank = line == b"\n" || line == b"\r\n";
+                    self.unescape(&mut line);
+                    message.extend_from_slice(&line);
+                }
+                Ok(false) => {
+                    self.done = true;
+                    break;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        // The blank line before the next separator belongs to the mbox framing.
+        if message.ends_with(b"\r\n\r\n") {
+            message.truncate(message.len() - 2);
+        } else if message.ends_with(b"\n\n") {
+            message.truncate(message.len() - 1);
+        }
+
+        Some(Ok(message))
+    }
+}
+
+/// Appends one message to an mbox stream, escaping body lines that could be
+/// mistaken for a separator.
+pub fn write_message<W: Write>(
+    writer: &mut W,
+    email: &Email,
+    raw: &[u8],
+    format: MboxFormat,
+) -> Result<()> {
+    let sender = if email.from.address.is_empty() {
+        "MAILER-DAEMON"
+    } else {
+        email.from.address.as_str()
+    };
+    let date = chrono::DateTime::parse_from_rfc2822(&email.date)
+        .map(|d| d.with_timezone(&chrono::Utc))
+        .unwrap_or_else(|_| chrono::Utc::now());
+    writeln!(writer, "From {} {}", sender, date.format("%a %b %e %H:%M:%S %Y"))?;
+
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        let quoted = line.iter().take_while(|&&b| b == b'>').count();
+        let needs_escape = line[quoted..].starts_with(b"From ")
+            && match format {
+                MboxFormat::Mboxrd => true,
+                MboxFormat::Mboxo => quoted == 0,
+            };
+        if needs_escape {
+            writer.write_all(b">")?;
+        }
+        writer.write_all(line)?;
+    }
+
+    if !raw.ends_with(b"\n") {
+        writer.write_all(b"\n")?;
+    }
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Imports every message of an mbox file into `account_id`, skipping messages
+/// already stored for that account, see `import_key`. The emails are added
+/// together at the end.
+pub fn import(
+    store: &Store,
+    path: &Path,
+    format: MboxFormat,
+    account_id: &str,
+    folder: Option<&str>,
+    mut on_progress: impl FnMut(&ImportProgress),
+) -> Result<ImportProgress> {
+    let file = File::open(path)?;
+    let mut progress = ImportProgress {
+        total_bytes: file.metadata()?.len(),
+        ..Default::default()
+    };
+    let mut seen = super::stored_import_keys(store, account_id)?;
+    let mut reader = MboxReader::new(BufReader::new(file), format);
+    let mut imported = Vec::new();
+
+    while let Some(raw) = reader.next() {
+        let raw = raw?;
+        progress.processed += 1;
+
+        match mime::parse_email(&raw, account_id) {
+            Ok(mut email) => {
+                if seen.insert(super::import_key(&email, &raw)) {
+                    email.folder = folder.map(|f| f.to_string());
+                    store.save_raw(&email.id, &raw)?;
+                    imported.push(email);
+                    progress.imported += 1;
+                } else {
+                    progress.duplicates += 1;
+                }
+            }
+            Err(_) => progress.failed += 1,
+        }
+
+        if progress.processed.is_multiple_of(PROGRESS_INTERVAL) {
+            progress.bytes_read = reader.bytes_read();
+            on_progress(&progress);
+        }
+    }
+
+    super::add_imported(store, imported)?;
+    progress.bytes_read = reader.bytes_read();
+    on_progress(&progress);
+    Ok(progress)
+}
+
+/// Writes all emails matching `query` to a new mbox file and returns how many were written.
+pub fn export(store: &Store, path: &Path, query: &EmailQuery, format: MboxFormat) -> Result<usize> {
+    let emails = store.query_emails(query)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    for email in &emails {
+        write_message(&mut writer, email, &super::raw_source(store, email)?, format)?;
+    }
+    writer.flush()?;
+    Ok(emails.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::autoconfig;
+
+    /// Body lines that look like separators, quoted once and twice, as
+    /// mboxrd writers escape them.
+    const MBOXRD: &[u8] = b"From ann@example.com Thu Jan  1 00:00:00 2026\n\
+Message-ID: <one@example.com>\n\
+Subject: One\n\
+\n\
+>From here on\n\
+>>From the archive\n\
+\n\
+From bob@example.com Thu Jan  1 00:00:00 2026\n\
+Subject: Two, without a Message-ID\n\
+\n\
+Hello\n";
+
+    fn messages(data: &[u8], format: MboxFormat) -> Vec<String> {
+        MboxReader::new(data, format)
+            .map(|m| String::from_utf8(m.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn mboxrd_removes_one_level_of_quoting() {
+        let messages = messages(MBOXRD, MboxFormat::Mboxrd);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            "Message-ID: <one@example.com>\nSubject: One\n\nFrom here on\n>From the archive\n"
+        );
+        assert_eq!(messages[1], "Subject: Two, without a Message-ID\n\nHello\n");
+    }
+
+    #[test]
+    fn mboxo_unquotes_only_single_quotes() {
+        let messages = messages(MBOXRD, MboxFormat::Mboxo);
+        assert_eq!(
+            messages[0],
+            "Message-ID: <one@example.com>\nSubject: One\n\nFrom here on\n>>From the archive\n"
+        );
+    }
+
+    #[test]
+    fn from_lines_without_a_blank_line_before_do_not_split() {
+        let data = b"From a@example.com Thu Jan  1 00:00:00 2026\r\nSubject: One\r\n\r\nText\r\nFrom me, with love\r\n";
+        let messages = messages(data, MboxFormat::Mboxrd);
+        assert_eq!(messages, ["Subject: One\r\n\r\nText\r\nFrom me, with love\r\n"]);
+    }
+
+    #[test]
+    fn written_messages_read_back_unchanged() {
+        let email = mime::parse_email(b"From: ann@example.com\n\nx\n", "acc").unwrap();
+        let raws: [&[u8]; 2] = [
+            b"Subject: A\n\nFrom here on\n>From the archive\n\n",
+            b"Subject: B\n\n>>From deeper\nFrom",
+        ];
+        for format in [MboxFormat::Mboxrd, MboxFormat::Mboxo] {
+            let mut data = Vec::new();
+            for raw in raws {
+                write_message(&mut data, &email, raw, format).unwrap();
+            }
+            let read: Vec<Vec<u8>> = MboxReader::new(&data[..], format).map(Result::unwrap).collect();
+            assert_eq!(read[1], b"Subject: B\n\n>>From deeper\nFrom\n");
+            match format {
+                MboxFormat::Mboxrd => assert_eq!(read[0], raws[0]),
+                // Quoted lines cannot be told apart from escaped ones
+                MboxFormat::Mboxo => assert_eq!(read[0], b"Subject: A\n\nFrom here on\nFrom the archive\n\n"),
+            }
+        }
+    }
+
+    #[test]
+    fn importing_again_adds_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.mbox");
+        std::fs::write(&path, MBOXRD).unwrap();
+        let store = Store::new(dir.path().join("data"), autoconfig::migrate_legacy_config).unwrap();
+
+        let mut events = 0;
+        let first = import(&store, &path, MboxFormat::Mboxrd, "acc", Some("Archive"), |_| events += 1).unwrap();
+        assert_eq!((first.processed, first.imported, first.duplicates), (2, 2, 0));
+        assert_eq!(first.bytes_read, MBOXRD.len() as u64);
+        assert_eq!(events, 1);
+
+        // A new store reads what the first one saved
+        let store = Store::new(dir.path().join("data"), autoconfig::migrate_legacy_config).unwrap();
+        let second = import(&store, &path, MboxFormat::Mboxrd, "acc", None, |_| {}).unwrap();
+        assert_eq!((second.imported, second.duplicates), (0, 2));
+        let emails = store.get_emails().unwrap();
+        assert_eq!(emails.len(), 2);
+        assert!(emails.iter().all(|e| e.folder.as_deref() == Some("Archive")));
+
+        // Other accounts get their own copy
+        let other = import(&store, &path, MboxFormat::Mboxrd, "other", None, |_| {}).unwrap();
+        assert_eq!(other.imported, 2);
+    }
+}
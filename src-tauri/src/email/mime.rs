@@ -0,0 +1,296 @@
+use crate::types::{Attachment, Email, EmailAddress};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use mail_parser::{Address, MessageParser, MessagePart, MimeHeaders};
+
+/// Parses a raw RFC 822 message into an `Email` owned by `account_id`.
+pub fn parse_email(raw: &[u8], account_id: &str) -> Result<Email> {
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| anyhow!("Not a valid RFC 822 message"))?;
+
+    let from = message
+        .from()
+        .and_then(|a| a.first())
+        .map(|a| EmailAddress {
+            name: a.name().map(|s| s.to_string()),
+            address: a.address().unwrap_or_default().to_string(),
+        })
+        .unwrap_or(EmailAddress {
+            name: None,
+            address: String::new(),
+        });
+
+    let date = message
+        .date()
+        .and_then(|d| chrono::DateTime::parse_from_rfc3339(&d.to_rfc3339()).ok())
+        .map(|d| d.to_rfc2822())
+        .unwrap_or_else(|| chrono::Utc::now().to_rfc2822());
+
+    let html_body = message
+        .html_body
+        .iter()
+        .filter_map(|id| message.part(*id))
+        .find(|part| part.is_text_html())
+        .and_then(|part| part.text_contents())
+        .map(|s| s.to_string());
+
+    let attachments: Vec<Attachment> = message.attachments().map(|part| attachment(part, None)).collect();
+
+    let references: Vec<String> = message
+        .references()
+        .as_text_list()
+        .map(|list| list.iter().map(|r| r.to_string()).collect())
+        .or_else(|| message.references().as_text().map(|r| vec![r.to_string()]))
+        .unwrap_or_default();
+
+    Ok(Email {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id: account_id.to_string(),
+        subject: message.subject().unwrap_or_default().to_string(),
+        from,
+        to: addresses(message.to()),
+        cc: non_empty(addresses(message.cc())),
+        bcc: non_empty(addresses(message.bcc())),
+        reply_to: non_empty(addresses(message.reply_to())),
+        date,
+        body: message
+            .body_text(0)
+            .map(|s| s.to_string())
+            .unwrap_or_default(),
+        html_body,
+        attachments: non_empty(attachments),
+        is_read: false,
+        is_starred: false,
+        labels: None,
+        ai_classification: None,
+        folder: None,
+        message_id: message.message_id().map(|s| s.to_string()),
+        in_reply_to: message
+            .in_reply_to()
+            .as_text()
+            .map(|s| s.to_string()),
+        references: non_empty(references),
+        remote_id: None,
+        quarantine: None,
+        trashed: None,
+        snooze: None,
+        summary: None,
+        thread_summary: None,
+    })
+}
+
+/// The attachments of a raw message, with their content.
+pub fn parse_attachments(raw: &[u8]) -> Result<Vec<Attachment>> {
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| anyhow!("Not a valid RFC 822 message"))?;
+    Ok(message
+        .attachments()
+        .map(|part| attachment(part, Some(BASE64.encode(part.contents()))))
+        .collect())
+}
+
+fn attachment(part: &MessagePart, content: Option<String>) -> Attachment {
+    Attachment {
+        id: uuid::Uuid::new_v4().to_string(),
+        filename: part.attachment_name().unwrap_or("attachment").to_string(),
+        mime_type: part
+            .content_type()
+            .map(|ct| match ct.subtype() {
+                Some(sub) => format!("{}/{}", ct.ctype(), sub),
+                None => ct.ctype().to_string(),
+            })
+            .unwrap_or_else(|| "application/octet-stream".to_string()),
+        size: part.contents().len() as u64,
+        content,
+    }
+}
+
+fn addresses(address: Option<&Address>) -> Vec<EmailAddress> {
+    address
+        .map(|a| {
+            a.iter()
+                .filter_map(|addr| {
+                    Some(EmailAddress {
+                        name: addr.name().map(|s| s.to_string()),
+                        address: addr.address()?.to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+/// Renders an `Email` back into an RFC 822 message with CRLF line endings.
+/// Used when no original source is available, e.g. for exports and sending.
+pub fn render_email(email: &Email) -> Vec<u8> {
+    let mut out = String::new();
+
+    let message_id = email
+        .message_id
+        .clone()
+        .unwrap_or_else(|| format!("{}@mailhub.local", email.id));
+    header(&mut out, "Message-ID", &format!("<{}>", message_id));
+    header(&mut out, "Date", &email.date);
+    header(&mut out, "From", &format_address(&email.from));
+    if !email.to.is_empty() {
+        header(&mut out, "To", &format_addresses(&email.to));
+    }
+    if let Some(cc) = email.cc.as_ref().filter(|cc| !cc.is_empty()) {
+        header(&mut out, "Cc", &format_addresses(cc));
+    }
+    if let Some(reply_to) = email.reply_to.as_ref().filter(|r| !r.is_empty()) {
+        header(&mut out, "Reply-To", &format_addresses(reply_to));
+    }
+    header(&mut out, "Subject", &encode_word(&email.subject));
+    if let Some(in_reply_to) = &email.in_reply_to {
+        header(&mut out, "In-Reply-To", &format!("<{}>", in_reply_to));
+    }
+    if let Some(references) = email.references.as_ref().filter(|r| !r.is_empty()) {
+        let refs: Vec<String> = references.iter().map(|r| format!("<{}>", r)).collect();
+        header(&mut out, "References", &refs.join(" "));
+    }
+    header(&mut out, "MIME-Version", "1.0");
+
+    let attachments: Vec<&Attachment> = email
+        .attachments
+        .iter()
+        .flatten()
+        .filter(|a| a.content.is_some())
+        .collect();
+
+    let body = render_body(email);
+    if attachments.is_empty() {
+        out.push_str(&body);
+    } else {
+        let boundary = format!("mixed-{}", uuid::Uuid::new_v4().simple());
+        header(
+            &mut out,
+            "Content-Type",
+            &format!("multipart/mixed; boundary=\"{}\"", boundary),
+        );
+        out.push_str("\r\n");
+        out.push_str(&format!("--{}\r\n", boundary));
+        out.push_str(&body);
+        for attachment in attachments {
+            out.push_str(&format!("\r\n--{}\r\n", boundary));
+            header(
+                &mut out,
+                "Content-Type",
+                &format!("{}; name=\"{}\"", attachment.mime_type, attachment.filename),
+            );
+            header(
+                &mut out,
+                "Content-Disposition",
+                &format!("attachment; filename=\"{}\"", attachment.filename),
+            );
+            header(&mut out, "Content-Transfer-Encoding", "base64");
+            out.push_str("\r\n");
+            out.push_str(&wrap_base64(attachment.content.as_deref().unwrap_or("")));
+        }
+        out.push_str(&format!("\r\n--{}--\r\n", boundary));
+    }
+
+    out.into_bytes()
+}
+
+/// Renders the text/html alternatives, including their own headers.
+fn render_body(email: &Email) -> String {
+    let mut out = String::new();
+    match &email.html_body {
+        Some(html) => {
+            let boundary = format!("alt-{}", uuid::Uuid::new_v4().simple());
+            header(
+                &mut out,
+                "Content-Type",
+                &format!("multipart/alternative; boundary=\"{}\"", boundary),
+            );
+            out.push_str("\r\n");
+            out.push_str(&format!("--{}\r\n", boundary));
+            out.push_str(&render_text_part("text/plain", &email.body));
+            out.push_str(&format!("\r\n--{}\r\n", boundary));
+            out.push_str(&render_text_part("text/html", html));
+            out.push_str(&format!("\r\n--{}--\r\n", boundary));
+        }
+        None => out.push_str(&render_text_part("text/plain", &email.body)),
+    }
+    out
+}
+
+fn render_text_part(mime_type: &str, text: &str) -> String {
+    let mut out = String::new();
+    header(
+        &mut out,
+        "Content-Type",
+        &format!("{}; charset=utf-8", mime_type),
+    );
+    if text.is_ascii() && text.lines().all(|l| l.len() <= 998) {
+        header(&mut out, "Content-Transfer-Encoding", "7bit");
+        out.push_str("\r\n");
+        for line in text.lines() {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    } else {
+        header(&mut out, "Content-Transfer-Encoding", "base64");
+        out.push_str("\r\n");
+        out.push_str(&wrap_base64(&BASE64.encode(text.as_bytes())));
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, value: &str) {
+    out.push_str(name);
+    out.push_str(": ");
+    out.push_str(value);
+    out.push_str("\r\n");
+}
+
+fn wrap_base64(encoded: &str) -> String {
+    let mut out = String::new();
+    for chunk in encoded.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// Encodes a header value as an RFC 2047 encoded-word when it is not plain ASCII.
+fn encode_word(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64.encode(value.as_bytes()))
+    }
+}
+
+pub fn format_address(address: &EmailAddress) -> String {
+    match &address.name {
+        Some(name) if !name.is_empty() => {
+            if name.is_ascii() {
+                format!("\"{}\" <{}>", name.replace('"', "\\\""), address.address)
+            } else {
+                format!("{} <{}>", encode_word(name), address.address)
+            }
+        }
+        _ => address.address.clone(),
+    }
+}
+
+fn format_addresses(addresses: &[EmailAddress]) -> String {
+    addresses
+        .iter()
+        .map(format_address)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
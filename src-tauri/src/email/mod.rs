@@ -1,13 +1,211 @@
-use crate::types::{Email, EmailAccount, EmailAddress};
-use anyhow::Result;
+pub mod autoconfig;
+pub mod eml;
+pub mod health;
+pub mod jmap;
+pub mod maildir;
+pub mod managesieve;
+pub mod mbox;
+pub mod mime;
+pub mod net;
+pub mod smtp;
+pub mod templates;
+
+use crate::storage::Store;
+use crate::types::{
+    Attachment, AuthMethod, Email, EmailAccount, EmailAddress, Identity, Protocol, RemoteFlags,
+    ServerConfig,
+};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Changes found on an account's backend since the last sync.
+#[derive(Debug, Default)]
+pub struct FetchResult {
+    /// Messages that are not stored yet.
+    pub emails: Vec<Email>,
+    /// Original RFC 822 source of new messages, by email id, for backends
+    /// that do not keep it as a local file.
+    pub sources: HashMap<String, Vec<u8>>,
+    /// Current flags of messages that are already stored.
+    pub flag_updates: Vec<RemoteFlags>,
+    /// Remote ids of stored messages that no longer exist on the backend.
+    pub removed: Vec<String>,
+    /// Opaque backend state to resume from on the next sync, e.g. a JMAP state string.
+    pub sync_state: Option<String>,
+}
+
+/// How to log in to one of an account's servers.
+pub enum Credentials {
+    Password { username: String, password: String },
+    OAuth2 { username: String, token: String },
+    None,
+}
+
+impl Credentials {
+    /// The username defaults to the account's address; OAuth2 uses the account's token.
+    pub fn for_server(account: &EmailAccount, server: &ServerConfig) -> Result<Self> {
+        let username = server.username.clone().unwrap_or_else(|| account.email.clone());
+        Ok(match server.auth {
+            AuthMethod::Password => Credentials::Password {
+                username,
+                password: server
+                    .password
+                    .clone()
+                    .ok_or_else(|| anyhow!("No password configured"))?,
+            },
+            AuthMethod::OAuth2 => Credentials::OAuth2 {
+                username,
+                token: account
+                    .config
+                    .oauth_token
+                    .clone()
+                    .ok_or_else(|| anyhow!("No OAuth token, sign in again"))?,
+            },
+            AuthMethod::None => Credentials::None,
+        })
+    }
+
+    /// The SASL XOAUTH2 initial response, for OAuth2 credentials.
+    pub fn xoauth2(&self) -> Option<String> {
+        match self {
+            Credentials::OAuth2 { username, token } => Some(BASE64.encode(format!(
+                "user={}\x01auth=Bearer {}\x01\x01",
+                username, token
+            ))),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the original RFC 822 source of an email. Stored sources (imported
+/// and JMAP mail) are used as-is, Maildir messages are read from their file,
+/// and anything else is rendered from the parsed fields.
+pub fn raw_source(store: &Store, email: &Email) -> Result<Vec<u8>> {
+    Ok(match original_source(store, email)? {
+        Some(raw) => raw,
+        None => mime::render_email(email),
+    })
+}
+
+/// The original RFC 822 source of an email, if it was kept.
+fn original_source(store: &Store, email: &Email) -> Result<Option<Vec<u8>>> {
+    if let Some(raw) = store.load_raw(&email.id)? {
+        return Ok(Some(raw));
+    }
+
+    let account = store
+        .get_accounts()?
+        .into_iter()
+        .find(|a| a.id == email.account_id);
+    if let (Some(account), Some(remote_id)) = (account, &email.remote_id) {
+        if matches!(account.protocol, Protocol::Maildir) {
+            let root = maildir::account_root(&account)?;
+            if let Some(entry) = maildir::scan(&root)?.into_iter().find(|e| &e.unique == remote_id) {
+                return Ok(Some(std::fs::read(entry.path)?));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Identifies a message on import: its Message-ID, or a hash of its source
+/// if it has none.
+pub fn import_key(email: &Email, raw: &[u8]) -> String {
+    email
+        .message_id
+        .clone()
+        .unwrap_or_else(|| hex::encode(Sha256::digest(raw)))
+}
+
+/// Import keys of the messages stored for an account, so importing the same
+/// messages again adds nothing. Messages without a Message-ID are only
+/// recognized by their stored source, which every import keeps.
+pub fn stored_import_keys(store: &Store, account_id: &str) -> Result<HashSet<String>> {
+    let mut keys = store.message_ids(account_id)?;
+    for id in store.ids_without_message_id(account_id)? {
+        if let Some(raw) = store.load_raw(&id)? {
+            keys.insert(hex::encode(Sha256::digest(&raw)));
+        }
+    }
+    Ok(keys)
+}
+
+/// Stores a batch of imported emails whose sources were already saved with
+/// `Store::save_raw`. If the batch cannot be stored, the saved sources are
+/// removed again so no orphaned files are left behind.
+pub fn add_imported(store: &Store, emails: Vec<Email>) -> Result<()> {
+    let ids: Vec<String> = emails.iter().map(|e| e.id.clone()).collect();
+    store.add_emails(emails).inspect_err(|_| {
+        for id in &ids {
+            let _ = store.delete_raw(id);
+        }
+    })
+}
+
+/// An email's attachments with their content. Only metadata is stored for
+/// synced and imported mail, so the content is read from the original
+/// source; fails if an attachment cannot be found there.
+pub fn load_attachments(store: &Store, email: &Email) -> Result<Option<Vec<Attachment>>> {
+    let Some(attachments) = &email.attachments else {
+        return Ok(None);
+    };
+    if attachments.iter().all(|a| a.content.is_some()) {
+        return Ok(Some(attachments.clone()));
+    }
+
+    let mut parsed = match original_source(store, email)? {
+        Some(raw) => mime::parse_attachments(&raw)?,
+        None => Vec::new(),
+    };
+    attachments
+        .iter()
+        .map(|attachment| {
+            if attachment.content.is_some() {
+                return Ok(attachment.clone());
+            }
+            let found = parsed
+                .iter()
+                .position(|p| p.filename == attachment.filename)
+                .ok_or_else(|| anyhow!("Attachment {} of \"{}\" is not available", attachment.filename, email.subject))?;
+            Ok(Attachment {
+                content: parsed.remove(found).content,
+                ..attachment.clone()
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
 
 pub struct EmailClient;
 
 impl EmailClient {
-    pub async fn fetch_emails(account: &EmailAccount) -> Result<Vec<Email>> {
-        // For demonstration, return mock emails
-        // In production, this would implement actual IMAP/POP3/OAuth2
-        Ok(Self::generate_demo_emails(&account.id))
+    /// Fetches changes for `account`. `known` holds the remote ids already
+    /// stored for it, so backends can skip downloading those messages again,
+    /// and `sync_state` is the state returned by the previous sync, if any.
+    pub async fn fetch_emails(
+        account: &EmailAccount,
+        known: &HashSet<String>,
+        sync_state: Option<&str>,
+    ) -> Result<FetchResult> {
+        match account.protocol {
+            // Local accounts only hold imported mail and have no server to sync with
+            Protocol::Local => Ok(FetchResult::default()),
+            Protocol::Maildir => {
+                let account = account.clone();
+                let known = known.clone();
+                tokio::task::spawn_blocking(move || maildir::fetch(&account, &known)).await?
+            }
+            Protocol::Jmap => jmap::fetch(account, known, sync_state).await,
+            // For demonstration, return mock emails
+            // In production, this would implement actual IMAP/POP3/OAuth2
+            _ => Ok(FetchResult {
+                emails: Self::generate_demo_emails(&account.id),
+                ..Default::default()
+            }),
+        }
     }
 
     fn generate_demo_emails(account_id: &str) -> Vec<Email> {
@@ -26,6 +224,7 @@ impl EmailClient {
                 }],
                 cc: None,
                 bcc: None,
+                reply_to: None,
                 date: chrono::Utc::now().to_rfc2822(),
                 body: "Thank you for using MailHub! This is a demo email to showcase the email management capabilities.".to_string(),
                 html_body: Some("<p>Thank you for using <strong>MailHub</strong>! This is a demo email to showcase the email management capabilities.</p>".to_string()),
@@ -34,6 +233,16 @@ impl EmailClient {
                 is_starred: false,
                 labels: None,
                 ai_classification: None,
+                folder: None,
+                message_id: Some(format!("{}@mailhub.app", uuid::Uuid::new_v4())),
+                in_reply_to: None,
+                references: None,
+                remote_id: None,
+                quarantine: None,
+                trashed: None,
+                snooze: None,
+                summary: None,
+                thread_summary: None,
             },
             Email {
                 id: uuid::Uuid::new_v4().to_string(),
@@ -49,6 +258,7 @@ impl EmailClient {
                 }],
                 cc: None,
                 bcc: None,
+                reply_to: None,
                 date: chrono::Utc::now().to_rfc2822(),
                 body: "Your verification code is: 123456. Please use it to verify your account.".to_string(),
                 html_body: Some("<p>Your verification code is: <strong>123456</strong>. Please use it to verify your account.</p>".to_string()),
@@ -57,19 +267,275 @@ impl EmailClient {
                 is_starred: false,
                 labels: None,
                 ai_classification: None,
+                folder: None,
+                message_id: Some(format!("{}@mailhub.app", uuid::Uuid::new_v4())),
+                in_reply_to: None,
+                references: None,
+                remote_id: None,
+                quarantine: None,
+                trashed: None,
+                snooze: None,
+                summary: None,
+                thread_summary: None,
             },
         ]
     }
 }
 
+/// Picks the identity a reply should be sent from: the one whose address
+/// the original message was sent to.
+pub fn identity_for_reply<'a>(account: &'a EmailAccount, original: &Email) -> Option<&'a Identity> {
+    let recipients: Vec<&EmailAddress> = original
+        .to
+        .iter()
+        .chain(original.cc.iter().flatten())
+        .collect();
+    account
+        .identities
+        .iter()
+        .flatten()
+        .find(|identity| {
+            recipients
+                .iter()
+                .any(|r| r.address.eq_ignore_ascii_case(&identity.email))
+        })
+}
+
+/// Builds an outgoing message from `identity`, or from the account's own
+/// address, threaded onto `original` when it is a reply.
+pub fn compose(
+    account: &EmailAccount,
+    identity: Option<&Identity>,
+    to: &str,
+    subject: &str,
+    body: &str,
+    original: Option<&Email>,
+) -> Email {
+    let from = match identity {
+        Some(identity) => EmailAddress {
+            name: identity.display_name.clone().or_else(|| account.display_name.clone()),
+            address: identity.email.clone(),
+        },
+        None => EmailAddress {
+            name: account.display_name.clone(),
+            address: account.email.clone(),
+        },
+    };
+
+    let mut references = original
+        .and_then(|o| o.references.clone())
+        .unwrap_or_default();
+    if let Some(message_id) = original.and_then(|o| o.message_id.clone()) {
+        references.push(message_id);
+    }
+
+    Email {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id: account.id.clone(),
+        subject: subject.to_string(),
+        from,
+        to: to
+            .split(',')
+            .map(|a| a.trim())
+            .filter(|a| !a.is_empty())
+            .map(|a| EmailAddress {
+                name: None,
+                address: a.to_string(),
+            })
+            .collect(),
+        cc: None,
+        bcc: None,
+        reply_to: identity
+            .and_then(|i| i.reply_to.clone())
+            .map(|address| vec![EmailAddress { name: None, address }]),
+        date: chrono::Utc::now().to_rfc2822(),
+        body: body.to_string(),
+        html_body: None,
+        attachments: None,
+        is_read: true,
+        is_starred: false,
+        labels: None,
+        ai_classification: None,
+        folder: None,
+        message_id: Some(format!("{}@mailhub.app", uuid::Uuid::new_v4())),
+        in_reply_to: original.and_then(|o| o.message_id.clone()),
+        references: (!references.is_empty()).then_some(references),
+        remote_id: None,
+        quarantine: None,
+        trashed: None,
+        snooze: None,
+        summary: None,
+        thread_summary: None,
+    }
+}
+
+/// Builds a message forwarding `original` to `to` inline, with its
+/// attachments as loaded by `load_attachments`.
+pub fn forward(
+    account: &EmailAccount,
+    original: &Email,
+    attachments: Option<Vec<Attachment>>,
+    to: &str,
+) -> Email {
+    let subject = if original.subject.to_lowercase().starts_with("fwd:") {
+        original.subject.clone()
+    } else {
+        format!("Fwd: {}", original.subject)
+    };
+    let recipients: Vec<String> = original.to.iter().map(mime::format_address).collect();
+    let body = format!(
+        "---------- Forwarded message ----------\nFrom: {}\nDate: {}\nSubject: {}\nTo: {}\n\n{}",
+        mime::format_address(&original.from),
+        original.date,
+        original.subject,
+        recipients.join(", "),
+        original.body
+    );
+    let mut email = compose(account, None, to, &subject, &body, None);
+    email.attachments = attachments;
+    email
+}
+
+/// Writes the read/starred state of emails of one account back to its
+/// backend in batches: a single Maildir scan, or one JMAP call per page of
+/// messages. Deleted emails stay on the server until purged, see `expunge`.
+pub async fn write_back_flags(account: &EmailAccount, emails: &[Email]) -> Result<()> {
+    let updates: Vec<RemoteFlags> = emails
+        .iter()
+        .filter_map(|e| {
+            Some(RemoteFlags {
+                remote_id: e.remote_id.clone()?,
+                is_read: e.is_read,
+                is_starred: e.is_starred,
+            })
+        })
+        .collect();
+    match account.protocol {
+        Protocol::Maildir => {
+            let root = maildir::account_root(account)?;
+            tokio::task::spawn_blocking(move || maildir::set_flags_many(&root, &updates)).await??
+        }
+        Protocol::Jmap => jmap::set_flags(account, &updates).await?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Deletes emails of one account for good on its backend: Maildir files
+/// are removed and JMAP messages destroyed. Other backends keep no server
+/// copy this client manages.
+pub async fn expunge(account: &EmailAccount, emails: &[Email]) -> Result<()> {
+    let remote_ids: Vec<String> = emails.iter().filter_map(|e| e.remote_id.clone()).collect();
+    match account.protocol {
+        Protocol::Maildir => {
+            let root = maildir::account_root(account)?;
+            for remote_id in &remote_ids {
+                maildir::expunge(&root, remote_id)?;
+            }
+        }
+        Protocol::Jmap => jmap::destroy(account, &remote_ids).await?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Sends a composed message through the identity's own SMTP server, the
+/// account's outgoing server, or JMAP.
 pub async fn send_email(
-    _account: &EmailAccount,
-    _to: &str,
-    _subject: &str,
-    _body: &str,
+    account: &EmailAccount,
+    identity: Option<&Identity>,
+    email: &Email,
 ) -> Result<()> {
-    // For demonstration, just return success
-    // In production, this would implement actual SMTP sending
-    println!("Email sent successfully (demo mode)");
-    Ok(())
+    if matches!(account.protocol, Protocol::Jmap) {
+        return jmap::send(account, email).await;
+    }
+
+    let server = identity
+        .and_then(|i| i.smtp.as_ref())
+        .or(account.config.outgoing.as_ref())
+        .filter(|s| s.host.is_some())
+        .ok_or_else(|| anyhow!("No outgoing server configured for {}", account.email))?;
+
+    let recipients: Vec<String> = email
+        .to
+        .iter()
+        .chain(email.cc.iter().flatten())
+        .chain(email.bcc.iter().flatten())
+        .map(|a| a.address.clone())
+        .collect();
+    let credentials = Credentials::for_server(account, server)?;
+    smtp::send(
+        server,
+        &credentials,
+        &email.from.address,
+        &recipients,
+        &mime::render_email(email),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountConfig, ServerConfig};
+
+    fn identity(id: &str, email: &str) -> Identity {
+        Identity {
+            id: id.to_string(),
+            email: email.to_string(),
+            display_name: None,
+            reply_to: None,
+            signature_id: None,
+            smtp: None,
+        }
+    }
+
+    fn account() -> EmailAccount {
+        EmailAccount {
+            id: "acc".to_string(),
+            name: "Work".to_string(),
+            email: "me@example.com".to_string(),
+            display_name: None,
+            tags: None,
+            protocol: Protocol::Local,
+            provider: None,
+            config: AccountConfig {
+                incoming: ServerConfig::default(),
+                outgoing: None,
+                sieve: None,
+                oauth_token: None,
+                refresh_token: None,
+                path: None,
+            },
+            identities: Some(vec![
+                identity("sales", "sales@example.com"),
+                identity("support", "support@example.com"),
+            ]),
+            signature_id: None,
+        }
+    }
+
+    fn received(headers: &str) -> Email {
+        let raw = format!("From: ann@example.org\r\n{}\r\nSubject: Hi\r\n\r\nHi\r\n", headers);
+        mime::parse_email(raw.as_bytes(), "acc").unwrap()
+    }
+
+    #[test]
+    fn replies_from_the_identity_that_was_addressed() {
+        let account = account();
+        let to = received("To: Support <SUPPORT@Example.com>");
+        assert_eq!(identity_for_reply(&account, &to).map(|i| i.id.as_str()), Some("support"));
+        let cc = received("To: me@example.com\r\nCc: bob@example.org, sales@example.com");
+        assert_eq!(identity_for_reply(&account, &cc).map(|i| i.id.as_str()), Some("sales"));
+    }
+
+    #[test]
+    fn replies_from_the_account_when_no_identity_was_addressed() {
+        let account = account();
+        let email = received("To: me@example.com");
+        assert!(identity_for_reply(&account, &email).is_none());
+        let identity = identity_for_reply(&account, &email);
+        let reply = compose(&account, identity, "ann@example.org", "Re: Hi", "Hello", Some(&email));
+        assert_eq!(reply.from.address, "me@example.com");
+    }
 }
@@ -0,0 +1,144 @@
+use crate::types::SecurityMode;
+use anyhow::{anyhow, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_native_tls::{native_tls, TlsConnector};
+
+/// How long a single network operation may take before it is considered failed.
+pub const TIMEOUT: Duration = Duration::from_secs(15);
+
+pub trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// The usual security of a well-known port: plain-text ports are upgraded
+/// with STARTTLS, everything else is assumed to be implicit TLS.
+pub fn security_for_port(port: u16) -> SecurityMode {
+    match port {
+        25 | 110 | 143 | 587 | 4190 => SecurityMode::StartTls,
+        _ => SecurityMode::Tls,
+    }
+}
+
+/// The command name only, so that credentials never end up in error messages.
+pub fn verb(command: &str) -> &str {
+    command.split_whitespace().next().unwrap_or(command)
+}
+
+pub async fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = timeout(TIMEOUT, tokio::net::lookup_host((host, port)))
+        .await
+        .map_err(|_| anyhow!("Timed out resolving {}", host))??
+        .collect();
+    if addrs.is_empty() {
+        return Err(anyhow!("{} has no addresses", host));
+    }
+    Ok(addrs)
+}
+
+/// Connects to the first address that accepts the connection.
+pub async fn connect(addrs: &[SocketAddr]) -> Result<TcpStream> {
+    let mut last_error = anyhow!("No addresses to connect to");
+    for addr in addrs {
+        match timeout(TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => last_error = anyhow!("{}: {}", addr, e),
+            Err(_) => last_error = anyhow!("{}: connection timed out", addr),
+        }
+    }
+    Err(last_error)
+}
+
+/// A line-based connection to an IMAP, POP3, SMTP or ManageSieve server, either plain or
+/// wrapped in TLS. Every line received is kept until `take_responses` so
+/// callers can report what the server said.
+pub struct Connection {
+    stream: BufReader<Box<dyn Io>>,
+    host: String,
+    responses: Vec<String>,
+}
+
+impl Connection {
+    pub fn plain(stream: TcpStream, host: &str) -> Self {
+        Self::new(Box::new(stream), host)
+    }
+
+    pub async fn tls(stream: TcpStream, host: &str) -> Result<Self> {
+        let stream = handshake(Box::new(stream), host).await?;
+        Ok(Self::new(stream, host))
+    }
+
+    fn new(stream: Box<dyn Io>, host: &str) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            host: host.to_string(),
+            responses: Vec::new(),
+        }
+    }
+
+    /// Upgrades the connection after the server accepted STARTTLS/STLS.
+    pub async fn start_tls(self) -> Result<Self> {
+        if !self.stream.buffer().is_empty() {
+            return Err(anyhow!("Server sent data before the TLS handshake"));
+        }
+        let stream = handshake(self.stream.into_inner(), &self.host).await?;
+        Ok(Self {
+            responses: self.responses,
+            ..Self::new(stream, &self.host)
+        })
+    }
+
+    /// Reads one line without its line ending.
+    pub async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        let n = timeout(TIMEOUT, self.stream.read_line(&mut line))
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for {}", self.host))??;
+        if n == 0 {
+            return Err(anyhow!("{} closed the connection", self.host));
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        self.responses.push(line.clone());
+        Ok(line)
+    }
+
+    /// Reads exactly `len` bytes, such as the contents of a literal.
+    pub async fn read_exact(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        timeout(TIMEOUT, self.stream.read_exact(&mut data))
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for {}", self.host))??;
+        Ok(data)
+    }
+
+    pub async fn write_line(&mut self, line: &str) -> Result<()> {
+        self.write_all(format!("{}\r\n", line).as_bytes()).await
+    }
+
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let stream = self.stream.get_mut();
+        timeout(TIMEOUT, async {
+            stream.write_all(data).await?;
+            stream.flush().await
+        })
+        .await
+        .map_err(|_| anyhow!("Timed out writing to {}", self.host))??;
+        Ok(())
+    }
+
+    /// Lines received since the last call.
+    pub fn take_responses(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.responses)
+    }
+}
+
+async fn handshake(stream: Box<dyn Io>, host: &str) -> Result<Box<dyn Io>> {
+    let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+    let stream = timeout(TIMEOUT, connector.connect(host, stream))
+        .await
+        .map_err(|_| anyhow!("Timed out negotiating TLS with {}", host))??;
+    Ok(Box::new(stream))
+}
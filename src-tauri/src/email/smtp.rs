@@ -0,0 +1,200 @@
+use super::net::{self, Connection};
+use super::Credentials;
+use crate::types::{SecurityMode, ServerConfig};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+const EHLO: &str = "EHLO localhost";
+
+/// Opens an SMTP session up to and including EHLO, upgrading with STARTTLS
+/// when the server is configured for it.
+pub async fn connect(server: &ServerConfig) -> Result<Connection> {
+    let host = server
+        .host
+        .as_deref()
+        .ok_or_else(|| anyhow!("No SMTP server configured"))?;
+    let port = server.port.unwrap_or(match server.security {
+        SecurityMode::Tls => 465,
+        SecurityMode::StartTls | SecurityMode::None => 587,
+    });
+
+    let stream = net::connect(&net::resolve(host, port).await?).await?;
+    let mut conn = match server.security {
+        SecurityMode::Tls => Connection::tls(stream, host).await?,
+        SecurityMode::StartTls | SecurityMode::None => Connection::plain(stream, host),
+    };
+    reply(&mut conn, 220).await?;
+    command(&mut conn, EHLO, 250).await?;
+
+    if server.security == SecurityMode::StartTls {
+        command(&mut conn, "STARTTLS", 220).await?;
+        conn = conn.start_tls().await?;
+        command(&mut conn, EHLO, 250).await?;
+    }
+    Ok(conn)
+}
+
+pub async fn authenticate(conn: &mut Connection, credentials: &Credentials) -> Result<()> {
+    match credentials {
+        Credentials::Password { username, password } => {
+            let plain = BASE64.encode(format!("\0{}\0{}", username, password));
+            command(conn, &format!("AUTH PLAIN {}", plain), 235).await?;
+        }
+        Credentials::OAuth2 { .. } => {
+            let xoauth2 = credentials.xoauth2().unwrap_or_default();
+            command(conn, &format!("AUTH XOAUTH2 {}", xoauth2), 235).await?;
+        }
+        Credentials::None => {}
+    }
+    Ok(())
+}
+
+/// Sends one message. `message` is a complete RFC 822 message; Bcc
+/// recipients only appear in `recipients`.
+pub async fn send(
+    server: &ServerConfig,
+    credentials: &Credentials,
+    from: &str,
+    recipients: &[String],
+    message: &[u8],
+) -> Result<()> {
+    if recipients.is_empty() {
+        return Err(anyhow!("No recipients"));
+    }
+
+    let mut conn = connect(server).await?;
+    authenticate(&mut conn, credentials).await?;
+    command(&mut conn, &format!("MAIL FROM:<{}>", from), 250).await?;
+    for recipient in recipients {
+        command(&mut conn, &format!("RCPT TO:<{}>", recipient), 250).await?;
+    }
+    command(&mut conn, "DATA", 354).await?;
+
+    let mut data = Vec::with_capacity(message.len() + 64);
+    for line in message.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        // Dot-stuffing keeps a leading "." from ending the data early
+        if line.starts_with(b".") {
+            data.push(b'.');
+        }
+        data.extend_from_slice(line);
+        data.extend_from_slice(b"\r\n");
+    }
+    if message.ends_with(b"\n") {
+        data.truncate(data.len() - 2);
+    }
+    data.extend_from_slice(b".\r\n");
+    conn.write_all(&data).await?;
+    reply(&mut conn, 250).await?;
+
+    let _ = conn.write_line("QUIT").await;
+    Ok(())
+}
+
+pub async fn command(conn: &mut Connection, command: &str, expected: u16) -> Result<Vec<String>> {
+    conn.write_line(command).await?;
+    reply(conn, expected)
+        .await
+        .map_err(|e| anyhow!("Server rejected {}: {}", net::verb(command), e))
+}
+
+/// Reads a possibly multi-line reply and checks its code. Only the class
+/// (first digit) must match, as RFC 5321 asks clients to do: `251 User not
+/// local; will forward` succeeds like `250`.
+pub async fn reply(conn: &mut Connection, expected: u16) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    loop {
+        let line = conn.read_line().await?;
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line);
+        if last {
+            break;
+        }
+    }
+    let reply = lines.last().cloned().unwrap_or_default();
+    match reply.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+        Some(code) if code / 100 == expected / 100 => Ok(lines),
+        _ => Err(anyhow!("{}", reply)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A plain-text SMTP server that answers each command with
+    /// `answer(command)` and returns the message data it received.
+    async fn server(answer: fn(&str) -> &'static str) -> (ServerConfig, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 test ESMTP\r\n").await.unwrap();
+            let mut data = String::new();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        write.write_all(b"250 Queued\r\n").await.unwrap();
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply = answer(&line);
+                in_data = reply.starts_with("354");
+                write.write_all(format!("{}\r\n", reply).as_bytes()).await.unwrap();
+                if line == "QUIT" {
+                    break;
+                }
+            }
+            data
+        });
+        let server = ServerConfig {
+            host: Some("127.0.0.1".to_string()),
+            port: Some(port),
+            security: SecurityMode::None,
+            ..Default::default()
+        };
+        (server, task)
+    }
+
+    fn answer(command: &str) -> &'static str {
+        match net::verb(command) {
+            "EHLO" => "250-test\r\n250 8BITMIME",
+            "RCPT" if command.contains("far@") => "251 User not local; will forward",
+            "RCPT" if command.contains("nobody@") => "550 No such user",
+            "DATA" => "354 Go ahead",
+            "QUIT" => "221 Bye",
+            _ => "250 OK",
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_any_positive_recipient_reply() {
+        let (server, task) = server(answer).await;
+        let recipients = ["near@example.com".to_string(), "far@example.org".to_string()];
+        send(&server, &Credentials::None, "me@example.com", &recipients, b"Subject: Hi\r\n\r\n.hidden\r\n")
+            .await
+            .unwrap();
+        // The leading dot arrives stuffed
+        assert_eq!(task.await.unwrap(), "Subject: Hi\n\n..hidden\n");
+    }
+
+    #[tokio::test]
+    async fn fails_on_rejected_recipients() {
+        let (server, _task) = server(answer).await;
+        let recipients = ["nobody@example.com".to_string()];
+        let error = send(&server, &Credentials::None, "me@example.com", &recipients, b"Subject: Hi\r\n\r\nHi\r\n")
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Server rejected RCPT: 550 No such user");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::types::{Draft, Email, EmailAccount, Identity, Signature, Template};
+
+/// Separator line between a message and its signature (RFC 3676).
+pub const SIGNATURE_SEPARATOR: &str = "\n-- \n";
+
+/// Replaces `{{placeholder}}`s with values from the message being replied
+/// to. Unknown placeholders are left untouched.
+pub fn render(text: &str, original: Option<&Email>) -> String {
+    substitute(text, original, |value| value)
+}
+
+/// Like `render`, but escapes the substituted values for use in HTML.
+pub fn render_html(html: &str, original: Option<&Email>) -> String {
+    substitute(html, original, |value| escape_html(&value))
+}
+
+fn substitute(text: &str, original: Option<&Email>, encode: impl Fn(String) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        match placeholder(after[..end].trim(), original) {
+            Some(value) => out.push_str(&encode(value)),
+            None => out.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn placeholder(name: &str, original: Option<&Email>) -> Option<String> {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    Some(match name {
+        "sender.name" => original
+            .map(|o| match &o.from.name {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => o.from.address.clone(),
+            })
+            .unwrap_or_default(),
+        "sender.email" => original.map(|o| o.from.address.clone()).unwrap_or_default(),
+        "subject" => original.map(|o| o.subject.clone()).unwrap_or_default(),
+        "date" => original
+            .and_then(|o| chrono::DateTime::parse_from_rfc2822(&o.date).ok())
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or(today),
+        "today" => today,
+        _ => return None,
+    })
+}
+
+/// The signature of the identity a message is sent from, or of the
+/// account's own address when no identity is used.
+pub fn signature_for<'a>(
+    signatures: &'a [Signature],
+    account: &EmailAccount,
+    identity: Option<&Identity>,
+) -> Option<&'a Signature> {
+    let id = match identity {
+        Some(identity) => identity.signature_id.as_deref()?,
+        None => account.signature_id.as_deref()?,
+    };
+    signatures.iter().find(|s| s.id == id)
+}
+
+/// Starts a new draft, optionally replying to `original` and filled from
+/// `template`, with the signature appended.
+pub fn new_draft(
+    account: &EmailAccount,
+    identity: Option<&Identity>,
+    original: Option<&Email>,
+    template: Option<&Template>,
+    signature: Option<&Signature>,
+) -> Draft {
+    let to = original
+        .map(|o| match o.reply_to.as_ref().and_then(|r| r.first()) {
+            Some(reply_to) => reply_to.address.clone(),
+            None => o.from.address.clone(),
+        })
+        .unwrap_or_default();
+
+    let subject = match (template.and_then(|t| t.subject.as_deref()), original) {
+        (Some(subject), _) => render(subject, original),
+        (None, Some(o)) if o.subject.to_lowercase().starts_with("re:") => o.subject.clone(),
+        (None, Some(o)) => format!("Re: {}", o.subject),
+        (None, None) => String::new(),
+    };
+
+    let text = template.map(|t| render(&t.body, original)).unwrap_or_default();
+    let html = template
+        .and_then(|t| t.html_body.as_deref())
+        .map(|html| render_html(html, original));
+    let (body, html_body) = sign(&text, html, signature);
+
+    Draft {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id: account.id.clone(),
+        identity_id: identity.map(|i| i.id.clone()),
+        to,
+        subject,
+        body,
+        html_body,
+        in_reply_to: original.map(|o| o.id.clone()),
+        updated_at: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+/// Starts a reply to `original` with the given text, e.g. written by the
+/// AI, as plain text and HTML, with the signature appended.
+pub fn reply_draft(
+    account: &EmailAccount,
+    identity: Option<&Identity>,
+    original: &Email,
+    text: &str,
+    signature: Option<&Signature>,
+) -> Draft {
+    let (body, html_body) = sign(text, Some(text_to_html(text)), signature);
+    Draft {
+        body,
+        html_body,
+        ..new_draft(account, identity, Some(original), None, signature)
+    }
+}
+
+/// Appends the plain text of the signature, if it has any, after the
+/// separator.
+pub fn append_signature(text: &str, signature: Option<&Signature>) -> String {
+    match signature.map(|s| s.text.as_str()).filter(|s| !s.is_empty()) {
+        Some(signature) => format!("{}\n{}{}", text, SIGNATURE_SEPARATOR, signature),
+        None => text.to_string(),
+    }
+}
+
+/// Appends the signature to the plain text and, if there is one, the HTML
+/// body. A HTML signature alone also makes a HTML body.
+fn sign(text: &str, html: Option<String>, signature: Option<&Signature>) -> (String, Option<String>) {
+    let body = append_signature(text, signature);
+    let signature_text = signature.map(|s| s.text.as_str()).filter(|s| !s.is_empty());
+
+    let signature_html = signature.and_then(|s| s.html.as_deref());
+    let html_body = (html.is_some() || signature_html.is_some()).then(|| {
+        let mut html = html.unwrap_or_else(|| text_to_html(text));
+        if let Some(signature) = signature_html
+            .map(|s| s.to_string())
+            .or_else(|| signature_text.map(text_to_html))
+        {
+            html.push_str("<br><br>-- <br>");
+            html.push_str(&signature);
+        }
+        html
+    });
+    (body, html_body)
+}
+
+fn text_to_html(text: &str) -> String {
+    escape_html(text).replace('\n', "<br>")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::mime;
+    use crate::types::{AccountConfig, Protocol, ServerConfig};
+
+    fn account() -> EmailAccount {
+        EmailAccount {
+            id: "acc".to_string(),
+            name: "Work".to_string(),
+            email: "me@example.com".to_string(),
+            display_name: None,
+            tags: None,
+            protocol: Protocol::Local,
+            provider: None,
+            config: AccountConfig {
+                incoming: ServerConfig::default(),
+                outgoing: None,
+                sieve: None,
+                oauth_token: None,
+                refresh_token: None,
+                path: None,
+            },
+            identities: None,
+            signature_id: None,
+        }
+    }
+
+    fn original(subject: &str) -> Email {
+        let raw = format!(
+            "From: Ann <ann@example.com>\r\nDate: Tue, 7 May 2024 10:00:00 +0000\r\nSubject: {}\r\n\r\nHi\r\n",
+            subject
+        );
+        mime::parse_email(raw.as_bytes(), "acc").unwrap()
+    }
+
+    fn signature(html: Option<&str>) -> Signature {
+        Signature {
+            id: "s1".to_string(),
+            name: "Work".to_string(),
+            text: "Bob\nExample Inc.".to_string(),
+            html: html.map(|h| h.to_string()),
+        }
+    }
+
+    #[test]
+    fn substitutes_known_placeholders_only() {
+        let original = original("Lunch");
+        assert_eq!(
+            render("Hi {{ sender.name }} <{{sender.email}}>, re {{subject}} of {{date}}", Some(&original)),
+            "Hi Ann <ann@example.com>, re Lunch of 2024-05-07"
+        );
+        assert_eq!(render("{{unknown}} and {{subject}}", Some(&original)), "{{unknown}} and Lunch");
+        assert_eq!(render("{{subject}} {{unterminated", Some(&original)), "Lunch {{unterminated");
+        assert_eq!(render("Dear {{sender.name}}", None), "Dear ");
+    }
+
+    #[test]
+    fn escapes_substituted_values_in_html() {
+        let original = original("<b>Q&A</b> \"now\"");
+        assert_eq!(
+            render_html("<p>{{subject}}</p>", Some(&original)),
+            "<p>&lt;b&gt;Q&amp;A&lt;/b&gt; &quot;now&quot;</p>"
+        );
+    }
+
+    #[test]
+    fn signs_after_the_separator() {
+        let (text, html) = sign("Thanks", None, Some(&signature(None)));
+        assert_eq!(text, "Thanks\n\n-- \nBob\nExample Inc.");
+        assert_eq!(html, None);
+
+        let (_, html) = sign("Thanks", Some("<p>Thanks</p>".to_string()), Some(&signature(None)));
+        assert_eq!(html.as_deref(), Some("<p>Thanks</p><br><br>-- <br>Bob<br>Example Inc."));
+
+        let (text, html) = sign("A & B", None, Some(&signature(Some("<b>Bob</b>"))));
+        assert!(text.ends_with("\n-- \nBob\nExample Inc."));
+        assert_eq!(html.as_deref(), Some("A &amp; B<br><br>-- <br><b>Bob</b>"));
+
+        assert_eq!(sign("Thanks", None, None), ("Thanks".to_string(), None));
+    }
+
+    #[test]
+    fn replies_do_not_double_the_prefix() {
+        let account = account();
+        let draft = new_draft(&account, None, Some(&original("RE: Lunch")), None, None);
+        assert_eq!(draft.subject, "RE: Lunch");
+        assert_eq!(draft.to, "ann@example.com");
+        let draft = new_draft(&account, None, Some(&original("Lunch")), None, None);
+        assert_eq!(draft.subject, "Re: Lunch");
+    }
+}
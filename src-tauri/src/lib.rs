@@ -2,14 +2,45 @@ mod types;
 mod storage;
 mod email;
 mod ai;
+mod categories;
+mod quarantine;
+mod rules;
+mod sieve;
+mod snooze;
+mod trash;
 
-use std::sync::Arc;
-use tauri::{AppHandle, Manager, State};
+use chrono::Timelike;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_notification::NotificationExt;
 use types::*;
 use storage::Store;
 
+/// How often snoozed emails and follow-up reminders are checked.
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Emails processed between two `bulk-progress` events.
+const BULK_PROGRESS_STEP: usize = 50;
+
+/// Local hour from which the daily AI digest is made when none is configured.
+const DEFAULT_DIGEST_HOUR: u32 = 8;
+
+/// Most emails summarized for one daily AI digest.
+const DIGEST_LIMIT: usize = 20;
+
+/// Digest entries listed in the OS notification; the rest are only counted.
+const DIGEST_NOTIFICATION_LINES: usize = 3;
+
+/// How often expired quarantined and trashed emails are looked for.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 struct AppState {
     store: Arc<Store>,
+    watchers: Mutex<HashMap<String, AccountWatcher>>,
 }
 
 #[tauri::command]
@@ -26,29 +57,68 @@ async fn get_accounts(state: State<'_, AppState>) -> Result<Vec<EmailAccount>, S
 async fn add_account(
     account: EmailAccount,
     state: State<'_, AppState>,
+    app: AppHandle,
 ) -> Result<(), String> {
     let mut new_account = account;
     if new_account.id.is_empty() {
         new_account.id = uuid::Uuid::new_v4().to_string();
     }
+    watch_account(&app, &new_account);
     state.store.add_account(new_account).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn discover_account(email: String) -> Result<Option<AccountSuggestion>, String> {
+    email::autoconfig::Discovery::system()
+        .discover(&email)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn update_account(
     id: String,
     account: EmailAccount,
     state: State<'_, AppState>,
+    app: AppHandle,
 ) -> Result<(), String> {
+    watch_account(&app, &account);
     state.store.update_account(&id, account).map_err(|e| e.to_string())
 }
 
+/// Moves an account to the deleted accounts. Its emails are kept until the
+/// account is purged, and `undo` or `restore_account` bring it back.
 #[tauri::command]
 async fn delete_account(
     id: String,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    state.store.delete_account(&id).map_err(|e| e.to_string())
+    state.watchers.lock().unwrap().remove(&id);
+    let account = find_account(&state.store, &id)?;
+    state.store.delete_account(&id).map_err(|e| e.to_string())?;
+    if let Some(account) = account {
+        state.store.push_undo(format!("Delete account {}", account.name), Vec::new(), Some(id));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn test_account(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<HealthReport, String> {
+    let account = find_account(&state.store, &id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let report = email::health::check_account(&account).await;
+    state.store.record_health_check(report.clone()).map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn get_account_health(
+    state: State<'_, AppState>,
+) -> Result<HashMap<String, AccountHealth>, String> {
+    state.store.get_health().map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -65,99 +135,2172 @@ async fn sync_emails(
     let settings = state.store.get_settings().map_err(|e| e.to_string())?;
     
     for account in accounts {
-        match email::EmailClient::fetch_emails(&account).await {
-            Ok(mut emails) => {
-                // Classify emails with AI if enabled
-                if let Some(ai_config) = &settings.ai_config {
-                    if ai_config.enabled {
-                        let classifier = ai::AIClassifier::new(ai_config.clone());
-                        for email_item in &mut emails {
-                            if let Ok(classification) = classifier.classify_email(email_item).await {
-                                email_item.ai_classification = Some(classification.clone());
-                                
-                                // Auto-delete marketing emails if configured
-                                if ai_config.auto_delete && matches!(classification.category, Category::Marketing) {
-                                    continue; // Skip adding this email
-                                }
-                                
-                                // Send notification for important emails
-                                if classification.should_notify && settings.notifications {
-                                    // Note: Notifications would be sent here using tauri-plugin-notification
-                                    // For now, just log it
-                                    println!("Notification: {} - From: {}", &email_item.subject, &email_item.from.address);
-                                }
-                            }
-                        }
+        let result = sync_account(&app, &state.store, &settings, &account).await;
+        record_sync(&app, &state.store, &account.id, result);
+    }
+    
+    Ok(())
+}
+
+/// Keeps the outcome of a sync in the account's health. Failing to save it
+/// must not stop other accounts from syncing.
+fn record_sync(app: &AppHandle, store: &Store, account_id: &str, result: anyhow::Result<()>) {
+    if let Err(e) = store.record_sync_result(account_id, result.err().map(|e| e.to_string())) {
+        report_error(app, Some(account_id), format!("Failed to record the sync: {}", e));
+    }
+}
+
+/// Reports a failure of background work, which has no caller to return it
+/// to: it is kept for `get_background_errors` and, unless it is a repeat,
+/// sent to the app as a `background-error` event.
+fn report_error(app: &AppHandle, account_id: Option<&str>, message: String) {
+    let error = BackgroundError {
+        account_id: account_id.map(|id| id.to_string()),
+        message,
+        occurred_at: chrono::Utc::now().to_rfc3339(),
+    };
+    if app.state::<AppState>().store.add_background_error(error.clone()) {
+        let _ = app.emit("background-error", &error);
+    }
+}
+
+async fn sync_account(
+    app: &AppHandle,
+    store: &Store,
+    settings: &AppSettings,
+    account: &EmailAccount,
+) -> anyhow::Result<()> {
+    let known = store.remote_ids(&account.id)?;
+    let sync_state = store.get_sync_state(&account.id)?;
+    let fetched = email::EmailClient::fetch_emails(account, &known, sync_state.as_deref()).await?;
+    store.apply_remote_changes(&account.id, &fetched.flag_updates, &fetched.removed)?;
+
+    let mut emails = fetched.emails;
+    // Classify emails with AI if enabled
+    if let Some(ai_config) = &settings.ai_config {
+        // Classification pauses once the monthly budget is spent
+        let budget_left = ai::usage::budget_left(&store.get_ai_usage()?, ai_config);
+        let paused = budget_left.is_some_and(|left| left <= 0.0);
+        if ai_config.enabled && paused {
+            report_error(app, None, "AI classification is paused: the monthly budget is used up".to_string());
+        }
+        if ai_config.enabled && !paused {
+            let categories = categories::all(store.get_categories()?);
+            let classifier = Arc::new(
+                ai::AIClassifier::new(ai_config.clone())
+                    .with_categories(categories.clone())
+                    .with_local_model(store.get_local_model()?)
+                    .with_budget_left(budget_left),
+            );
+            let corrections = store.get_corrections()?;
+
+            // Emails already classified, e.g. carried over by the backend, are
+            // kept; the cache answers for messages seen before
+            let mut classifications = Vec::new();
+            let mut uncached = Vec::new();
+            for (i, email_item) in emails.iter().enumerate() {
+                if email_item.ai_classification.is_some() {
+                    continue;
+                }
+                let key = classifier.cache_key(email_item);
+                match store.cached_classification(&key) {
+                    Some(classification) => classifications.push((i, classification)),
+                    None => uncached.push((i, key)),
+                }
+            }
+            let batch: Vec<Email> = uncached.iter().map(|(i, _)| emails[*i].clone()).collect();
+            let results = classifier.classify_many(&batch, |_| {}).await;
+            store.record_ai_usage(classifier.take_usage())?;
+            let mut new_cache_entries = Vec::new();
+            let mut errors = Vec::new();
+            for ((i, key), result) in uncached.into_iter().zip(results) {
+                match result {
+                    Ok(classification) => {
+                        new_cache_entries.push((key, classification.clone()));
+                        classifications.push((i, classification));
                     }
+                    // Left unclassified rather than guessed
+                    Err(e) => errors.push(e),
+                }
+            }
+            report_classification_errors(app, &account.id, &errors);
+
+            for (i, mut classification) in classifications {
+                let email_item = &mut emails[i];
+                // The user's corrections for a sender win over the AI
+                if let Some(category) = quarantine::corrected_category(&corrections, email_item) {
+                    quarantine::set_category(&mut classification, category, &categories);
+                }
+                email_item.ai_classification = Some(classification.clone());
+                
+                // Quarantine marketing emails if configured; they are only
+                // deleted, also on the server, once their retention has passed
+                if ai_config.auto_delete
+                    && matches!(classification.category, Category::Marketing)
+                    && ai::is_confident(&classification, ai_config)
+                {
+                    quarantine::quarantine(email_item);
+                    continue;
                 }
                 
-                state.store.add_emails(emails).map_err(|e| e.to_string())?;
+                // Send notification for important emails
+                if classification.should_notify && settings.notifications {
+                    let title = match &classification.verification_code {
+                        Some(code) => format!("Verification code {}", code),
+                        None => sender_name(&email_item.from).to_string(),
+                    };
+                    notify(app, &title, &email_item.subject);
+                }
             }
-            Err(e) => eprintln!("Failed to fetch emails for account {}: {}", account.id, e),
+            store.cache_classifications(new_cache_entries)?;
         }
     }
     
+    // Saved first, so header rules and Sieve see the original headers
+    for (id, raw) in &fetched.sources {
+        store.save_raw(id, raw)?;
+    }
+    let rule_set = load_rules(store)?;
+    if !rule_set.is_empty() {
+        for email_item in emails.iter_mut().filter(|e| e.quarantine.is_none()) {
+            apply_rules(app, store, &rule_set, account, email_item).await?;
+        }
+    }
+
+    store.add_emails(emails)?;
+    if fetched.sync_state.is_some() {
+        store.set_sync_state(&account.id, fetched.sync_state)?;
+    }
+    Ok(())
+}
+
+/// Reports emails the AI failed to classify, once for all of them since
+/// they usually fail for the same reason.
+fn report_classification_errors(app: &AppHandle, account_id: &str, errors: &[anyhow::Error]) {
+    if let Some(first) = errors.first() {
+        let message = format!("Failed to classify {} emails: {}", errors.len(), first);
+        report_error(app, Some(account_id), message);
+    }
+}
+
+/// The categories' actions, followed by the user's rules.
+fn load_rules(store: &Store) -> anyhow::Result<rules::RuleSet> {
+    let mut all_rules = categories::rules(&categories::all(store.get_categories()?));
+    all_rules.extend(store.get_rules()?);
+    rules::RuleSet::new(&all_rules)
+}
+
+/// Applies the matching rules to an email, forwarding and notifying as
+/// needed. Returns `None` when no rule matched.
+async fn apply_rules(
+    app: &AppHandle,
+    store: &Store,
+    rule_set: &rules::RuleSet,
+    account: &EmailAccount,
+    email_item: &mut Email,
+) -> anyhow::Result<Option<rules::Effects>> {
+    let raw = if rule_set.needs_headers() {
+        email::raw_source(store, email_item).ok()
+    } else {
+        None
+    };
+    let matched = rule_set.evaluate(email_item, raw.as_deref());
+    if matched.is_empty() {
+        return Ok(None);
+    }
+
+    let effects = rules::apply(email_item, &matched);
+    // Deleted emails go to the trash and only leave the server once purged
+    if effects.delete {
+        trash::trash(email_item);
+    }
+    if let Err(e) = email::write_back_flags(account, std::slice::from_ref(email_item)).await {
+        let message = format!("Failed to update flags of \"{}\" on the server: {}", email_item.subject, e);
+        report_error(app, Some(&account.id), message);
+    }
+    if !effects.forward_to.is_empty() {
+        // Never forwarded without the attachments
+        match email::load_attachments(store, email_item) {
+            Ok(attachments) => {
+                for to in &effects.forward_to {
+                    let forward = email::forward(account, email_item, attachments.clone(), to);
+                    if let Err(e) = email::send_email(account, None, &forward).await {
+                        let message = format!("Failed to forward \"{}\" to {}: {}", email_item.subject, to, e);
+                        report_error(app, Some(&account.id), message);
+                    }
+                }
+            }
+            Err(e) => {
+                let message = format!("Failed to forward \"{}\": {}", email_item.subject, e);
+                report_error(app, Some(&account.id), message);
+            }
+        }
+    }
+    for notification in &effects.notifications {
+        let _ = app.emit("rule-notification", notification);
+    }
+    Ok(Some(effects))
+}
+
+#[tauri::command]
+async fn set_email_flags(
+    id: String,
+    is_read: Option<bool>,
+    is_starred: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    email.is_read = is_read.unwrap_or(email.is_read);
+    email.is_starred = is_starred.unwrap_or(email.is_starred);
+
+    if let Some(account) = find_account(&state.store, &email.account_id)? {
+        email::write_back_flags(&account, std::slice::from_ref(&email))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    state.store.update_email(&id, email).map_err(|e| e.to_string())
+}
+
+/// Moves an email to the trash, or deletes it for good (also on the
+/// server) when it already is in the trash.
+#[tauri::command]
+async fn delete_email(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+
+    if email.trashed.is_some() {
+        if let Some(account) = find_account(&state.store, &email.account_id)? {
+            email::expunge(&account, std::slice::from_ref(&email))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        return state.store.delete_emails(&[id]).map_err(|e| e.to_string());
+    }
+
+    let previous = email.clone();
+    trash::trash(&mut email);
+    state.store.update_email(&id, email).map_err(|e| e.to_string())?;
+    state.store.push_undo(format!("Delete \"{}\"", previous.subject), vec![previous], None);
     Ok(())
 }
 
+/// Moves emails to a folder. Moving to `Trash` deletes them; moving out of
+/// the trash, quarantine or snoozed folder restores them.
 #[tauri::command]
-async fn send_email(
-    from_account_id: String,
-    to: String,
-    subject: String,
-    body: String,
+async fn move_emails(
+    ids: Vec<String>,
+    folder: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let previous = state.store.get_emails_by_id(&ids).map_err(|e| e.to_string())?;
+    let moved = previous
+        .iter()
+        .cloned()
+        .map(|mut email_item| {
+            move_to_folder(&mut email_item, &folder);
+            email_item
+        })
+        .collect();
+    state.store.update_emails(moved).map_err(|e| e.to_string())?;
+    state.store.push_undo(
+        format!("Move {} emails to {}", previous.len(), folder),
+        previous,
+        None,
+    );
+    Ok(())
+}
+
+fn move_to_folder(email_item: &mut Email, folder: &str) {
+    if folder == storage::TRASH {
+        if email_item.trashed.is_none() {
+            trash::trash(email_item);
+        }
+    } else {
+        email_item.trashed = None;
+        email_item.quarantine = None;
+        email_item.snooze = None;
+        email_item.folder = (folder != storage::INBOX).then(|| folder.to_string());
+    }
+}
+
+/// The configured AI with the user's categories and local model, limited
+/// to what is left of the monthly budget. Fails if AI is turned off or the
+/// budget is used up.
+fn enabled_ai(store: &Store) -> anyhow::Result<ai::AIClassifier> {
+    let ai_config = store.get_settings()?
+        .ai_config
+        .filter(|c| c.enabled)
+        .ok_or_else(|| anyhow::anyhow!("AI is not enabled"))?;
+    let budget_left = ai::usage::budget_left(&store.get_ai_usage()?, &ai_config);
+    if budget_left.is_some_and(|left| left <= 0.0) {
+        return Err(anyhow::anyhow!("The monthly AI budget is used up"));
+    }
+    Ok(ai::AIClassifier::new(ai_config)
+        .with_categories(categories::all(store.get_categories()?))
+        .with_local_model(store.get_local_model()?)
+        .with_budget_left(budget_left))
+}
+
+/// Applies one action to many emails at once: the given ids, or everything
+/// a query matches. Flag changes are written to the server in one batch per
+/// account and all local changes are saved together, so the operation can
+/// be undone as a whole. Emits `bulk-progress` events while it runs.
+#[tauri::command]
+async fn bulk_update(
+    selection: BulkSelection,
+    action: BulkAction,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<BulkResult, String> {
+    let store = &state.store;
+    let emails = match selection {
+        BulkSelection::Ids { ids } => store.get_emails_by_id(&ids),
+        BulkSelection::Query { query } => store.query_emails(&query),
+    }
+    .map_err(|e| e.to_string())?;
+    let categories = categories::all(store.get_categories().map_err(|e| e.to_string())?);
+    let classifier = match action {
+        BulkAction::Reclassify => Some(Arc::new(enabled_ai(store).map_err(|e| e.to_string())?)),
+        _ => None,
+    };
+    let corrections = store.get_corrections().map_err(|e| e.to_string())?;
+    let accounts = store.get_accounts().map_err(|e| e.to_string())?;
+
+    let operation_id = uuid::Uuid::new_v4().to_string();
+    let total = emails.len();
+    let emit_progress = |processed| {
+        let _ = app.emit("bulk-progress", BulkProgress {
+            operation_id: operation_id.clone(),
+            processed,
+            total,
+        });
+    };
+    emit_progress(0);
+
+    let mut by_account: HashMap<String, Vec<Email>> = HashMap::new();
+    for email_item in emails {
+        by_account.entry(email_item.account_id.clone()).or_default().push(email_item);
+    }
+
+    let mut previous = Vec::new();
+    let mut updated = Vec::new();
+    let mut new_cache_entries = Vec::new();
+    let mut failed = 0;
+    let mut processed = 0;
+    for (account_id, emails) in by_account {
+        let mut originals = Vec::with_capacity(emails.len());
+        let mut changed = Vec::with_capacity(emails.len());
+        // Re-classifying bypasses the cache and refreshes it
+        let mut classifications = match &classifier {
+            Some(classifier) => {
+                let start = processed;
+                let results = classifier
+                    .classify_many(&emails, |done| {
+                        if (start + done) % BULK_PROGRESS_STEP == 0 {
+                            emit_progress(start + done);
+                        }
+                    })
+                    .await;
+                store.record_ai_usage(classifier.take_usage()).map_err(|e| e.to_string())?;
+                results
+            }
+            None => Vec::new(),
+        }
+        .into_iter();
+        let mut errors = Vec::new();
+        for original in emails {
+            processed += 1;
+            let mut email_item = original.clone();
+            if let Some(classifier) = &classifier {
+                match classifications.next().expect("one classification per email") {
+                    Ok(mut classification) => {
+                        new_cache_entries.push((classifier.cache_key(&email_item), classification.clone()));
+                        if let Some(category) = quarantine::corrected_category(&corrections, &email_item) {
+                            quarantine::set_category(&mut classification, category, &categories);
+                        }
+                        email_item.ai_classification = Some(classification);
+                    }
+                    Err(e) => {
+                        errors.push(e);
+                        failed += 1;
+                        continue;
+                    }
+                }
+            } else {
+                apply_bulk_action(&mut email_item, &action);
+                if processed % BULK_PROGRESS_STEP == 0 {
+                    emit_progress(processed);
+                }
+            }
+            originals.push(original);
+            changed.push(email_item);
+        }
+        report_classification_errors(&app, &account_id, &errors);
+
+        if matches!(action, BulkAction::Read { .. } | BulkAction::Star { .. }) {
+            if let Some(account) = accounts.iter().find(|a| a.id == account_id) {
+                if let Err(e) = email::write_back_flags(account, &changed).await {
+                    report_error(&app, Some(&account_id), format!("Failed to update flags on the server: {}", e));
+                    failed += changed.len();
+                    emit_progress(processed);
+                    continue;
+                }
+            }
+        }
+        previous.extend(originals);
+        updated.extend(changed);
+        emit_progress(processed);
+    }
+
+    let updated_count = updated.len();
+    store.update_emails(updated).map_err(|e| e.to_string())?;
+    store.cache_classifications(new_cache_entries).map_err(|e| e.to_string())?;
+    if !previous.is_empty() {
+        store.push_undo(describe_bulk_action(&action, previous.len()), previous, None);
+    }
+    Ok(BulkResult {
+        operation_id,
+        matched: total,
+        updated: updated_count,
+        failed,
+    })
+}
+
+fn apply_bulk_action(email_item: &mut Email, action: &BulkAction) {
+    match action {
+        BulkAction::Read { value } => email_item.is_read = *value,
+        BulkAction::Star { value } => email_item.is_starred = *value,
+        BulkAction::Label { label, value } => {
+            let mut labels = email_item.labels.take().unwrap_or_default();
+            labels.retain(|l| l != label);
+            if *value {
+                labels.push(label.clone());
+            }
+            email_item.labels = (!labels.is_empty()).then_some(labels);
+        }
+        BulkAction::Move { folder } => move_to_folder(email_item, folder),
+        BulkAction::Delete => move_to_folder(email_item, storage::TRASH),
+        // Needs the AI, see bulk_update
+        BulkAction::Reclassify => {}
+    }
+}
+
+fn describe_bulk_action(action: &BulkAction, count: usize) -> String {
+    match action {
+        BulkAction::Read { value: true } => format!("Mark {} emails read", count),
+        BulkAction::Read { value: false } => format!("Mark {} emails unread", count),
+        BulkAction::Star { value: true } => format!("Star {} emails", count),
+        BulkAction::Star { value: false } => format!("Unstar {} emails", count),
+        BulkAction::Label { label, value: true } => format!("Label {} emails {}", count, label),
+        BulkAction::Label { label, value: false } => format!("Remove label {} from {} emails", label, count),
+        BulkAction::Move { folder } => format!("Move {} emails to {}", count, folder),
+        BulkAction::Delete => format!("Delete {} emails", count),
+        BulkAction::Reclassify => format!("Reclassify {} emails", count),
+    }
+}
+
+/// Hides an email until `until` (RFC 3339), when it returns to the top of
+/// its folder as unread and a `reminder` event is emitted.
+#[tauri::command]
+async fn snooze_email(
+    id: String,
+    until: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let until = parse_future_time(&until)?;
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    snooze::snooze(&mut email, until);
+    state.store.update_email(&id, email).map_err(|e| e.to_string())
+}
+
+/// Brings a snoozed email back right away, leaving its read state alone.
+#[tauri::command]
+async fn unsnooze_email(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    snooze::unsnooze(&mut email);
+    state.store.update_email(&id, email).map_err(|e| e.to_string())
+}
+
+/// Reminds about a sent message, e.g. one synced from the Sent folder, if
+/// no reply to it has arrived by `remind_at` (RFC 3339).
+#[tauri::command]
+async fn add_follow_up_reminder(
+    email_id: String,
+    remind_at: String,
+    state: State<'_, AppState>,
+) -> Result<FollowUpReminder, String> {
+    let remind_at = parse_future_time(&remind_at)?;
+    let email = state.store.get_email(&email_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    let message_id = email.message_id
+        .clone()
+        .ok_or_else(|| "Email has no Message-ID to look for replies to".to_string())?;
+    let reminder = FollowUpReminder {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id: email.account_id.clone(),
+        message_id,
+        email_id: Some(email.id.clone()),
+        subject: email.subject.clone(),
+        to: email.to.clone(),
+        remind_at: remind_at.to_rfc3339(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    state.store.add_reminder(reminder.clone()).map_err(|e| e.to_string())?;
+    Ok(reminder)
+}
+
+#[tauri::command]
+async fn get_follow_up_reminders(state: State<'_, AppState>) -> Result<Vec<FollowUpReminder>, String> {
+    state.store.get_reminders().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_follow_up_reminder(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.delete_reminders(&[id]).map_err(|e| e.to_string())
+}
+
+fn parse_future_time(value: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    let time = chrono::DateTime::parse_from_rfc3339(value)
+        .map_err(|e| e.to_string())?
+        .with_timezone(&chrono::Utc);
+    if time <= chrono::Utc::now() {
+        return Err("Time must be in the future".to_string());
+    }
+    Ok(time)
+}
+
+/// Takes an email out of the trash, back into the folder it was deleted from.
+#[tauri::command]
+async fn restore_email(
+    id: String,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    trash::restore(&mut email);
+    state.store.update_email(&id, email).map_err(|e| e.to_string())
+}
+
+/// Deletes everything in the trash for good: trashed emails, also on the
+/// server, and deleted accounts with their local emails. Returns how many
+/// emails were deleted.
+#[tauri::command]
+async fn empty_trash(state: State<'_, AppState>, app: AppHandle) -> Result<usize, String> {
+    let store = &state.store;
+    let deleted_accounts: Vec<String> = store.get_deleted_accounts()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|d| d.account.id)
+        .collect();
+    let mut purged = purge_accounts(&app, store, &deleted_accounts).await.map_err(|e| e.to_string())?;
+    let trashed = store.get_emails()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|e| e.trashed.is_some())
+        .collect();
+    purged.extend(purge_emails(&app, store, trashed).await.map_err(|e| e.to_string())?);
+    Ok(purged.len())
+}
+
+#[tauri::command]
+async fn get_deleted_accounts(state: State<'_, AppState>) -> Result<Vec<DeletedAccount>, String> {
+    state.store.get_deleted_accounts().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_account(
+    id: String,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<(), String> {
+    let account = state.store.restore_account(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Deleted account not found".to_string())?;
+    watch_account(&app, &account);
+    Ok(())
+}
+
+/// Reverts the most recent delete, move or bulk operation and returns it,
+/// or `None` when there is nothing left to undo. Emails purged in the
+/// meantime cannot be brought back.
+#[tauri::command]
+async fn undo(
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Option<UndoOperation>, String> {
+    let Some(entry) = state.store.pop_undo() else {
+        return Ok(None);
+    };
+    if let Some(account_id) = &entry.account_id {
+        if let Some(account) = state.store.restore_account(account_id).map_err(|e| e.to_string())? {
+            watch_account(&app, &account);
+        }
+    }
+
     let accounts = state.store.get_accounts().map_err(|e| e.to_string())?;
-    let account = accounts.iter()
-        .find(|a| a.id == from_account_id)
-        .ok_or_else(|| "Account not found".to_string())?;
-    
-    email::send_email(account, &to, &subject, &body)
-        .await
-        .map_err(|e| e.to_string())
+    for account in &accounts {
+        let emails: Vec<Email> = entry.emails
+            .iter()
+            .filter(|e| e.account_id == account.id)
+            .cloned()
+            .collect();
+        if emails.is_empty() {
+            continue;
+        }
+        if let Err(e) = email::write_back_flags(account, &emails).await {
+            report_error(&app, Some(&account.id), format!("Failed to restore flags on the server: {}", e));
+        }
+    }
+    state.store.update_emails(entry.emails).map_err(|e| e.to_string())?;
+    Ok(Some(entry.operation))
 }
 
+/// The operations `undo` can revert, most recent first.
 #[tauri::command]
-async fn get_settings(state: State<'_, AppState>) -> Result<AppSettings, String> {
-    state.store.get_settings().map_err(|e| e.to_string())
+async fn get_undo_history(state: State<'_, AppState>) -> Result<Vec<UndoOperation>, String> {
+    Ok(state.store.get_undo_history())
 }
 
+/// Failures of syncing and other background work this session, most
+/// recent first.
 #[tauri::command]
-async fn update_settings(
-    settings: AppSettings,
+async fn get_background_errors(state: State<'_, AppState>) -> Result<Vec<BackgroundError>, String> {
+    Ok(state.store.get_background_errors())
+}
+
+#[tauri::command]
+async fn clear_background_errors(state: State<'_, AppState>) -> Result<(), String> {
+    state.store.clear_background_errors();
+    Ok(())
+}
+
+/// Lists quarantined emails, only those quarantined after `since` if given.
+#[tauri::command]
+async fn get_quarantine_digest(
+    since: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<QuarantineDigest, String> {
+    let since = since
+        .map(|s| chrono::DateTime::parse_from_rfc3339(&s).map(|d| d.with_timezone(&chrono::Utc)))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let settings = state.store.get_settings().map_err(|e| e.to_string())?;
+    let emails = state.store.get_emails().map_err(|e| e.to_string())?;
+    Ok(quarantine::digest(&emails, quarantine::retention_days(&settings), since))
+}
+
+/// Takes an email out of quarantine and remembers its category (normal
+/// unless given) for later mail from the same sender.
+#[tauri::command]
+async fn restore_quarantined_email(
+    id: String,
+    category: Option<Category>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    state.store.update_settings(settings).map_err(|e| e.to_string())
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    if email.quarantine.is_none() {
+        return Err("Email is not quarantined".to_string());
+    }
+    let category = category.unwrap_or(Category::Normal);
+    let categories = categories::all(state.store.get_categories().map_err(|e| e.to_string())?);
+    quarantine::restore(&mut email, category.clone(), &categories);
+    learn_correction(&state.store, &email, category).map_err(|e| e.to_string())?;
+    state.store.update_email(&id, email).map_err(|e| e.to_string())
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .setup(|app| {
-            let app_dir = app.path().app_data_dir()
-                .expect("Failed to get app data directory");
-            
-            let store = Arc::new(
-                Store::new(app_dir).expect("Failed to initialize store")
-            );
-            
-            app.manage(AppState { store });
+/// Gives an email the user's category in place of the AI's, taking it out
+/// of quarantine unless it is marketing. Later mail from the same sender gets it too,
+/// and the local classifier learns from it.
+#[tauri::command]
+async fn set_email_category(
+    id: String,
+    category: Category,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut email = state.store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    let categories = categories::all(state.store.get_categories().map_err(|e| e.to_string())?);
+    if categories::find(&categories, &category).is_none() {
+        return Err("Unknown category".to_string());
+    }
+    let classification = email.ai_classification.get_or_insert(AIClassification {
+        category: Category::Normal,
+        verification_code: None,
+        verification_link: None,
+        should_notify: false,
+        confidence: None,
+        reason: None,
+    });
+    if category == Category::Marketing {
+        quarantine::set_category(classification, category.clone(), &categories);
+    } else {
+        quarantine::restore(&mut email, category.clone(), &categories);
+    }
+    learn_correction(&state.store, &email, category).map_err(|e| e.to_string())?;
+    state.store.update_email(&id, email).map_err(|e| e.to_string())
+}
+
+/// Remembers the category the user gave an email, and teaches it to the
+/// local classifier.
+fn learn_correction(store: &Store, email: &Email, category: Category) -> anyhow::Result<()> {
+    let features = ai::local::features(email);
+    store.update_local_model(|model| ai::local::train(model, &features, &category))?;
+    store.add_correction(CategoryCorrection {
+        email_id: email.id.clone(),
+        sender: email.from.address.clone(),
+        subject: email.subject.clone(),
+        category,
+        created_at: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+/// Trains the local classifier afresh from all corrections, the latest
+/// per email, and reports its accuracy on corrections held out from
+/// training. Fixes what repeated corrections of one email taught it.
+#[tauri::command]
+async fn retrain_local_classifier(state: State<'_, AppState>) -> Result<TrainingReport, String> {
+    let store = &state.store;
+    let corrections = store.get_corrections().map_err(|e| e.to_string())?;
+    let mut latest: Vec<&CategoryCorrection> = Vec::new();
+    for correction in corrections.iter().rev() {
+        if !latest.iter().any(|c| c.email_id == correction.email_id) {
+            latest.push(correction);
+        }
+    }
+    latest.reverse();
+
+    let ids: Vec<String> = latest.iter().map(|c| c.email_id.clone()).collect();
+    let emails = store.get_emails_by_id(&ids).map_err(|e| e.to_string())?;
+    let examples: Vec<(Vec<String>, Category)> = latest
+        .into_iter()
+        .map(|correction| {
+            let features = match emails.iter().find(|e| e.id == correction.email_id) {
+                Some(email_item) => ai::local::features(email_item),
+                // Deleted since; its sender and subject were recorded
+                None => ai::local::correction_features(correction),
+            };
+            (features, correction.category.clone())
+        })
+        .collect();
+
+    let (model, report) = ai::local::retrain(&examples);
+    store.update_local_model(|current| *current = model).map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+/// Summarizes an email with its action items and deadlines. The summary is
+/// kept on the email and returned again unless `refresh` is set.
+#[tauri::command]
+async fn summarize_email(
+    id: String,
+    refresh: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<EmailSummary, String> {
+    let store = &state.store;
+    let mut email = store.get_email(&id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Email not found".to_string())?;
+    if let Some(summary) = email.summary.clone().filter(|_| !refresh.unwrap_or(false)) {
+        return Ok(summary);
+    }
+    let classifier = enabled_ai(store).map_err(|e| e.to_string())?;
+    let result = classifier.summarize(std::slice::from_ref(&email)).await;
+    store.record_ai_usage(classifier.take_usage()).map_err(|e| e.to_string())?;
+    let summary = result.map_err(|e| e.to_string())?;
+    email.summary = Some(summary.clone());
+    store.update_email(&id, email).map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+/// Summarizes the conversation an email belongs to. The summary is kept on
+/// the thread's newest email and made again once the thread has grown, or
+/// when `refresh` is set.
+#[tauri::command]
+async fn summarize_thread(
+    id: String,
+    refresh: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<EmailSummary, String> {
+    let store = &state.store;
+    let emails = store.get_emails().map_err(|e| e.to_string())?;
+    let email = emails.iter()
+        .find(|e| e.id == id)
+        .ok_or_else(|| "Email not found".to_string())?;
+    let thread = ai::summary::thread(&emails, email);
+    let mut newest = thread.last().cloned().unwrap_or_else(|| email.clone());
+    let ids: Vec<String> = thread.iter().map(|e| e.id.clone()).collect();
+    let cached = newest.thread_summary.clone().filter(|s| s.email_ids == ids);
+    if let Some(summary) = cached.filter(|_| !refresh.unwrap_or(false)) {
+        return Ok(summary);
+    }
+    let classifier = enabled_ai(store).map_err(|e| e.to_string())?;
+    let result = classifier.summarize(&thread).await;
+    store.record_ai_usage(classifier.take_usage()).map_err(|e| e.to_string())?;
+    let summary = result.map_err(|e| e.to_string())?;
+    newest.thread_summary = Some(summary.clone());
+    let newest_id = newest.id.clone();
+    store.update_email(&newest_id, newest).map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+/// Once a day from the configured hour, summarizes unread important mail
+/// and delivers the summaries as a notification or as a message in each
+/// account's inbox. Does nothing unless AI and the digest are turned on.
+async fn run_ai_digest(app: &AppHandle, store: &Store) -> anyhow::Result<()> {
+    let Some(ai_config) = store.get_settings()?.ai_config.filter(|c| c.enabled) else {
+        return Ok(());
+    };
+    let Some(delivery) = ai_config.daily_digest.clone() else {
+        return Ok(());
+    };
+    let now = chrono::Local::now();
+    let today = now.format("%Y-%m-%d").to_string();
+    if now.hour() < ai_config.digest_hour.unwrap_or(DEFAULT_DIGEST_HOUR)
+        || store.get_last_digest()?.as_deref() == Some(today.as_str())
+    {
+        return Ok(());
+    }
+    // Marked first, so a failing AI is not asked again every minute
+    store.set_last_digest(&today)?;
+
+    let mut emails: Vec<Email> = store
+        .get_emails()?
+        .into_iter()
+        .filter(|e| {
+            !e.is_read
+                && e.quarantine.is_none()
+                && e.trashed.is_none()
+                && e.snooze.is_none()
+                && e.ai_classification.as_ref().is_some_and(|c| c.category == Category::Important)
+        })
+        .take(DIGEST_LIMIT)
+        .collect();
+    if emails.is_empty() {
+        return Ok(());
+    }
+
+    let classifier = enabled_ai(store)?;
+    let mut summarized = Vec::new();
+    for email_item in emails.iter_mut().filter(|e| e.summary.is_none()) {
+        match classifier.summarize(std::slice::from_ref(email_item)).await {
+            Ok(summary) => {
+                email_item.summary = Some(summary);
+                summarized.push(email_item.clone());
+            }
+            Err(e) => {
+                let message = format!("Failed to summarize \"{}\" for the digest: {}", email_item.subject, e);
+                report_error(app, Some(&email_item.account_id), message);
+            }
+        }
+    }
+    store.record_ai_usage(classifier.take_usage())?;
+    store.update_emails(summarized)?;
+
+    let entries: Vec<DigestEntry> = emails
+        .into_iter()
+        .filter_map(|e| {
+            let summary = e.summary?;
+            Some(DigestEntry {
+                email_id: e.id,
+                account_id: e.account_id,
+                subject: e.subject,
+                from: e.from,
+                summary: summary.summary,
+                action_items: summary.action_items,
+            })
+        })
+        .collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    match delivery {
+        DigestDelivery::Notification => {
+            let title = match entries.len() {
+                1 => "Daily digest: 1 important email".to_string(),
+                n => format!("Daily digest: {} important emails", n),
+            };
+            let mut lines: Vec<String> = entries
+                .iter()
+                .take(DIGEST_NOTIFICATION_LINES)
+                .map(|e| format!("{}: {}", sender_name(&e.from), e.subject))
+                .collect();
+            if entries.len() > DIGEST_NOTIFICATION_LINES {
+                lines.push(format!("and {} more", entries.len() - DIGEST_NOTIFICATION_LINES));
+            }
+            notify(app, &title, &lines.join("\n"));
+            let _ = app.emit("ai-digest", AIDigest {
+                created_at: chrono::Utc::now().to_rfc3339(),
+                entries,
+            });
+        }
+        DigestDelivery::Message => {
+            for account in store.get_accounts()? {
+                let own: Vec<DigestEntry> = entries
+                    .iter()
+                    .filter(|e| e.account_id == account.id)
+                    .cloned()
+                    .collect();
+                if own.is_empty() {
+                    continue;
+                }
+                store.add_emails(vec![ai::summary::digest_message(&account, &own)])?;
+                let _ = app.emit("account-changed", &account.id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Deletes quarantined emails whose retention has passed right away
+/// instead of waiting for the hourly purge. Returns how many were deleted.
+#[tauri::command]
+async fn purge_quarantine(state: State<'_, AppState>, app: AppHandle) -> Result<usize, String> {
+    let settings = state.store.get_settings().map_err(|e| e.to_string())?;
+    let retention = quarantine::retention_days(&settings);
+    let now = chrono::Utc::now();
+    let expired = state.store.get_emails()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|e| quarantine::is_expired(e, retention, now))
+        .collect();
+    purge_emails(&app, &state.store, expired)
+        .await
+        .map(|purged| purged.len())
+        .map_err(|e| e.to_string())
+}
+
+/// Deletes emails for good, expunging them on their account's backend;
+/// emails of deleted accounts are only removed locally. Emails whose server
+/// copy could not be deleted are kept so a later purge retries them.
+/// Returns the deleted emails.
+async fn purge_emails(app: &AppHandle, store: &Store, emails: Vec<Email>) -> anyhow::Result<Vec<Email>> {
+    let accounts = store.get_accounts()?;
+    let mut by_account: HashMap<String, Vec<Email>> = HashMap::new();
+    for email_item in emails {
+        by_account.entry(email_item.account_id.clone()).or_default().push(email_item);
+    }
+
+    let mut purged = Vec::new();
+    for (account_id, emails) in by_account {
+        if let Some(account) = accounts.iter().find(|a| a.id == account_id) {
+            if let Err(e) = email::expunge(account, &emails).await {
+                report_error(app, Some(&account_id), format!("Failed to delete emails on the server: {}", e));
+                continue;
+            }
+        }
+        purged.extend(emails);
+    }
+    let ids: Vec<String> = purged.iter().map(|e| e.id.clone()).collect();
+    store.delete_emails(&ids)?;
+    Ok(purged)
+}
+
+/// Forgets deleted accounts together with their local emails. Returns the
+/// deleted emails.
+async fn purge_accounts(app: &AppHandle, store: &Store, account_ids: &[String]) -> anyhow::Result<Vec<Email>> {
+    let emails = store
+        .get_emails()?
+        .into_iter()
+        .filter(|e| account_ids.contains(&e.account_id))
+        .collect();
+    let purged = purge_emails(app, store, emails).await?;
+    for id in account_ids {
+        store.purge_account(id)?;
+    }
+    Ok(purged)
+}
+
+/// Deletes quarantined emails, trashed emails and deleted accounts whose
+/// retention has passed. Returns the deleted emails.
+async fn purge_expired(app: &AppHandle, store: &Store) -> anyhow::Result<Vec<Email>> {
+    let settings = store.get_settings()?;
+    let quarantine_days = quarantine::retention_days(&settings);
+    let trash_days = trash::retention_days(&settings);
+    let now = chrono::Utc::now();
+
+    let expired_accounts: Vec<String> = store
+        .get_deleted_accounts()?
+        .into_iter()
+        .filter(|d| trash::is_account_expired(d, trash_days, now))
+        .map(|d| d.account.id)
+        .collect();
+    let mut purged = purge_accounts(app, store, &expired_accounts).await?;
+
+    let expired = store
+        .get_emails()?
+        .into_iter()
+        .filter(|e| {
+            quarantine::is_expired(e, quarantine_days, now) || trash::is_expired(e, trash_days, now)
+        })
+        .collect();
+    purged.extend(purge_emails(app, store, expired).await?);
+    Ok(purged)
+}
+
+/// Wakes snoozed emails and fires follow-up reminders that are due.
+/// Returns a notification for each.
+fn run_scheduled(store: &Store) -> anyhow::Result<Vec<ReminderNotification>> {
+    let now = chrono::Utc::now();
+    let emails = store.get_emails()?;
+    let mut notifications = Vec::new();
+
+    let mut woken = Vec::new();
+    for mut email_item in emails.iter().filter(|e| snooze::is_due(e, now)).cloned() {
+        snooze::wake(&mut email_item);
+        notifications.push(ReminderNotification {
+            kind: ReminderKind::Snooze,
+            account_id: email_item.account_id.clone(),
+            email_id: Some(email_item.id.clone()),
+            subject: email_item.subject.clone(),
+        });
+        woken.push(email_item);
+    }
+    store.resurface_emails(woken)?;
+
+    let due: Vec<FollowUpReminder> = store
+        .get_reminders()?
+        .into_iter()
+        .filter(|r| snooze::is_reminder_due(r, now))
+        .collect();
+    for reminder in due.iter().filter(|r| !snooze::has_reply(&emails, r)) {
+        notifications.push(ReminderNotification {
+            kind: ReminderKind::FollowUp,
+            account_id: reminder.account_id.clone(),
+            email_id: reminder.email_id.clone(),
+            subject: reminder.subject.clone(),
+        });
+    }
+    let ids: Vec<String> = due.into_iter().map(|r| r.id).collect();
+    store.delete_reminders(&ids)?;
+    Ok(notifications)
+}
+
+/// Shows an OS notification.
+fn notify(app: &AppHandle, title: &str, body: &str) {
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        report_error(app, None, format!("Failed to show a notification: {}", e));
+    }
+}
+
+fn sender_name(address: &EmailAddress) -> &str {
+    address.name.as_deref().filter(|n| !n.is_empty()).unwrap_or(&address.address)
+}
+
+/// Checks for due snoozes, reminders and the daily AI digest every minute. Both are stored, so
+/// those that came due while the app was closed fire on the first check.
+async fn scheduler_job(app: AppHandle) {
+    loop {
+        let store = app.state::<AppState>().store.clone();
+        match run_scheduled(&store) {
+            Ok(notifications) => {
+                let woken: HashSet<&String> = notifications
+                    .iter()
+                    .filter(|n| matches!(n.kind, ReminderKind::Snooze))
+                    .map(|n| &n.account_id)
+                    .collect();
+                for account_id in woken {
+                    let _ = app.emit("account-changed", account_id);
+                }
+                let show = store.get_settings().map(|s| s.notifications).unwrap_or(true);
+                for notification in &notifications {
+                    let _ = app.emit("reminder", notification);
+                    if show {
+                        let title = match notification.kind {
+                            ReminderKind::Snooze => "Snoozed email is back",
+                            ReminderKind::FollowUp => "No reply yet",
+                        };
+                        notify(&app, title, &notification.subject);
+                    }
+                }
+            }
+            Err(e) => report_error(&app, None, format!("Failed to run scheduled reminders: {}", e)),
+        }
+        if let Err(e) = run_ai_digest(&app, &store).await {
+            report_error(&app, None, format!("Failed to make the daily AI digest: {}", e));
+        }
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+    }
+}
+
+/// Purges expired quarantine and trash every hour and, once a day, emits a
+/// `quarantine-digest` event listing the emails quarantined since the last one.
+async fn cleanup_job(app: AppHandle) {
+    loop {
+        let store = app.state::<AppState>().store.clone();
+        match purge_expired(&app, &store).await {
+            Ok(purged) => {
+                let account_ids: HashSet<&String> = purged.iter().map(|e| &e.account_id).collect();
+                for account_id in account_ids {
+                    let _ = app.emit("account-changed", account_id);
+                }
+            }
+            Err(e) => report_error(&app, None, format!("Failed to purge expired emails: {}", e)),
+        }
+
+        let now = chrono::Utc::now();
+        // The first digest is sent a day after the first start
+        let last_digest = match store.get_last_quarantine_digest() {
+            Ok(Some(last_digest)) => last_digest,
+            _ => {
+                let _ = store.set_last_quarantine_digest(now);
+                now
+            }
+        };
+        if now - last_digest >= chrono::Duration::days(1) {
+            if let (Ok(settings), Ok(emails)) = (store.get_settings(), store.get_emails()) {
+                let digest = quarantine::digest(
+                    &emails,
+                    quarantine::retention_days(&settings),
+                    Some(last_digest),
+                );
+                if !digest.entries.is_empty() {
+                    let _ = app.emit("quarantine-digest", &digest);
+                }
+            }
+            if let Err(e) = store.set_last_quarantine_digest(now) {
+                report_error(&app, None, format!("Failed to save the quarantine digest time: {}", e));
+            }
+        }
+
+        tokio::time::sleep(PURGE_INTERVAL).await;
+    }
+}
+
+fn find_account(store: &Store, id: &str) -> Result<Option<EmailAccount>, String> {
+    let accounts = store.get_accounts().map_err(|e| e.to_string())?;
+    Ok(accounts.into_iter().find(|a| a.id == id))
+}
+
+/// Keeps an account's change notifications running; dropping it stops them.
+enum AccountWatcher {
+    // Only held so the watcher is dropped together with the entry
+    #[allow(dead_code)]
+    Maildir(notify::RecommendedWatcher),
+    Push(tauri::async_runtime::JoinHandle<()>),
+}
+
+impl Drop for AccountWatcher {
+    fn drop(&mut self) {
+        if let AccountWatcher::Push(task) = self {
+            task.abort();
+        }
+    }
+}
+
+/// Starts (or restarts) listening for changes on an account's backend, so
+/// that Maildir deliveries and JMAP push notifications are synced right away.
+fn watch_account(app: &AppHandle, account: &EmailAccount) {
+    let state = app.state::<AppState>();
+    let mut watchers = state.watchers.lock().unwrap();
+    watchers.remove(&account.id);
+
+    let on_change = sync_trigger(app.clone(), account.clone());
+    let watcher = match account.protocol {
+        Protocol::Maildir => email::maildir::account_root(account)
+            .and_then(|root| email::maildir::watch(&root, on_change))
+            .map(AccountWatcher::Maildir),
+        Protocol::Jmap => {
+            let app = app.clone();
+            let account_id = account.id.clone();
+            let on_error = move |e: anyhow::Error| {
+                report_error(&app, Some(&account_id), format!("Push notifications disconnected: {}", e));
+            };
+            Ok(AccountWatcher::Push(tauri::async_runtime::spawn(
+                email::jmap::listen(account.clone(), on_change, on_error),
+            )))
+        }
+        _ => return,
+    };
+
+    match watcher {
+        Ok(watcher) => {
+            watchers.insert(account.id.clone(), watcher);
+        }
+        Err(e) => report_error(app, Some(&account.id), format!("Failed to watch for changes: {}", e)),
+    }
+}
+
+/// Returns a callback that syncs `account` shortly after it is invoked,
+/// coalescing the bursts of notifications a single change produces.
+fn sync_trigger(app: AppHandle, account: EmailAccount) -> impl Fn() + Send + Sync + 'static {
+    let pending = Arc::new(AtomicBool::new(false));
+    move || {
+        if pending.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let app = app.clone();
+        let account = account.clone();
+        let pending = pending.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            pending.store(false, Ordering::SeqCst);
+
+            let state = app.state::<AppState>();
+            let Ok(settings) = state.store.get_settings() else {
+                return;
+            };
+            let result = sync_account(&app, &state.store, &settings, &account).await;
+            record_sync(&app, &state.store, &account.id, result);
+            let _ = app.emit("account-changed", &account.id);
+        });
+    }
+}
+
+/// Sends a message, with the signature of the identity it is sent from.
+/// Replies (`in_reply_to` is the id of the original) are threaded onto it
+/// and, unless an identity is given, sent from the identity the original
+/// was addressed to. With `follow_up_at` a reminder fires then
+/// unless a reply has arrived.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn send_email(
+    from_account_id: String,
+    to: String,
+    subject: String,
+    body: String,
+    identity_id: Option<String>,
+    in_reply_to: Option<String>,
+    follow_up_at: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let follow_up_at = follow_up_at.as_deref().map(parse_future_time).transpose()?;
+    let accounts = state.store.get_accounts().map_err(|e| e.to_string())?;
+    let account = accounts.iter()
+        .find(|a| a.id == from_account_id)
+        .ok_or_else(|| "Account not found".to_string())?;
+
+    let original = match &in_reply_to {
+        Some(id) => state.store.get_email(id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let identity = match &identity_id {
+        Some(id) => Some(
+            account.identities.iter().flatten()
+                .find(|i| &i.id == id)
+                .ok_or_else(|| "Identity not found".to_string())?,
+        ),
+        None => original.as_ref().and_then(|o| email::identity_for_reply(account, o)),
+    };
+
+    let signatures = state.store.get_signatures().map_err(|e| e.to_string())?;
+    let signature = email::templates::signature_for(&signatures, account, identity);
+    let body = email::templates::append_signature(&body, signature);
+    let message = email::compose(account, identity, &to, &subject, &body, original.as_ref());
+    email::send_email(account, identity, &message)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Remind about the message if nobody has replied by then
+    if let (Some(remind_at), Some(message_id)) = (follow_up_at, message.message_id.clone()) {
+        state.store
+            .add_reminder(FollowUpReminder {
+                id: uuid::Uuid::new_v4().to_string(),
+                account_id: account.id.clone(),
+                message_id,
+                email_id: None,
+                subject: message.subject.clone(),
+                to: message.to.clone(),
+                remind_at: remind_at.to_rfc3339(),
+                created_at: chrono::Utc::now().to_rfc3339(),
+            })
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_templates(state: State<'_, AppState>) -> Result<Vec<Template>, String> {
+    state.store.get_templates().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_template(
+    template: Template,
+    state: State<'_, AppState>,
+) -> Result<Template, String> {
+    let mut new_template = template;
+    if new_template.id.is_empty() {
+        new_template.id = uuid::Uuid::new_v4().to_string();
+    }
+    state.store.add_template(new_template.clone()).map_err(|e| e.to_string())?;
+    Ok(new_template)
+}
+
+#[tauri::command]
+async fn update_template(
+    id: String,
+    template: Template,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.update_template(&id, template).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_template(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.delete_template(&id).map_err(|e| e.to_string())
+}
+
+/// Fills in a template's placeholders from the email being replied to.
+#[tauri::command]
+async fn render_template(
+    id: String,
+    in_reply_to: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Template, String> {
+    let template = state.store.get_templates()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|t| t.id == id)
+        .ok_or_else(|| "Template not found".to_string())?;
+    let original = match &in_reply_to {
+        Some(id) => state.store.get_email(id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    Ok(Template {
+        subject: template.subject.as_deref().map(|s| email::templates::render(s, original.as_ref())),
+        body: email::templates::render(&template.body, original.as_ref()),
+        html_body: template.html_body.as_deref().map(|h| email::templates::render_html(h, original.as_ref())),
+        ..template
+    })
+}
+
+#[tauri::command]
+async fn get_signatures(state: State<'_, AppState>) -> Result<Vec<Signature>, String> {
+    state.store.get_signatures().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_signature(
+    signature: Signature,
+    state: State<'_, AppState>,
+) -> Result<Signature, String> {
+    let mut new_signature = signature;
+    if new_signature.id.is_empty() {
+        new_signature.id = uuid::Uuid::new_v4().to_string();
+    }
+    state.store.add_signature(new_signature.clone()).map_err(|e| e.to_string())?;
+    Ok(new_signature)
+}
+
+#[tauri::command]
+async fn update_signature(
+    id: String,
+    signature: Signature,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.update_signature(&id, signature).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_signature(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.delete_signature(&id).map_err(|e| e.to_string())
+}
+
+/// Creates a draft with the signature of the identity it is sent from,
+/// optionally replying to an email and filled from a template.
+#[tauri::command]
+async fn create_draft(
+    account_id: String,
+    identity_id: Option<String>,
+    in_reply_to: Option<String>,
+    template_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Draft, String> {
+    let account = find_account(&state.store, &account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let original = match &in_reply_to {
+        Some(id) => state.store.get_email(id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let identity = match &identity_id {
+        Some(id) => Some(
+            account.identities.iter().flatten()
+                .find(|i| &i.id == id)
+                .ok_or_else(|| "Identity not found".to_string())?,
+        ),
+        None => original.as_ref().and_then(|o| email::identity_for_reply(&account, o)),
+    };
+    let templates = state.store.get_templates().map_err(|e| e.to_string())?;
+    let template = match &template_id {
+        Some(id) => Some(
+            templates.iter()
+                .find(|t| &t.id == id)
+                .ok_or_else(|| "Template not found".to_string())?,
+        ),
+        None => None,
+    };
+    let signatures = state.store.get_signatures().map_err(|e| e.to_string())?;
+    let signature = email::templates::signature_for(&signatures, &account, identity);
+
+    let draft = email::templates::new_draft(&account, identity, original.as_ref(), template, signature);
+    state.store.add_draft(draft.clone()).map_err(|e| e.to_string())?;
+    Ok(draft)
+}
+
+/// Has the AI write a reply to an email, following the user's instructions
+/// if given, and saves it as a draft with the signature for the user to
+/// edit. Nothing is sent.
+#[tauri::command]
+async fn draft_reply(
+    id: String,
+    instructions: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Draft, String> {
+    let store = &state.store;
+    let emails = store.get_emails().map_err(|e| e.to_string())?;
+    let original = emails.iter()
+        .find(|e| e.id == id)
+        .ok_or_else(|| "Email not found".to_string())?;
+    let account = find_account(store, &original.account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let identity = email::identity_for_reply(&account, original);
+    let signatures = store.get_signatures().map_err(|e| e.to_string())?;
+    let signature = email::templates::signature_for(&signatures, &account, identity);
+
+    // The thread up to the email replied to, and other mail from its sender
+    let mut thread = ai::summary::thread(&emails, original);
+    if let Some(end) = thread.iter().position(|e| e.id == id) {
+        thread.truncate(end + 1);
+    }
+    let history: Vec<Email> = emails.iter()
+        .filter(|e| {
+            e.account_id == account.id
+                && e.from.address.eq_ignore_ascii_case(&original.from.address)
+                && !thread.iter().any(|t| t.id == e.id)
+        })
+        .cloned()
+        .collect();
+    let author = match identity {
+        Some(identity) => EmailAddress {
+            name: identity.display_name.clone(),
+            address: identity.email.clone(),
+        },
+        None => EmailAddress {
+            name: account.display_name.clone(),
+            address: account.email.clone(),
+        },
+    };
+
+    let classifier = enabled_ai(store).map_err(|e| e.to_string())?;
+    let result = classifier
+        .draft_reply(&thread, &history, &author, instructions.as_deref())
+        .await;
+    store.record_ai_usage(classifier.take_usage()).map_err(|e| e.to_string())?;
+    let text = result.map_err(|e| e.to_string())?;
+
+    let draft = email::templates::reply_draft(&account, identity, original, &text, signature);
+    store.add_draft(draft.clone()).map_err(|e| e.to_string())?;
+    Ok(draft)
+}
+
+#[tauri::command]
+async fn get_drafts(state: State<'_, AppState>) -> Result<Vec<Draft>, String> {
+    state.store.get_drafts().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_draft(
+    id: String,
+    draft: Draft,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut draft = draft;
+    draft.updated_at = chrono::Utc::now().to_rfc3339();
+    state.store.update_draft(&id, draft).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_draft(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.delete_draft(&id).map_err(|e| e.to_string())
+}
+
+/// Sends a draft, including its HTML part, and removes it.
+#[tauri::command]
+async fn send_draft(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let draft = state.store.get_drafts()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|d| d.id == id)
+        .ok_or_else(|| "Draft not found".to_string())?;
+    let account = find_account(&state.store, &draft.account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let original = match &draft.in_reply_to {
+        Some(id) => state.store.get_email(id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let identity = account.identities.iter().flatten()
+        .find(|i| Some(&i.id) == draft.identity_id.as_ref());
+
+    let mut message = email::compose(&account, identity, &draft.to, &draft.subject, &draft.body, original.as_ref());
+    message.html_body = draft.html_body.clone();
+    email::send_email(&account, identity, &message)
+        .await
+        .map_err(|e| e.to_string())?;
+    state.store.delete_draft(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_rules(state: State<'_, AppState>) -> Result<Vec<Rule>, String> {
+    state.store.get_rules().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_rule(
+    rule: Rule,
+    state: State<'_, AppState>,
+) -> Result<Rule, String> {
+    rules::validate(&rule).map_err(|e| e.to_string())?;
+    let mut new_rule = rule;
+    if new_rule.id.is_empty() {
+        new_rule.id = uuid::Uuid::new_v4().to_string();
+    }
+    state.store.add_rule(new_rule.clone()).map_err(|e| e.to_string())?;
+    Ok(new_rule)
+}
+
+#[tauri::command]
+async fn update_rule(
+    id: String,
+    rule: Rule,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    rules::validate(&rule).map_err(|e| e.to_string())?;
+    state.store.update_rule(&id, rule).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_rule(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.delete_rule(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn reorder_rules(
+    ids: Vec<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.reorder_rules(&ids).map_err(|e| e.to_string())
+}
+
+/// Runs the rules against stored emails (all of them, or those matching
+/// `query`). With `dry_run` nothing is changed and only the matches are reported.
+#[tauri::command]
+async fn run_rules(
+    query: Option<EmailQuery>,
+    dry_run: bool,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<Vec<RuleMatch>, String> {
+    let store = &state.store;
+    let rule_set = load_rules(store).map_err(|e| e.to_string())?;
+    let emails = store.query_emails(&query.unwrap_or_default()).map_err(|e| e.to_string())?;
+    let accounts = store.get_accounts().map_err(|e| e.to_string())?;
+
+    let mut matches = Vec::new();
+    let mut updated = Vec::new();
+    let mut previous = Vec::new();
+    for mut email_item in emails {
+        // Quarantined, trashed and snoozed emails are left alone until they are back
+        if email_item.quarantine.is_some() || email_item.trashed.is_some() || email_item.snooze.is_some() {
+            continue;
+        }
+        if dry_run {
+            let raw = if rule_set.needs_headers() {
+                email::raw_source(store, &email_item).ok()
+            } else {
+                None
+            };
+            let matched = rule_set.evaluate(&email_item, raw.as_deref());
+            if !matched.is_empty() {
+                matches.push(rules::report(&email_item, &matched));
+            }
+            continue;
+        }
+
+        let Some(account) = accounts.iter().find(|a| a.id == email_item.account_id) else {
+            continue;
+        };
+        let original = email_item.clone();
+        let effects = apply_rules(&app, store, &rule_set, account, &mut email_item)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(effects) = effects {
+            matches.push(effects.report);
+            previous.push(original);
+            updated.push(email_item);
+        }
+    }
+
+    store.update_emails(updated).map_err(|e| e.to_string())?;
+    if !previous.is_empty() {
+        store.push_undo(format!("Run rules on {} emails", previous.len()), previous, None);
+    }
+    Ok(matches)
+}
+
+#[tauri::command]
+async fn get_sieve_scripts(state: State<'_, AppState>) -> Result<Vec<SieveScript>, String> {
+    state.store.get_sieve_scripts().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_sieve_script(
+    script: SieveScript,
+    state: State<'_, AppState>,
+) -> Result<SieveScript, String> {
+    let mut new_script = script;
+    if new_script.id.is_empty() {
+        new_script.id = uuid::Uuid::new_v4().to_string();
+    }
+    new_script.updated_at = chrono::Utc::now().to_rfc3339();
+    state.store.add_sieve_script(new_script.clone()).map_err(|e| e.to_string())?;
+    Ok(new_script)
+}
+
+#[tauri::command]
+async fn update_sieve_script(
+    id: String,
+    script: SieveScript,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let mut script = script;
+    script.updated_at = chrono::Utc::now().to_rfc3339();
+    state.store.update_sieve_script(&id, script).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_sieve_script(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state.store.delete_sieve_script(&id).map_err(|e| e.to_string())
+}
+
+/// Adds a script from a `.sieve` file, named after the file.
+#[tauri::command]
+async fn import_sieve_script(
+    path: String,
+    account_id: String,
+    state: State<'_, AppState>,
+) -> Result<SieveScript, String> {
+    let path = PathBuf::from(path);
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "imported".to_string());
+    let script = SieveScript {
+        id: uuid::Uuid::new_v4().to_string(),
+        account_id,
+        name,
+        content,
+        active: false,
+        updated_at: chrono::Utc::now().to_rfc3339(),
+    };
+    state.store.add_sieve_script(script.clone()).map_err(|e| e.to_string())?;
+    Ok(script)
+}
+
+#[tauri::command]
+async fn list_remote_sieve_scripts(
+    account_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<RemoteSieveScript>, String> {
+    let account = find_account(&state.store, &account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let mut session = email::managesieve::connect(&account).await.map_err(|e| e.to_string())?;
+    let scripts = session.list_scripts().await.map_err(|e| e.to_string())?;
+    session.logout().await;
+    Ok(scripts)
+}
+
+/// Copies a script from the account's server, replacing an earlier local
+/// copy of the same name.
+#[tauri::command]
+async fn download_sieve_script(
+    account_id: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<SieveScript, String> {
+    let account = find_account(&state.store, &account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let mut session = email::managesieve::connect(&account).await.map_err(|e| e.to_string())?;
+    let remote = session.list_scripts().await.map_err(|e| e.to_string())?;
+    let content = session.get_script(&name).await.map_err(|e| e.to_string())?;
+    session.logout().await;
+
+    let existing = state.store.get_sieve_scripts().map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|s| s.account_id == account_id && s.name == name);
+    let script = SieveScript {
+        id: existing.as_ref().map(|s| s.id.clone()).unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        account_id,
+        active: remote.iter().any(|r| r.name == name && r.active),
+        name,
+        content,
+        updated_at: chrono::Utc::now().to_rfc3339(),
+    };
+    match existing {
+        Some(existing) => state.store.update_sieve_script(&existing.id, script.clone()),
+        None => state.store.add_sieve_script(script.clone()),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(script)
+}
+
+/// Uploads a local script to its account's server, which rejects scripts
+/// it cannot run, and optionally makes it the active one.
+#[tauri::command]
+async fn upload_sieve_script(
+    id: String,
+    activate: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let script = state.store.get_sieve_scripts().map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|s| s.id == id)
+        .ok_or_else(|| "Sieve script not found".to_string())?;
+    let account = find_account(&state.store, &script.account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let mut session = email::managesieve::connect(&account).await.map_err(|e| e.to_string())?;
+    session.put_script(&script.name, &script.content).await.map_err(|e| e.to_string())?;
+    if activate {
+        session.set_active(Some(&script.name)).await.map_err(|e| e.to_string())?;
+    }
+    session.logout().await;
+    if activate {
+        state.store
+            .set_active_sieve_script(&account.id, Some(&script.name))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Activates the named script on the account's server, or deactivates
+/// Sieve filtering for `None`.
+#[tauri::command]
+async fn activate_sieve_script(
+    account_id: String,
+    name: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let account = find_account(&state.store, &account_id)?
+        .ok_or_else(|| "Account not found".to_string())?;
+    let mut session = email::managesieve::connect(&account).await.map_err(|e| e.to_string())?;
+    session.set_active(name.as_deref()).await.map_err(|e| e.to_string())?;
+    session.logout().await;
+    state.store
+        .set_active_sieve_script(&account_id, name.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+/// Runs a script against stored emails (all of them, or those matching
+/// `query`) and reports what it would do with each. Nothing is changed.
+#[tauri::command]
+async fn test_sieve_script(
+    content: String,
+    query: Option<EmailQuery>,
+    state: State<'_, AppState>,
+) -> Result<Vec<SieveTestResult>, String> {
+    let script = sieve::Script::parse(&content).map_err(|e| e.to_string())?;
+    let store = &state.store;
+    let emails = store.query_emails(&query.unwrap_or_default()).map_err(|e| e.to_string())?;
+    emails
+        .iter()
+        .map(|email_item| {
+            let raw = email::raw_source(store, email_item).map_err(|e| e.to_string())?;
+            Ok(SieveTestResult {
+                email_id: email_item.id.clone(),
+                subject: email_item.subject.clone(),
+                actions: script.evaluate(email_item, &raw),
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+async fn import_mbox(
+    path: String,
+    account_id: String,
+    folder: Option<String>,
+    format: Option<MboxFormat>,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<ImportProgress, String> {
+    let store = state.store.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        email::mbox::import(
+            &store,
+            &PathBuf::from(path),
+            format.unwrap_or_default(),
+            &account_id,
+            folder.as_deref(),
+            |progress| {
+                let _ = app.emit("mbox-import-progress", progress);
+            },
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_mbox(
+    path: String,
+    query: EmailQuery,
+    format: Option<MboxFormat>,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    let store = state.store.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        email::mbox::export(&store, &PathBuf::from(path), &query, format.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_maildir(
+    path: String,
+    account_id: String,
+    state: State<'_, AppState>,
+    app: AppHandle,
+) -> Result<ImportProgress, String> {
+    let store = state.store.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        email::maildir::import(&store, &PathBuf::from(path), &account_id, |progress| {
+            let _ = app.emit("maildir-import-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_maildir(
+    path: String,
+    query: EmailQuery,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    let store = state.store.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        email::maildir::export(&store, &PathBuf::from(path), &query)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_eml(
+    paths: Vec<String>,
+    account_id: String,
+    folder: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ImportProgress, String> {
+    let store = state.store.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+        email::eml::import(&store, &paths, &account_id, folder.as_deref())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_eml(
+    id: String,
+    path: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    email::eml::export(&state.store, &id, &PathBuf::from(path)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_settings(state: State<'_, AppState>) -> Result<AppSettings, String> {
+    state.store.get_settings().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_settings(
+    settings: AppSettings,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if let Some(ai_config) = settings.ai_config.as_ref().filter(|c| c.monthly_budget.is_some()) {
+        if !ai::usage::is_priced(ai_config) {
+            return Err(format!(
+                "No price is known for model {}; set one to use a monthly budget",
+                ai_config.model.as_deref().unwrap_or("(default)")
+            ));
+        }
+    }
+    state.store.update_settings(settings).map_err(|e| e.to_string())
+}
+
+/// The built-in categories and the user's, in the order the AI sees them.
+#[tauri::command]
+async fn get_categories(state: State<'_, AppState>) -> Result<Vec<CategoryDefinition>, String> {
+    let custom = state.store.get_categories().map_err(|e| e.to_string())?;
+    Ok(categories::all(custom))
+}
+
+#[tauri::command]
+async fn add_category(
+    category: CategoryDefinition,
+    state: State<'_, AppState>,
+) -> Result<CategoryDefinition, String> {
+    let mut new_category = category;
+    if new_category.id.is_empty() {
+        new_category.id = uuid::Uuid::new_v4().to_string();
+    }
+    let custom = state.store.get_categories().map_err(|e| e.to_string())?;
+    categories::validate(&new_category, &custom).map_err(|e| e.to_string())?;
+    state.store.add_category(new_category.clone()).map_err(|e| e.to_string())?;
+    Ok(new_category)
+}
+
+#[tauri::command]
+async fn update_category(
+    id: String,
+    category: CategoryDefinition,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let category = CategoryDefinition { id: id.clone(), ..category };
+    let custom = state.store.get_categories().map_err(|e| e.to_string())?;
+    categories::validate(&category, &custom).map_err(|e| e.to_string())?;
+    state.store.update_category(&id, category).map_err(|e| e.to_string())
+}
+
+/// Deletes a user-defined category. Emails keep it until they are
+/// reclassified.
+#[tauri::command]
+async fn delete_category(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if categories::is_builtin(&id) {
+        return Err("Built-in categories cannot be deleted".to_string());
+    }
+    state.store.delete_category(&id).map_err(|e| e.to_string())
+}
+
+/// Forgets all cached classifications, e.g. after changing the prompt or
+/// model, so emails are sent to the AI again. Returns how many were cached.
+#[tauri::command]
+async fn clear_classification_cache(state: State<'_, AppState>) -> Result<usize, String> {
+    state.store.clear_classification_cache().map_err(|e| e.to_string())
+}
+
+/// Token usage per provider, model and account by day and by month, with
+/// estimated costs and the state of the monthly budget.
+#[tauri::command]
+async fn get_ai_usage(state: State<'_, AppState>) -> Result<AIUsageReport, String> {
+    let usage = state.store.get_ai_usage().map_err(|e| e.to_string())?;
+    let settings = state.store.get_settings().map_err(|e| e.to_string())?;
+    Ok(ai::usage::report(&usage, settings.ai_config.as_ref()))
+}
+
+/// Models the AI backend offers: the one described by `config`, e.g. an
+/// unsaved settings form, or else the saved one.
+#[tauri::command]
+async fn list_ai_models(
+    config: Option<AIConfig>,
+    state: State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let config = ai_config_or_saved(config, &state.store)?;
+    ai::provider::from_config(&config)
+        .list_models()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Checks that the AI backend (from `config`, or else the saved one) is
+/// reachable and offers the configured model.
+#[tauri::command]
+async fn check_ai_health(
+    config: Option<AIConfig>,
+    state: State<'_, AppState>,
+) -> Result<AIHealth, String> {
+    let config = ai_config_or_saved(config, &state.store)?;
+    Ok(match ai::provider::from_config(&config).list_models().await {
+        Ok(models) => AIHealth {
+            reachable: true,
+            // Ollama lists untagged models as `name:latest`
+            model_available: config.model.as_ref().map(|model| {
+                models.iter().any(|m| m == model || m.strip_suffix(":latest") == Some(model.as_str()))
+            }),
+            models,
+            error: None,
+        },
+        Err(e) => AIHealth {
+            reachable: false,
+            models: Vec::new(),
+            model_available: None,
+            error: Some(e.to_string()),
+        },
+    })
+}
+
+fn ai_config_or_saved(config: Option<AIConfig>, store: &Store) -> Result<AIConfig, String> {
+    match config {
+        Some(config) => Ok(config),
+        None => store.get_settings()
+            .map_err(|e| e.to_string())?
+            .ai_config
+            .ok_or_else(|| "AI is not configured".to_string()),
+    }
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .setup(|app| {
+            let app_dir = app.path().app_data_dir()
+                .expect("Failed to get app data directory");
+            
+            let store = Arc::new(
+                Store::new(app_dir, email::autoconfig::migrate_legacy_config).expect("Failed to initialize store")
+            );
+            
+            let accounts = store.get_accounts().unwrap_or_default();
+            app.manage(AppState {
+                store,
+                watchers: Mutex::new(HashMap::new()),
+            });
+
+            for account in &accounts {
+                watch_account(app.handle(), account);
+            }
+            tauri::async_runtime::spawn(cleanup_job(app.handle().clone()));
+            tauri::async_runtime::spawn(scheduler_job(app.handle().clone()));
             
             Ok(())
         })
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_notification::init())
         .invoke_handler(tauri::generate_handler![
             greet,
             get_accounts,
             add_account,
+            discover_account,
             update_account,
             delete_account,
+            test_account,
+            get_account_health,
             get_emails,
             sync_emails,
+            set_email_flags,
+            delete_email,
+            move_emails,
+            bulk_update,
+            restore_email,
+            snooze_email,
+            unsnooze_email,
+            add_follow_up_reminder,
+            get_follow_up_reminders,
+            delete_follow_up_reminder,
+            empty_trash,
+            get_deleted_accounts,
+            restore_account,
+            undo,
+            get_undo_history,
+            get_background_errors,
+            clear_background_errors,
+            get_quarantine_digest,
+            restore_quarantined_email,
+            set_email_category,
+            retrain_local_classifier,
+            summarize_email,
+            summarize_thread,
+            purge_quarantine,
             send_email,
+            get_templates,
+            add_template,
+            update_template,
+            delete_template,
+            render_template,
+            get_signatures,
+            add_signature,
+            update_signature,
+            delete_signature,
+            create_draft,
+            draft_reply,
+            get_drafts,
+            update_draft,
+            delete_draft,
+            send_draft,
+            get_rules,
+            add_rule,
+            update_rule,
+            delete_rule,
+            reorder_rules,
+            run_rules,
+            get_sieve_scripts,
+            add_sieve_script,
+            update_sieve_script,
+            delete_sieve_script,
+            import_sieve_script,
+            list_remote_sieve_scripts,
+            download_sieve_script,
+            upload_sieve_script,
+            activate_sieve_script,
+            test_sieve_script,
+            import_mbox,
+            export_mbox,
+            import_maildir,
+            export_maildir,
+            import_eml,
+            export_eml,
             get_settings,
             update_settings,
+            list_ai_models,
+            get_categories,
+            add_category,
+            update_category,
+            delete_category,
+            clear_classification_cache,
+            get_ai_usage,
+            check_ai_health,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
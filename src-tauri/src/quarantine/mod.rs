@@ -0,0 +1,95 @@
+use crate::categories;
+use crate::storage::QUARANTINE;
+use crate::types::{
+    AIClassification, AppSettings, Category, CategoryCorrection, CategoryDefinition, Email,
+    Quarantine, QuarantineDigest, QuarantineEntry,
+};
+use chrono::{DateTime, Duration, Utc};
+
+/// Days quarantined emails are kept when no retention is configured.
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+pub fn retention_days(settings: &AppSettings) -> u32 {
+    settings
+        .ai_config
+        .as_ref()
+        .and_then(|c| c.quarantine_days)
+        .unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+/// Moves an email into the quarantine folder, remembering where it was.
+pub fn quarantine(email: &mut Email) {
+    email.quarantine = Some(Quarantine {
+        since: Utc::now().to_rfc3339(),
+        folder: email.folder.take(),
+    });
+    email.folder = Some(QUARANTINE.to_string());
+}
+
+/// Takes an email out of quarantine, back into its original folder, with
+/// the category the user gave it.
+pub fn restore(email: &mut Email, category: Category, categories: &[CategoryDefinition]) {
+    if let Some(quarantine) = email.quarantine.take() {
+        email.folder = quarantine.folder;
+    }
+    if let Some(classification) = email.ai_classification.as_mut() {
+        set_category(classification, category, categories);
+    }
+}
+
+/// Overrides the AI's category with the user's, which is certain.
+pub fn set_category(classification: &mut AIClassification, category: Category, categories: &[CategoryDefinition]) {
+    classification.should_notify = categories::should_notify(categories, &category);
+    classification.category = category;
+    classification.confidence = Some(1.0);
+    classification.reason = Some("Corrected by the user".to_string());
+}
+
+pub fn expires_at(email: &Email, retention_days: u32) -> Option<DateTime<Utc>> {
+    let since = DateTime::parse_from_rfc3339(&email.quarantine.as_ref()?.since).ok()?;
+    Some(since.with_timezone(&Utc) + Duration::days(retention_days.into()))
+}
+
+pub fn is_expired(email: &Email, retention_days: u32, now: DateTime<Utc>) -> bool {
+    expires_at(email, retention_days).is_some_and(|expires| expires <= now)
+}
+
+/// Lists the emails quarantined after `since`, or all of them.
+pub fn digest(emails: &[Email], retention_days: u32, since: Option<DateTime<Utc>>) -> QuarantineDigest {
+    let quarantined: Vec<(&Email, &Quarantine)> = emails
+        .iter()
+        .filter_map(|e| Some((e, e.quarantine.as_ref()?)))
+        .collect();
+    let entries = quarantined
+        .iter()
+        .filter(|(_, q)| {
+            since.is_none_or(|since| {
+                DateTime::parse_from_rfc3339(&q.since).is_ok_and(|at| at.with_timezone(&Utc) > since)
+            })
+        })
+        .map(|(email, q)| QuarantineEntry {
+            email_id: email.id.clone(),
+            account_id: email.account_id.clone(),
+            subject: email.subject.clone(),
+            from: email.from.clone(),
+            quarantined_at: q.since.clone(),
+            expires_at: expires_at(email, retention_days)
+                .map(|e| e.to_rfc3339())
+                .unwrap_or_default(),
+        })
+        .collect();
+    QuarantineDigest {
+        since: since.map(|s| s.to_rfc3339()),
+        total: quarantined.len(),
+        entries,
+    }
+}
+
+/// The category the user last gave mail from this email's sender.
+pub fn corrected_category(corrections: &[CategoryCorrection], email: &Email) -> Option<Category> {
+    corrections
+        .iter()
+        .rev()
+        .find(|c| c.sender.eq_ignore_ascii_case(&email.from.address))
+        .map(|c| c.category.clone())
+}
@@ -0,0 +1,404 @@
+use crate::storage::INBOX;
+use crate::types::{
+    Email, EmailAddress, MatchOperator, Rule, RuleAction, RuleCondition, RuleMatch,
+    RuleNotification,
+};
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+
+/// The enabled rules, with their patterns compiled once up front.
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+struct CompiledRule {
+    rule: Rule,
+    /// One matcher per condition; `None` for conditions that compare no text.
+    matchers: Vec<Option<TextMatcher>>,
+}
+
+struct TextMatcher {
+    operator: MatchOperator,
+    value: String,
+    regex: Option<Regex>,
+}
+
+/// What applying the actions of the matching rules left to do besides
+/// changing the email itself.
+#[derive(Debug)]
+pub struct Effects {
+    pub report: RuleMatch,
+    pub delete: bool,
+    pub forward_to: Vec<String>,
+    pub notifications: Vec<RuleNotification>,
+}
+
+impl RuleSet {
+    /// Compiles the enabled rules, failing on the first invalid regex.
+    pub fn new(rules: &[Rule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .filter(|r| r.enabled)
+            .map(compile)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether any rule looks at headers, which requires the raw message.
+    pub fn needs_headers(&self) -> bool {
+        self.rules.iter().any(|r| {
+            r.rule
+                .conditions
+                .iter()
+                .any(|c| matches!(c, RuleCondition::Header(_)))
+        })
+    }
+
+    /// Returns the rules matching `email` in order, up to and including the
+    /// first matching rule that stops processing. `raw` is only needed for
+    /// header conditions.
+    pub fn evaluate(&self, email: &Email, raw: Option<&[u8]>) -> Vec<&Rule> {
+        let mut matched = Vec::new();
+        for compiled in &self.rules {
+            if compiled.matches(email, raw) {
+                matched.push(&compiled.rule);
+                if compiled.rule.stop_processing {
+                    break;
+                }
+            }
+        }
+        matched
+    }
+}
+
+/// Checks that a rule's regular expressions compile.
+pub fn validate(rule: &Rule) -> Result<()> {
+    compile(rule).map(|_| ())
+}
+
+fn compile(rule: &Rule) -> Result<CompiledRule> {
+    let matchers = rule
+        .conditions
+        .iter()
+        .map(|condition| {
+            let (operator, value) = match condition {
+                RuleCondition::Sender(m)
+                | RuleCondition::Recipients(m)
+                | RuleCondition::Subject(m)
+                | RuleCondition::Body(m) => (m.operator, &m.value),
+                RuleCondition::Header(h) => (h.operator, &h.value),
+                _ => return Ok(None),
+            };
+            TextMatcher::new(operator, value)
+                .map(Some)
+                .map_err(|e| anyhow!("Rule \"{}\": {}", rule.name, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(CompiledRule {
+        rule: rule.clone(),
+        matchers,
+    })
+}
+
+impl CompiledRule {
+    fn matches(&self, email: &Email, raw: Option<&[u8]>) -> bool {
+        let mut results = self
+            .rule
+            .conditions
+            .iter()
+            .zip(&self.matchers)
+            .map(|(condition, matcher)| condition_matches(condition, matcher.as_ref(), email, raw));
+        if self.rule.match_all {
+            results.all(|m| m)
+        } else {
+            self.rule.conditions.is_empty() || results.any(|m| m)
+        }
+    }
+}
+
+fn condition_matches(
+    condition: &RuleCondition,
+    matcher: Option<&TextMatcher>,
+    email: &Email,
+    raw: Option<&[u8]>,
+) -> bool {
+    let text = |haystack: &str| matcher.is_some_and(|m| m.matches(haystack));
+    match condition {
+        RuleCondition::Sender(_) => address_matches(&email.from, &text),
+        RuleCondition::Recipients(_) => email
+            .to
+            .iter()
+            .chain(email.cc.iter().flatten())
+            .chain(email.bcc.iter().flatten())
+            .any(|a| address_matches(a, &text)),
+        RuleCondition::Subject(_) => text(&email.subject),
+        RuleCondition::Body(_) => text(&email.body),
+        RuleCondition::Header(header) => raw
+            .map(|raw| header_values(raw, &header.name))
+            .unwrap_or_default()
+            .iter()
+            .any(|v| text(v)),
+        RuleCondition::Account { account_id } => &email.account_id == account_id,
+        RuleCondition::Attachment { present } => {
+            email.attachments.as_ref().is_some_and(|a| !a.is_empty()) == *present
+        }
+        RuleCondition::Category { category } => email
+            .ai_classification
+            .as_ref()
+            .is_some_and(|c| &c.category == category),
+    }
+}
+
+fn address_matches(address: &EmailAddress, text: &impl Fn(&str) -> bool) -> bool {
+    text(&address.address) || address.name.as_deref().is_some_and(text)
+}
+
+impl TextMatcher {
+    fn new(operator: MatchOperator, value: &str) -> Result<Self> {
+        let regex = match operator {
+            MatchOperator::Regex => Some(RegexBuilder::new(value).size_limit(1 << 20).build()?),
+            _ => None,
+        };
+        Ok(Self {
+            operator,
+            value: value.to_lowercase(),
+            regex,
+        })
+    }
+
+    fn matches(&self, haystack: &str) -> bool {
+        if let Some(regex) = &self.regex {
+            return regex.is_match(haystack);
+        }
+        let haystack = haystack.to_lowercase();
+        match self.operator {
+            MatchOperator::Contains => haystack.contains(&self.value),
+            MatchOperator::Equals => haystack == self.value,
+            MatchOperator::StartsWith => haystack.starts_with(&self.value),
+            MatchOperator::EndsWith => haystack.ends_with(&self.value),
+            MatchOperator::Regex => false,
+        }
+    }
+}
+
+/// Values of all header fields called `name`, unfolded.
+fn header_values(raw: &[u8], name: &str) -> Vec<String> {
+    let text = String::from_utf8_lossy(raw);
+    let mut values: Vec<String> = Vec::new();
+    let mut current: Option<String> = None;
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = current.as_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        values.extend(current.take());
+        if let Some((field, value)) = line.split_once(':') {
+            if field.trim().eq_ignore_ascii_case(name) {
+                current = Some(value.trim().to_string());
+            }
+        }
+    }
+    values.extend(current);
+    values
+}
+
+/// Applies the actions of the matched rules to `email` and returns what is
+/// left to do outside of it.
+pub fn apply(email: &mut Email, matched: &[&Rule]) -> Effects {
+    let mut effects = Effects {
+        report: report(email, matched),
+        delete: false,
+        forward_to: Vec::new(),
+        notifications: Vec::new(),
+    };
+    for rule in matched {
+        for action in &rule.actions {
+            match action {
+                RuleAction::Move { folder } => {
+                    email.folder = (folder != INBOX).then(|| folder.clone());
+                }
+                RuleAction::Label { label } => {
+                    let labels = email.labels.get_or_insert_with(Vec::new);
+                    if !labels.contains(label) {
+                        labels.push(label.clone());
+                    }
+                }
+                RuleAction::MarkRead => email.is_read = true,
+                RuleAction::Star => email.is_starred = true,
+                RuleAction::Forward { to } => effects.forward_to.push(to.clone()),
+                RuleAction::Delete => effects.delete = true,
+                RuleAction::Notify { message } => effects.notifications.push(RuleNotification {
+                    rule_id: rule.id.clone(),
+                    email_id: email.id.clone(),
+                    message: message.clone().unwrap_or_else(|| email.subject.clone()),
+                }),
+            }
+        }
+    }
+    effects
+}
+
+/// Describes what the matched rules would do, for dry runs.
+pub fn report(email: &Email, matched: &[&Rule]) -> RuleMatch {
+    RuleMatch {
+        email_id: email.id.clone(),
+        subject: email.subject.clone(),
+        rule_ids: matched.iter().map(|r| r.id.clone()).collect(),
+        actions: matched.iter().flat_map(|r| r.actions.clone()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::mime;
+    use crate::types::{AIClassification, Attachment, Category, HeaderMatch, TextMatch};
+
+    const RAW: &[u8] = b"From: Ann Example <ann@example.com>\r\n\
+To: me@example.com\r\n\
+Cc: Team <team@example.org>\r\n\
+Subject: Invoice 2024-05\r\n\
+List-Id: Billing\r\n\t<billing.example.com>\r\n\
+\r\n\
+Please pay by Friday.\r\n\
+List-Id: not a header\r\n";
+
+    fn email() -> Email {
+        mime::parse_email(RAW, "acc").unwrap()
+    }
+
+    fn text(operator: MatchOperator, value: &str) -> TextMatch {
+        TextMatch {
+            operator,
+            value: value.to_string(),
+        }
+    }
+
+    fn rule(id: &str, match_all: bool, conditions: Vec<RuleCondition>) -> Rule {
+        Rule {
+            id: id.to_string(),
+            name: id.to_string(),
+            enabled: true,
+            match_all,
+            conditions,
+            actions: vec![RuleAction::Label { label: id.to_string() }],
+            stop_processing: false,
+        }
+    }
+
+    fn matches(condition: RuleCondition, email: &Email) -> bool {
+        let rules = RuleSet::new(&[rule("r", true, vec![condition])]).unwrap();
+        !rules.evaluate(email, Some(RAW)).is_empty()
+    }
+
+    #[test]
+    fn text_conditions() {
+        let email = email();
+        assert!(matches(RuleCondition::Sender(text(MatchOperator::Contains, "ANN EXAMPLE")), &email));
+        assert!(matches(RuleCondition::Sender(text(MatchOperator::EndsWith, "@example.com")), &email));
+        assert!(!matches(RuleCondition::Sender(text(MatchOperator::Equals, "ann")), &email));
+        assert!(matches(RuleCondition::Recipients(text(MatchOperator::Equals, "team@example.org")), &email));
+        assert!(matches(RuleCondition::Subject(text(MatchOperator::StartsWith, "invoice")), &email));
+        assert!(matches(RuleCondition::Subject(text(MatchOperator::Regex, r"\d{4}-\d{2}$")), &email));
+        // Regular expressions are case-sensitive
+        assert!(!matches(RuleCondition::Subject(text(MatchOperator::Regex, "^invoice")), &email));
+        assert!(matches(RuleCondition::Body(text(MatchOperator::Contains, "by friday")), &email));
+    }
+
+    #[test]
+    fn header_conditions_read_unfolded_values() {
+        assert_eq!(header_values(RAW, "list-id"), ["Billing <billing.example.com>"]);
+        let header = |value: &str| {
+            RuleCondition::Header(HeaderMatch {
+                name: "List-ID".to_string(),
+                operator: MatchOperator::Equals,
+                value: value.to_string(),
+            })
+        };
+        assert!(matches(header("billing <billing.example.com>"), &email()));
+        assert!(!matches(header("not a header"), &email()));
+    }
+
+    #[test]
+    fn other_conditions() {
+        let mut email = email();
+        let account = |id: &str| RuleCondition::Account { account_id: id.to_string() };
+        assert!(matches(account("acc"), &email));
+        assert!(!matches(account("other"), &email));
+
+        assert!(matches(RuleCondition::Attachment { present: false }, &email));
+        email.attachments = Some(vec![Attachment {
+            id: "a1".to_string(),
+            filename: "invoice.pdf".to_string(),
+            mime_type: "application/pdf".to_string(),
+            size: 1,
+            content: None,
+        }]);
+        assert!(matches(RuleCondition::Attachment { present: true }, &email));
+
+        let category = RuleCondition::Category { category: Category::Important };
+        assert!(!matches(category.clone(), &email));
+        email.ai_classification = Some(AIClassification {
+            category: Category::Important,
+            verification_code: None,
+            verification_link: None,
+            should_notify: false,
+            confidence: None,
+            reason: None,
+        });
+        assert!(matches(category, &email));
+    }
+
+    #[test]
+    fn match_all_and_match_any() {
+        let conditions = vec![
+            RuleCondition::Subject(text(MatchOperator::Contains, "invoice")),
+            RuleCondition::Subject(text(MatchOperator::Contains, "receipt")),
+        ];
+        let all = rule("all", true, conditions.clone());
+        let any = rule("any", false, conditions);
+        let everything = rule("everything", false, Vec::new());
+        let rules = RuleSet::new(&[all, any, everything]).unwrap();
+
+        let ids: Vec<&str> = rules.evaluate(&email(), None).iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["any", "everything"]);
+    }
+
+    #[test]
+    fn stop_processing_skips_later_rules() {
+        let mut first = rule("first", true, Vec::new());
+        first.stop_processing = true;
+        first.actions = vec![RuleAction::MarkRead, RuleAction::Move { folder: INBOX.to_string() }];
+        let mut disabled = rule("disabled", true, Vec::new());
+        disabled.enabled = false;
+        let rules = RuleSet::new(&[disabled, first, rule("second", true, Vec::new())]).unwrap();
+
+        let mut email = email();
+        email.folder = Some("Later".to_string());
+        let matched = rules.evaluate(&email, None);
+        assert_eq!(matched.len(), 1);
+        let effects = apply(&mut email, &matched);
+        assert_eq!(effects.report.rule_ids, ["first"]);
+        assert!(email.is_read);
+        assert_eq!(email.folder, None);
+        assert_eq!(email.labels, None);
+    }
+
+    #[test]
+    fn invalid_regex_is_rejected() {
+        let bad = rule("bad", true, vec![RuleCondition::Body(text(MatchOperator::Regex, "(unclosed"))]);
+        let error = validate(&bad).unwrap_err();
+        assert!(error.to_string().starts_with("Rule \"bad\": "));
+        assert!(RuleSet::new(&[bad]).is_err());
+    }
+}
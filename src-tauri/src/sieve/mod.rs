@@ -0,0 +1,558 @@
+//! A Sieve (RFC 5228) interpreter with the fileinto, imap4flags (RFC 5232),
+//! vacation (RFC 5230), copy (RFC 3894) and regex extensions, so scripts
+//! kept on the server can be tried against stored mail.
+
+mod parser;
+
+use crate::types::{Email, SieveAction};
+use anyhow::Result;
+use mail_parser::{HeaderValue, MessageParser};
+use regex::Regex;
+use std::ops::ControlFlow;
+
+/// Extensions that scripts may `require`.
+pub const EXTENSIONS: &[&str] = &[
+    "fileinto",
+    "imap4flags",
+    "vacation",
+    "copy",
+    "envelope",
+    "regex",
+    "comparator-i;octet",
+    "comparator-i;ascii-casemap",
+];
+
+/// A parsed script.
+#[derive(Debug)]
+pub struct Script {
+    commands: Vec<Command>,
+}
+
+#[derive(Debug)]
+enum Command {
+    If {
+        branches: Vec<(Test, Vec<Command>)>,
+        otherwise: Option<Vec<Command>>,
+    },
+    Stop,
+    Keep {
+        flags: Option<Vec<String>>,
+    },
+    Discard,
+    FileInto {
+        folder: String,
+        flags: Option<Vec<String>>,
+        copy: bool,
+    },
+    Redirect {
+        address: String,
+        copy: bool,
+    },
+    Flags {
+        operation: FlagOperation,
+        flags: Vec<String>,
+    },
+    Vacation(Box<Vacation>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FlagOperation {
+    Set,
+    Add,
+    Remove,
+}
+
+#[derive(Debug)]
+struct Vacation {
+    days: u64,
+    subject: Option<String>,
+    from: Option<String>,
+    reason: String,
+    mime: bool,
+}
+
+#[derive(Debug)]
+enum Test {
+    True,
+    False,
+    Not(Box<Test>),
+    AllOf(Vec<Test>),
+    AnyOf(Vec<Test>),
+    Exists(Vec<String>),
+    Size { over: bool, limit: u64 },
+    Header { names: Vec<String>, comparison: Comparison },
+    Address { names: Vec<String>, part: AddressPart, comparison: Comparison },
+    Envelope { parts: Vec<String>, part: AddressPart, comparison: Comparison },
+    HasFlag { comparison: Comparison },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AddressPart {
+    All,
+    LocalPart,
+    Domain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MatchType {
+    Is,
+    Contains,
+    Matches,
+    Regex,
+}
+
+/// A match type and comparator together with the keys to compare against.
+#[derive(Debug)]
+struct Comparison {
+    match_type: MatchType,
+    /// `i;octet` compares exactly; the default `i;ascii-casemap` ignores case.
+    octet: bool,
+    keys: Vec<String>,
+    /// Compiled keys for `:matches` and `:regex`.
+    patterns: Vec<Regex>,
+}
+
+impl Comparison {
+    fn matches(&self, value: &str) -> bool {
+        match self.match_type {
+            MatchType::Matches | MatchType::Regex => self.patterns.iter().any(|p| p.is_match(value)),
+            MatchType::Is | MatchType::Contains => {
+                let value = if self.octet {
+                    value.to_string()
+                } else {
+                    value.to_ascii_lowercase()
+                };
+                self.keys.iter().any(|key| match self.match_type {
+                    MatchType::Is => &value == key,
+                    _ => value.contains(key.as_str()),
+                })
+            }
+        }
+    }
+}
+
+/// A header field of the message being evaluated.
+struct Field {
+    /// Lower-cased field name.
+    name: String,
+    /// Decoded, unfolded value.
+    value: String,
+    addresses: Vec<String>,
+}
+
+struct Message<'a> {
+    email: &'a Email,
+    fields: Vec<Field>,
+    size: u64,
+}
+
+struct Run<'a> {
+    message: &'a Message<'a>,
+    flags: Vec<String>,
+    actions: Vec<SieveAction>,
+    implicit_keep: bool,
+    discarded: bool,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self> {
+        parser::parse(source)
+    }
+
+    /// Runs the script against `email`, whose RFC 822 source is `raw`, and
+    /// returns the resulting actions, including the implicit keep.
+    pub fn evaluate(&self, email: &Email, raw: &[u8]) -> Vec<SieveAction> {
+        let message = Message::new(email, raw);
+        let mut run = Run {
+            message: &message,
+            flags: Vec::new(),
+            actions: Vec::new(),
+            implicit_keep: true,
+            discarded: false,
+        };
+        let _ = run.execute(&self.commands);
+
+        if run.implicit_keep {
+            let flags = run.flags.clone();
+            run.push(SieveAction::Keep { flags });
+        } else if run.discarded && !run.actions.iter().any(delivers) {
+            run.push(SieveAction::Discard);
+        }
+        run.actions
+    }
+}
+
+fn delivers(action: &SieveAction) -> bool {
+    matches!(
+        action,
+        SieveAction::Keep { .. } | SieveAction::FileInto { .. } | SieveAction::Redirect { .. }
+    )
+}
+
+impl Run<'_> {
+    fn execute(&mut self, commands: &[Command]) -> ControlFlow<()> {
+        for command in commands {
+            match command {
+                Command::If { branches, otherwise } => {
+                    let block = branches
+                        .iter()
+                        .find(|(test, _)| self.test(test))
+                        .map(|(_, block)| block)
+                        .or(otherwise.as_ref());
+                    if let Some(block) = block {
+                        self.execute(block)?;
+                    }
+                }
+                Command::Stop => return ControlFlow::Break(()),
+                Command::Keep { flags } => {
+                    let flags = flags.clone().unwrap_or_else(|| self.flags.clone());
+                    self.implicit_keep = false;
+                    self.push(SieveAction::Keep { flags });
+                }
+                Command::Discard => {
+                    self.implicit_keep = false;
+                    self.discarded = true;
+                }
+                Command::FileInto { folder, flags, copy } => {
+                    let flags = flags.clone().unwrap_or_else(|| self.flags.clone());
+                    self.implicit_keep &= *copy;
+                    self.push(SieveAction::FileInto {
+                        folder: folder.clone(),
+                        flags,
+                    });
+                }
+                Command::Redirect { address, copy } => {
+                    self.implicit_keep &= *copy;
+                    self.push(SieveAction::Redirect {
+                        address: address.clone(),
+                    });
+                }
+                Command::Flags { operation, flags } => self.change_flags(*operation, flags),
+                Command::Vacation(vacation) => self.vacation(vacation),
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn push(&mut self, action: SieveAction) {
+        if !self.actions.contains(&action) {
+            self.actions.push(action);
+        }
+    }
+
+    fn test(&self, test: &Test) -> bool {
+        let message = self.message;
+        match test {
+            Test::True => true,
+            Test::False => false,
+            Test::Not(test) => !self.test(test),
+            Test::AllOf(tests) => tests.iter().all(|t| self.test(t)),
+            Test::AnyOf(tests) => tests.iter().any(|t| self.test(t)),
+            Test::Exists(names) => names.iter().all(|name| message.fields(name).next().is_some()),
+            Test::Size { over, limit } => {
+                if *over {
+                    message.size > *limit
+                } else {
+                    message.size < *limit
+                }
+            }
+            Test::Header { names, comparison } => names
+                .iter()
+                .flat_map(|name| message.fields(name))
+                .any(|field| comparison.matches(&field.value)),
+            Test::Address { names, part, comparison } => names
+                .iter()
+                .flat_map(|name| message.fields(name))
+                .flat_map(|field| &field.addresses)
+                .any(|address| comparison.matches(address_part(address, *part))),
+            Test::Envelope { parts, part, comparison } => parts
+                .iter()
+                .flat_map(|name| message.envelope(name))
+                .any(|address| comparison.matches(address_part(&address, *part))),
+            Test::HasFlag { comparison } => self.flags.iter().any(|f| comparison.matches(f)),
+        }
+    }
+
+    fn change_flags(&mut self, operation: FlagOperation, flags: &[String]) {
+        let flags = split_flags(flags);
+        match operation {
+            FlagOperation::Set => self.flags = flags,
+            FlagOperation::Add => {
+                for flag in flags {
+                    if !self.flags.iter().any(|f| f.eq_ignore_ascii_case(&flag)) {
+                        self.flags.push(flag);
+                    }
+                }
+            }
+            FlagOperation::Remove => self
+                .flags
+                .retain(|f| !flags.iter().any(|r| r.eq_ignore_ascii_case(f))),
+        }
+    }
+
+    /// Answers the sender, except for mail that must not be auto-replied to
+    /// (RFC 3834): automatic messages, mailing lists and bounces.
+    fn vacation(&mut self, vacation: &Vacation) {
+        let message = self.message;
+        if self.actions.iter().any(|a| matches!(a, SieveAction::Vacation { .. })) {
+            return;
+        }
+        let automatic = message
+            .fields("auto-submitted")
+            .any(|f| !f.value.eq_ignore_ascii_case("no"))
+            || message.fields("list-id").next().is_some()
+            || message
+                .fields("precedence")
+                .any(|f| ["bulk", "list", "junk"].contains(&f.value.to_ascii_lowercase().as_str()));
+        let to = message
+            .fields("reply-to")
+            .chain(message.fields("from"))
+            .flat_map(|f| f.addresses.first())
+            .next()
+            .cloned()
+            .unwrap_or_else(|| message.email.from.address.clone());
+        let local = address_part(&to, AddressPart::LocalPart).to_ascii_lowercase();
+        if automatic || to.is_empty() || local == "mailer-daemon" || local.starts_with("owner-") {
+            return;
+        }
+
+        self.actions.push(SieveAction::Vacation {
+            to,
+            subject: vacation
+                .subject
+                .clone()
+                .unwrap_or_else(|| format!("Auto: {}", message.email.subject)),
+            reason: vacation.reason.clone(),
+            from: vacation.from.clone(),
+            days: vacation.days,
+            mime: vacation.mime,
+        });
+    }
+}
+
+/// imap4flags lists may hold several space-separated flags per string.
+fn split_flags(flags: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for flag in flags.iter().flat_map(|f| f.split_whitespace()) {
+        if !result.iter().any(|f| f.eq_ignore_ascii_case(flag)) {
+            result.push(flag.to_string());
+        }
+    }
+    result
+}
+
+fn address_part(address: &str, part: AddressPart) -> &str {
+    match (part, address.rsplit_once('@')) {
+        (AddressPart::LocalPart, Some((local, _))) => local,
+        (AddressPart::Domain, Some((_, domain))) => domain,
+        (AddressPart::Domain, None) => "",
+        _ => address,
+    }
+}
+
+impl<'a> Message<'a> {
+    fn new(email: &'a Email, raw: &[u8]) -> Self {
+        let fields = MessageParser::default()
+            .parse_headers(raw)
+            .map(|parsed| {
+                parsed
+                    .headers()
+                    .iter()
+                    .map(|header| {
+                        let unparsed = || {
+                            let start = header.offset_start() as usize;
+                            let end = (header.offset_end() as usize).min(raw.len());
+                            unfold(&String::from_utf8_lossy(raw.get(start..end).unwrap_or_default()))
+                        };
+                        let (value, addresses) = match header.value() {
+                            HeaderValue::Text(text) => (text.to_string(), Vec::new()),
+                            HeaderValue::TextList(list) => (list.join(", "), Vec::new()),
+                            HeaderValue::Address(address) => (
+                                unparsed(),
+                                address
+                                    .iter()
+                                    .filter_map(|a| a.address())
+                                    .map(|a| a.to_string())
+                                    .collect(),
+                            ),
+                            _ => (unparsed(), Vec::new()),
+                        };
+                        let addresses = if addresses.is_empty() {
+                            parse_addresses(&value)
+                        } else {
+                            addresses
+                        };
+                        Field {
+                            name: header.name().to_ascii_lowercase(),
+                            value,
+                            addresses,
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            email,
+            fields,
+            size: raw.len() as u64,
+        }
+    }
+
+    fn fields<'s>(&'s self, name: &str) -> impl Iterator<Item = &'s Field> + 's {
+        let name = name.to_ascii_lowercase();
+        self.fields.iter().filter(move |f| f.name == name)
+    }
+
+    /// The SMTP envelope is not kept, so it is approximated from the
+    /// headers delivery agents add, falling back to From and To.
+    fn envelope(&self, part: &str) -> Vec<String> {
+        let (header, fallback) = match part.to_ascii_lowercase().as_str() {
+            "from" => ("return-path", vec![self.email.from.address.clone()]),
+            "to" => (
+                "delivered-to",
+                self.email.to.iter().map(|a| a.address.clone()).collect(),
+            ),
+            _ => return Vec::new(),
+        };
+        let addresses: Vec<String> = self
+            .fields(header)
+            .flat_map(|f| f.addresses.iter().cloned())
+            .collect();
+        if addresses.is_empty() {
+            fallback
+        } else {
+            addresses
+        }
+    }
+}
+
+fn unfold(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Addresses in a header that was not parsed as an address list.
+fn parse_addresses(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter_map(|part| {
+            let part = part.trim();
+            let address = match (part.rfind('<'), part.rfind('>')) {
+                (Some(start), Some(end)) if start < end => &part[start + 1..end],
+                _ => part,
+            };
+            address.contains('@').then(|| address.trim().to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::mime;
+
+    const RAW: &str = "From: Ann <ann@example.com>\r\n\
+To: me@example.com\r\n\
+Subject: Your Receipt\r\n\
+X-Topic: Shopping\r\n\
+\r\n\
+Thanks for your order.\r\n";
+
+    fn run(script: &str) -> Vec<SieveAction> {
+        let email = mime::parse_email(RAW.as_bytes(), "acc").unwrap();
+        Script::parse(script).unwrap().evaluate(&email, RAW.as_bytes())
+    }
+
+    fn file_into(folder: &str) -> SieveAction {
+        SieveAction::FileInto {
+            folder: folder.to_string(),
+            flags: Vec::new(),
+        }
+    }
+
+    fn keep() -> SieveAction {
+        SieveAction::Keep { flags: Vec::new() }
+    }
+
+    #[test]
+    fn string_lists_match_any_name_and_key() {
+        let script = r#"require ["fileinto"];
+            if header :contains ["x-mailer", "subject"] ["invoice", "receipt"] { fileinto "Bills"; }"#;
+        assert_eq!(run(script), [file_into("Bills")]);
+
+        let script = r#"require "fileinto";
+            if address :domain ["to", "from"] ["other.example", "example.com"] { fileinto "Known"; }
+            if exists ["x-topic", "x-missing"] { fileinto "Missing"; }
+            if exists ["x-topic", "subject"] { fileinto "Both"; }"#;
+        assert_eq!(run(script), [file_into("Known"), file_into("Both")]);
+    }
+
+    #[test]
+    fn comparators_decide_on_case() {
+        let script = |comparator: &str| {
+            format!(
+                r#"require ["fileinto", "comparator-i;octet"];
+                if header :is {} "subject" "your receipt" {{ fileinto "Match"; }}"#,
+                comparator
+            )
+        };
+        assert_eq!(run(&script("")), [file_into("Match")]);
+        assert_eq!(run(&script(r#":comparator "i;ascii-casemap""#)), [file_into("Match")]);
+        assert_eq!(run(&script(r#":comparator "i;octet""#)), [keep()]);
+
+        let script = r#"if header :matches :comparator "i;octet" "subject" "Your R*" { discard; }"#;
+        assert_eq!(run(script), [SieveAction::Discard]);
+        assert!(Script::parse(r#"if header :comparator "i;unicode" "subject" "x" { stop; }"#).is_err());
+    }
+
+    #[test]
+    fn multi_line_strings_are_unstuffed() {
+        let script = "require \"vacation\";\r\n\
+vacation :subject \"Away\" text: # the reason\r\n\
+I am away.\r\n\
+..signed\r\n\
+\r\n\
+.\r\n\
+;\r\n\
+keep;\r\n";
+        let actions = run(script);
+        assert_eq!(actions.len(), 2);
+        let SieveAction::Vacation { to, subject, reason, days, .. } = &actions[0] else {
+            panic!("expected vacation, got {:?}", actions[0]);
+        };
+        assert_eq!(to, "ann@example.com");
+        assert_eq!(subject, "Away");
+        assert_eq!(reason, "I am away.\n.signed\n\n");
+        assert_eq!(*days, 7);
+        assert_eq!(actions[1], keep());
+
+        let error = Script::parse("require \"vacation\";\nvacation text:\nno end\n").unwrap_err();
+        assert!(error.to_string().contains("unterminated"), "{}", error);
+    }
+
+    #[test]
+    fn stop_ends_the_script() {
+        let script = r#"require "fileinto";
+            fileinto "First";
+            if true { stop; fileinto "Nested"; }
+            fileinto "Second";"#;
+        assert_eq!(run(script), [file_into("First")]);
+
+        // The implicit keep still applies when nothing was done
+        assert_eq!(run("stop; discard;"), [keep()]);
+    }
+
+    #[test]
+    fn fileinto_after_discard_delivers() {
+        let script = r#"require "fileinto"; discard; fileinto "Saved";"#;
+        assert_eq!(run(script), [file_into("Saved")]);
+        assert_eq!(run("discard;"), [SieveAction::Discard]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = Script::parse("keep;\nfileinto \"A\";").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: missing require \"fileinto\"");
+        let error = Script::parse("if header :is \"subject\" [\"a\", ] { keep; }").unwrap_err();
+        assert!(error.to_string().starts_with("line 1: "), "{}", error);
+    }
+}
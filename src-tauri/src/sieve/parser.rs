@@ -0,0 +1,780 @@
+use super::{
+    AddressPart, Command, Comparison, FlagOperation, MatchType, Script, Test, Vacation, EXTENSIONS,
+};
+use anyhow::{anyhow, Result};
+use regex::RegexBuilder;
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Tag(String),
+    Number(u64),
+    String(String),
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    LeftBrace,
+    RightBrace,
+}
+
+/// A command or test before its arguments are checked.
+struct Node {
+    name: String,
+    line: usize,
+    args: VecDeque<Argument>,
+    tests: Vec<Node>,
+    block: Option<Vec<Node>>,
+}
+
+enum Argument {
+    Tag(String),
+    Number(u64),
+    Strings(Vec<String>),
+}
+
+pub fn parse(source: &str) -> Result<Script> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let nodes = parser.commands(false)?;
+    let mut compiler = Compiler {
+        required: HashSet::new(),
+    };
+    Ok(Script {
+        commands: compiler.block(nodes, true)?,
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while let Some(&c) = chars.get(i) {
+        let start = line;
+        let token = match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+            ' ' | '\t' | '\r' => {
+                i += 1;
+                continue;
+            }
+            '#' => {
+                while chars.get(i).is_some_and(|&c| c != '\n') {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(anyhow!("line {}: unterminated comment", start)),
+                        Some('*') if chars.get(i + 1) == Some(&'/') => break,
+                        Some('\n') => line += 1,
+                        Some(_) => {}
+                    }
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    let c = match chars.get(i) {
+                        None => return Err(anyhow!("line {}: unterminated string", start)),
+                        Some('"') => break,
+                        // Any escaped character stands for itself
+                        Some('\\') => {
+                            i += 1;
+                            match chars.get(i) {
+                                Some(&c) => c,
+                                None => continue,
+                            }
+                        }
+                        Some(&c) => c,
+                    };
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    value.push(c);
+                    i += 1;
+                }
+                i += 1;
+                Token::String(value)
+            }
+            '0'..='9' => {
+                let digits: String = chars[i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                i += digits.len();
+                let multiplier: u64 = match chars.get(i).map(|c| c.to_ascii_uppercase()) {
+                    Some('K') => 1 << 10,
+                    Some('M') => 1 << 20,
+                    Some('G') => 1 << 30,
+                    _ => 1,
+                };
+                if multiplier > 1 {
+                    i += 1;
+                }
+                let number = digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|n| n.checked_mul(multiplier))
+                    .ok_or_else(|| anyhow!("line {}: number too large", start))?;
+                Token::Number(number)
+            }
+            ':' => {
+                let name = identifier(&chars[i + 1..]);
+                if name.is_empty() {
+                    return Err(anyhow!("line {}: expected a tag after \":\"", start));
+                }
+                i += 1 + name.len();
+                Token::Tag(format!(":{}", name.to_ascii_lowercase()))
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let name = identifier(&chars[i..]);
+                i += name.len();
+                if name.eq_ignore_ascii_case("text") && chars.get(i) == Some(&':') {
+                    let (value, end, lines) = multiline(&chars, i + 1, start)?;
+                    i = end;
+                    line += lines;
+                    Token::String(value)
+                } else {
+                    Token::Identifier(name.to_ascii_lowercase())
+                }
+            }
+            '[' => punctuation(&mut i, Token::LeftBracket),
+            ']' => punctuation(&mut i, Token::RightBracket),
+            '(' => punctuation(&mut i, Token::LeftParen),
+            ')' => punctuation(&mut i, Token::RightParen),
+            ',' => punctuation(&mut i, Token::Comma),
+            ';' => punctuation(&mut i, Token::Semicolon),
+            '{' => punctuation(&mut i, Token::LeftBrace),
+            '}' => punctuation(&mut i, Token::RightBrace),
+            c => return Err(anyhow!("line {}: unexpected character {:?}", start, c)),
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+fn punctuation(i: &mut usize, token: Token) -> Token {
+    *i += 1;
+    token
+}
+
+fn identifier(chars: &[char]) -> String {
+    chars
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+        .collect()
+}
+
+/// Reads a `text:` string starting right after the colon, returning it with
+/// the position after its terminating "." line and the number of lines read.
+fn multiline(chars: &[char], mut i: usize, start: usize) -> Result<(String, usize, usize)> {
+    while matches!(chars.get(i), Some(' ' | '\t')) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        while chars.get(i).is_some_and(|&c| c != '\n') {
+            i += 1;
+        }
+    }
+    if chars.get(i) == Some(&'\r') {
+        i += 1;
+    }
+    if chars.get(i) != Some(&'\n') {
+        return Err(anyhow!("line {}: expected a line break after \"text:\"", start));
+    }
+    i += 1;
+
+    let mut value = String::new();
+    let mut lines = 1;
+    loop {
+        if i >= chars.len() {
+            return Err(anyhow!("line {}: unterminated multi-line string", start));
+        }
+        let end = chars[i..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(chars.len(), |p| i + p);
+        let text: String = chars[i..end].iter().collect();
+        let text = text.strip_suffix('\r').unwrap_or(&text);
+        i = (end + 1).min(chars.len());
+        lines += 1;
+        if text == "." {
+            return Ok((value, i, lines));
+        }
+        // Dot-stuffing: ".." at the start of a line stands for "."
+        value.push_str(text.strip_prefix('.').filter(|t| t.starts_with('.')).unwrap_or(text));
+        value.push('\n');
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!("line {}: {}", self.line(), message)
+    }
+
+    fn commands(&mut self, nested: bool) -> Result<Vec<Node>> {
+        let mut commands = Vec::new();
+        loop {
+            match self.peek() {
+                None if nested => return Err(self.error("expected \"}\"")),
+                None => return Ok(commands),
+                Some(Token::RightBrace) if nested => {
+                    self.pos += 1;
+                    return Ok(commands);
+                }
+                _ => {
+                    let mut command = self.node("command")?;
+                    match self.next() {
+                        Some(Token::Semicolon) => {}
+                        Some(Token::LeftBrace) => command.block = Some(self.commands(true)?),
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error(&format!(
+                                "expected \";\" or \"{{\" after {}",
+                                command.name
+                            )));
+                        }
+                    }
+                    commands.push(command);
+                }
+            }
+        }
+    }
+
+    /// An identifier with its arguments and its test or test list.
+    fn node(&mut self, what: &str) -> Result<Node> {
+        let line = self.line();
+        let name = match self.peek() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(self.error(&format!("expected a {}", what))),
+        };
+        self.pos += 1;
+
+        let mut args = VecDeque::new();
+        loop {
+            let argument = match self.peek() {
+                Some(Token::Tag(tag)) => Argument::Tag(tag.clone()),
+                Some(Token::Number(n)) => Argument::Number(*n),
+                Some(Token::String(s)) => Argument::Strings(vec![s.clone()]),
+                Some(Token::LeftBracket) => {
+                    self.pos += 1;
+                    args.push_back(Argument::Strings(self.string_list()?));
+                    continue;
+                }
+                _ => break,
+            };
+            self.pos += 1;
+            args.push_back(argument);
+        }
+
+        let mut tests = Vec::new();
+        match self.peek() {
+            Some(Token::Identifier(_)) => tests.push(self.node("test")?),
+            Some(Token::LeftParen) => {
+                self.pos += 1;
+                loop {
+                    tests.push(self.node("test")?);
+                    match self.next() {
+                        Some(Token::Comma) => {}
+                        Some(Token::RightParen) => break,
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("expected \",\" or \")\" in test list"));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(Node {
+            name,
+            line,
+            args,
+            tests,
+            block: None,
+        })
+    }
+
+    fn string_list(&mut self) -> Result<Vec<String>> {
+        let mut strings = Vec::new();
+        loop {
+            match self.next() {
+                Some(Token::String(s)) => strings.push(s),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected a string in string list"));
+                }
+            }
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(Token::RightBracket) => return Ok(strings),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected \",\" or \"]\" in string list"));
+                }
+            }
+        }
+    }
+}
+
+/// Turns nodes into commands and tests, checking their arguments and that
+/// the extensions they use were required.
+struct Compiler {
+    required: HashSet<String>,
+}
+
+impl Compiler {
+    fn block(&mut self, nodes: Vec<Node>, top_level: bool) -> Result<Vec<Command>> {
+        let mut commands = Vec::new();
+        let mut requires_allowed = top_level;
+        for mut node in nodes {
+            if node.name == "require" {
+                if !requires_allowed {
+                    return Err(anyhow!("line {}: require must come before other commands", node.line));
+                }
+                let extensions = node.strings("extension list")?;
+                node.finish()?;
+                for extension in extensions {
+                    let extension = extension.to_ascii_lowercase();
+                    if !EXTENSIONS.contains(&extension.as_str()) {
+                        return Err(anyhow!("line {}: unsupported extension \"{}\"", node.line, extension));
+                    }
+                    self.required.insert(extension);
+                }
+                continue;
+            }
+            requires_allowed = false;
+
+            match node.name.as_str() {
+                "elsif" | "else" => {
+                    let Some(Command::If { branches, otherwise }) = commands.last_mut() else {
+                        return Err(anyhow!("line {}: {} without if", node.line, node.name));
+                    };
+                    if otherwise.is_some() {
+                        return Err(anyhow!("line {}: {} after else", node.line, node.name));
+                    }
+                    if node.name == "elsif" {
+                        branches.push(self.conditional(node)?);
+                    } else {
+                        let block = node.block.take();
+                        if !node.tests.is_empty() {
+                            return Err(anyhow!("line {}: else takes no test", node.line));
+                        }
+                        node.finish()?;
+                        *otherwise = Some(self.nested(block, node.line)?);
+                    }
+                }
+                _ => commands.push(self.command(node)?),
+            }
+        }
+        Ok(commands)
+    }
+
+    fn nested(&mut self, block: Option<Vec<Node>>, line: usize) -> Result<Vec<Command>> {
+        let block = block.ok_or_else(|| anyhow!("line {}: expected a block", line))?;
+        self.block(block, false)
+    }
+
+    fn conditional(&mut self, mut node: Node) -> Result<(Test, Vec<Command>)> {
+        let block = node.block.take();
+        let test = node.single_test()?;
+        node.finish()?;
+        Ok((self.test(test)?, self.nested(block, node.line)?))
+    }
+
+    fn require(&self, extension: &str, line: usize) -> Result<()> {
+        if self.required.contains(extension) {
+            Ok(())
+        } else {
+            Err(anyhow!("line {}: missing require \"{}\"", line, extension))
+        }
+    }
+
+    fn command(&mut self, mut node: Node) -> Result<Command> {
+        let line = node.line;
+        if node.block.is_some() && node.name != "if" {
+            return Err(anyhow!("line {}: {} takes no block", line, node.name));
+        }
+        let command = match node.name.as_str() {
+            "if" => {
+                let branch = self.conditional(node)?;
+                return Ok(Command::If {
+                    branches: vec![branch],
+                    otherwise: None,
+                });
+            }
+            "stop" => Command::Stop,
+            "discard" => Command::Discard,
+            "keep" => {
+                let mut flags = None;
+                while let Some(tag) = node.tag() {
+                    match tag.as_str() {
+                        ":flags" => {
+                            self.require("imap4flags", line)?;
+                            flags = Some(super::split_flags(&node.strings("flag list")?));
+                        }
+                        _ => return Err(node.unexpected(&tag)),
+                    }
+                }
+                Command::Keep { flags }
+            }
+            "fileinto" => {
+                self.require("fileinto", line)?;
+                let mut flags = None;
+                let mut copy = false;
+                while let Some(tag) = node.tag() {
+                    match tag.as_str() {
+                        ":flags" => {
+                            self.require("imap4flags", line)?;
+                            flags = Some(super::split_flags(&node.strings("flag list")?));
+                        }
+                        ":copy" => {
+                            self.require("copy", line)?;
+                            copy = true;
+                        }
+                        _ => return Err(node.unexpected(&tag)),
+                    }
+                }
+                Command::FileInto {
+                    folder: node.string("folder")?,
+                    flags,
+                    copy,
+                }
+            }
+            "redirect" => {
+                let mut copy = false;
+                while let Some(tag) = node.tag() {
+                    match tag.as_str() {
+                        ":copy" => {
+                            self.require("copy", line)?;
+                            copy = true;
+                        }
+                        _ => return Err(node.unexpected(&tag)),
+                    }
+                }
+                Command::Redirect {
+                    address: node.string("address")?,
+                    copy,
+                }
+            }
+            "setflag" | "addflag" | "removeflag" => {
+                self.require("imap4flags", line)?;
+                let operation = match node.name.as_str() {
+                    "setflag" => FlagOperation::Set,
+                    "addflag" => FlagOperation::Add,
+                    _ => FlagOperation::Remove,
+                };
+                let flags = node.strings("flag list")?;
+                if !node.args.is_empty() {
+                    return Err(anyhow!("line {}: flag variables are not supported", line));
+                }
+                Command::Flags { operation, flags }
+            }
+            "vacation" => {
+                self.require("vacation", line)?;
+                let mut vacation = Vacation {
+                    days: 7,
+                    subject: None,
+                    from: None,
+                    reason: String::new(),
+                    mime: false,
+                };
+                while let Some(tag) = node.tag() {
+                    match tag.as_str() {
+                        ":days" => vacation.days = node.number("days")?.max(1),
+                        ":subject" => vacation.subject = Some(node.string("subject")?),
+                        ":from" => vacation.from = Some(node.string("from address")?),
+                        ":addresses" => {
+                            node.strings("address list")?;
+                        }
+                        ":handle" => {
+                            node.string("handle")?;
+                        }
+                        ":mime" => vacation.mime = true,
+                        _ => return Err(node.unexpected(&tag)),
+                    }
+                }
+                vacation.reason = node.string("reason")?;
+                Command::Vacation(Box::new(vacation))
+            }
+            name => return Err(anyhow!("line {}: unknown command \"{}\"", line, name)),
+        };
+        if !node.tests.is_empty() {
+            return Err(anyhow!("line {}: {} takes no test", line, node.name));
+        }
+        node.finish()?;
+        Ok(command)
+    }
+
+    fn test(&mut self, mut node: Node) -> Result<Test> {
+        let line = node.line;
+        let test = match node.name.as_str() {
+            "true" => Test::True,
+            "false" => Test::False,
+            "not" => {
+                let test = node.single_test()?;
+                Test::Not(Box::new(self.test(test)?))
+            }
+            "allof" | "anyof" => {
+                let tests = std::mem::take(&mut node.tests)
+                    .into_iter()
+                    .map(|t| self.test(t))
+                    .collect::<Result<Vec<_>>>()?;
+                if tests.is_empty() {
+                    return Err(anyhow!("line {}: {} needs a test list", line, node.name));
+                }
+                if node.name == "allof" {
+                    Test::AllOf(tests)
+                } else {
+                    Test::AnyOf(tests)
+                }
+            }
+            "exists" => Test::Exists(node.strings("header names")?),
+            "size" => {
+                let over = match node.tag().as_deref() {
+                    Some(":over") => true,
+                    Some(":under") => false,
+                    _ => return Err(anyhow!("line {}: size needs :over or :under", line)),
+                };
+                Test::Size {
+                    over,
+                    limit: node.number("size limit")?,
+                }
+            }
+            "header" => {
+                let spec = self.match_spec(&mut node, false)?;
+                let names = node.strings("header names")?;
+                Test::Header {
+                    names,
+                    comparison: spec.compile(node.strings("key list")?, line)?,
+                }
+            }
+            "address" | "envelope" => {
+                if node.name == "envelope" {
+                    self.require("envelope", line)?;
+                }
+                let spec = self.match_spec(&mut node, true)?;
+                let names = node.strings("header names")?;
+                let comparison = spec.compile(node.strings("key list")?, line)?;
+                if node.name == "envelope" {
+                    Test::Envelope {
+                        parts: names,
+                        part: spec.part,
+                        comparison,
+                    }
+                } else {
+                    Test::Address {
+                        names,
+                        part: spec.part,
+                        comparison,
+                    }
+                }
+            }
+            "hasflag" => {
+                self.require("imap4flags", line)?;
+                let spec = self.match_spec(&mut node, false)?;
+                let keys = node.strings("flag list")?;
+                if !node.args.is_empty() {
+                    return Err(anyhow!("line {}: flag variables are not supported", line));
+                }
+                Test::HasFlag {
+                    comparison: spec.compile(keys, line)?,
+                }
+            }
+            name => return Err(anyhow!("line {}: unknown test \"{}\"", line, name)),
+        };
+        if !node.tests.is_empty() {
+            return Err(anyhow!("line {}: {} takes no test", line, node.name));
+        }
+        node.finish()?;
+        Ok(test)
+    }
+
+    /// Reads the match type, comparator and, for address tests, address part tags.
+    fn match_spec(&self, node: &mut Node, address: bool) -> Result<MatchSpec> {
+        let mut spec = MatchSpec {
+            match_type: MatchType::Is,
+            octet: false,
+            part: AddressPart::All,
+        };
+        while let Some(tag) = node.tag() {
+            match tag.as_str() {
+                ":is" => spec.match_type = MatchType::Is,
+                ":contains" => spec.match_type = MatchType::Contains,
+                ":matches" => spec.match_type = MatchType::Matches,
+                ":regex" => {
+                    self.require("regex", node.line)?;
+                    spec.match_type = MatchType::Regex;
+                }
+                ":comparator" => {
+                    spec.octet = match node.string("comparator")?.to_ascii_lowercase().as_str() {
+                        "i;octet" => true,
+                        "i;ascii-casemap" => false,
+                        other => {
+                            return Err(anyhow!("line {}: unsupported comparator \"{}\"", node.line, other))
+                        }
+                    };
+                }
+                ":all" if address => spec.part = AddressPart::All,
+                ":localpart" if address => spec.part = AddressPart::LocalPart,
+                ":domain" if address => spec.part = AddressPart::Domain,
+                _ => return Err(node.unexpected(&tag)),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+struct MatchSpec {
+    match_type: MatchType,
+    octet: bool,
+    part: AddressPart,
+}
+
+impl MatchSpec {
+    fn compile(&self, keys: Vec<String>, line: usize) -> Result<Comparison> {
+        let patterns = match self.match_type {
+            MatchType::Is | MatchType::Contains => Vec::new(),
+            MatchType::Matches | MatchType::Regex => keys
+                .iter()
+                .map(|key| {
+                    let pattern = if self.match_type == MatchType::Matches {
+                        wildcard_pattern(key)
+                    } else {
+                        key.clone()
+                    };
+                    RegexBuilder::new(&pattern)
+                        .case_insensitive(!self.octet)
+                        .size_limit(1 << 20)
+                        .build()
+                        .map_err(|e| anyhow!("line {}: invalid pattern \"{}\": {}", line, key, e))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let keys = if self.octet {
+            keys
+        } else {
+            keys.iter().map(|k| k.to_ascii_lowercase()).collect()
+        };
+        Ok(Comparison {
+            match_type: self.match_type,
+            octet: self.octet,
+            keys,
+            patterns,
+        })
+    }
+}
+
+/// Translates a `:matches` key, where `*` and `?` are wildcards and `\`
+/// escapes, into an anchored regular expression.
+fn wildcard_pattern(key: &str) -> String {
+    let mut pattern = String::from("(?s)^");
+    let mut chars = key.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    pattern.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+impl Node {
+    /// Takes the next argument if it is a tag.
+    fn tag(&mut self) -> Option<String> {
+        match self.args.front() {
+            Some(Argument::Tag(tag)) => {
+                let tag = tag.clone();
+                self.args.pop_front();
+                Some(tag)
+            }
+            _ => None,
+        }
+    }
+
+    fn strings(&mut self, what: &str) -> Result<Vec<String>> {
+        match self.args.pop_front() {
+            Some(Argument::Strings(strings)) => Ok(strings),
+            _ => Err(anyhow!("line {}: {} expects a {}", self.line, self.name, what)),
+        }
+    }
+
+    fn string(&mut self, what: &str) -> Result<String> {
+        let mut strings = self.strings(what)?;
+        if strings.len() != 1 {
+            return Err(anyhow!("line {}: {} expects a single {}", self.line, self.name, what));
+        }
+        Ok(strings.remove(0))
+    }
+
+    fn number(&mut self, what: &str) -> Result<u64> {
+        match self.args.pop_front() {
+            Some(Argument::Number(n)) => Ok(n),
+            _ => Err(anyhow!("line {}: {} expects a {}", self.line, self.name, what)),
+        }
+    }
+
+    fn single_test(&mut self) -> Result<Node> {
+        if self.tests.len() != 1 {
+            return Err(anyhow!("line {}: {} expects a single test", self.line, self.name));
+        }
+        Ok(self.tests.remove(0))
+    }
+
+    fn unexpected(&self, tag: &str) -> anyhow::Error {
+        anyhow!("line {}: unexpected {} for {}", self.line, tag, self.name)
+    }
+
+    /// Fails if any arguments were left unread.
+    fn finish(&self) -> Result<()> {
+        if self.args.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("line {}: too many arguments for {}", self.line, self.name))
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::storage::SNOOZED;
+use crate::types::{Email, FollowUpReminder, Snooze};
+use chrono::{DateTime, Utc};
+
+/// Hides an email in the snoozed folder until `until`, remembering where it was.
+pub fn snooze(email: &mut Email, until: DateTime<Utc>) {
+    let folder = match email.snooze.take() {
+        // Snoozing again only moves the wake-up time
+        Some(snooze) => snooze.folder,
+        None => email.folder.take(),
+    };
+    email.snooze = Some(Snooze {
+        until: until.to_rfc3339(),
+        folder,
+    });
+    email.folder = Some(SNOOZED.to_string());
+}
+
+/// Takes an email out of the snoozed folder, back into its original folder.
+pub fn unsnooze(email: &mut Email) {
+    if let Some(snooze) = email.snooze.take() {
+        email.folder = snooze.folder;
+    }
+}
+
+/// Brings a snoozed email back as unread.
+pub fn wake(email: &mut Email) {
+    unsnooze(email);
+    email.is_read = false;
+}
+
+pub fn is_due(email: &Email, now: DateTime<Utc>) -> bool {
+    email.snooze.as_ref().is_some_and(|s| due(&s.until, now))
+}
+
+pub fn is_reminder_due(reminder: &FollowUpReminder, now: DateTime<Utc>) -> bool {
+    due(&reminder.remind_at, now)
+}
+
+/// Whether any stored email replies to the reminder's message, going by
+/// its In-Reply-To and References headers.
+pub fn has_reply(emails: &[Email], reminder: &FollowUpReminder) -> bool {
+    let message_id = normalize(&reminder.message_id);
+    emails.iter().any(|email| {
+        email.in_reply_to.iter().chain(email.references.iter().flatten())
+            .any(|id| normalize(id) == message_id)
+    })
+}
+
+fn normalize(message_id: &str) -> &str {
+    message_id.trim().trim_start_matches('<').trim_end_matches('>')
+}
+
+fn due(at: &str, now: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc3339(at).is_ok_and(|at| at.with_timezone(&Utc) <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::mime;
+    use chrono::Duration;
+
+    fn email() -> Email {
+        let mut email = mime::parse_email(b"From: ann@example.com\r\nSubject: Hi\r\n\r\nHi\r\n", "acc").unwrap();
+        email.folder = Some("Projects".to_string());
+        email.is_read = true;
+        email
+    }
+
+    fn reminder(message_id: &str) -> FollowUpReminder {
+        FollowUpReminder {
+            id: "r1".to_string(),
+            account_id: "acc".to_string(),
+            message_id: message_id.to_string(),
+            email_id: None,
+            subject: "Offer".to_string(),
+            to: Vec::new(),
+            remind_at: Utc::now().to_rfc3339(),
+            created_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    #[test]
+    fn snoozing_again_keeps_the_original_folder() {
+        let mut email = email();
+        let now = Utc::now();
+        snooze(&mut email, now + Duration::hours(1));
+        snooze(&mut email, now + Duration::days(1));
+        assert_eq!(email.folder.as_deref(), Some(SNOOZED));
+        assert_eq!(email.snooze.as_ref().unwrap().folder.as_deref(), Some("Projects"));
+
+        wake(&mut email);
+        assert_eq!(email.folder.as_deref(), Some("Projects"));
+        assert!(email.snooze.is_none() && !email.is_read);
+    }
+
+    #[test]
+    fn due_once_the_time_has_come() {
+        let mut email = email();
+        let now = Utc::now();
+        assert!(!is_due(&email, now));
+        snooze(&mut email, now + Duration::hours(1));
+        assert!(!is_due(&email, now));
+        assert!(is_due(&email, now + Duration::hours(1)));
+    }
+
+    #[test]
+    fn replies_are_found_by_in_reply_to_or_references() {
+        let reminder = reminder(" <offer@example.com>");
+        let mut reply = email();
+        assert!(!has_reply(std::slice::from_ref(&reply), &reminder));
+
+        reply.in_reply_to = Some("offer@example.com".to_string());
+        assert!(has_reply(std::slice::from_ref(&reply), &reminder));
+
+        reply.in_reply_to = Some("<other@example.com>".to_string());
+        reply.references = Some(vec!["<first@example.com>".to_string(), "<offer@example.com> ".to_string()]);
+        assert!(has_reply(&[email(), reply], &reminder));
+    }
+}
@@ -1,27 +1,95 @@
-use crate::types::{AppSettings, EmailAccount, Email};
+use crate::types::{
+    AIClassification, AIUsageRecord, AccountConfig, AccountHealth, AppSettings, BackgroundError,
+    CachedClassification,
+    CategoryCorrection, CategoryDefinition, DeletedAccount, Draft, EmailAccount, Email, EmailQuery, FollowUpReminder,
+    HealthReport, LocalModel, RemoteFlags, Rule, SieveScript, Signature, Template,
+    UndoOperation,
+};
 use anyhow::Result;
-use serde_json;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+/// Folder name used for emails that have no explicit folder.
+pub const INBOX: &str = "Inbox";
+
+/// Folder holding emails quarantined by AI classification.
+pub const QUARANTINE: &str = "Quarantine";
+
+/// Folder holding deleted emails until they are purged.
+pub const TRASH: &str = "Trash";
+
+/// Folder holding snoozed emails until they wake up.
+pub const SNOOZED: &str = "Snoozed";
+
+/// How many classifications are cached; the oldest are dropped beyond that.
+const CLASSIFICATION_CACHE_LIMIT: usize = 10_000;
+
+/// Days of AI usage that are kept.
+const AI_USAGE_RETENTION_DAYS: i64 = 400;
+
+/// How many operations can be undone.
+const UNDO_LIMIT: usize = 20;
+
+/// How many background failures are kept.
+const BACKGROUND_ERROR_LIMIT: usize = 100;
+
+/// An operation that can be undone, with the emails as they were before it.
+pub struct UndoEntry {
+    pub operation: UndoOperation,
+    pub emails: Vec<Email>,
+    /// Account the operation deleted, if any.
+    pub account_id: Option<String>,
+}
+
 pub struct Store {
     data_dir: PathBuf,
     accounts: Mutex<Vec<EmailAccount>>,
     emails: Mutex<Vec<Email>>,
     settings: Mutex<AppSettings>,
+    sync_states: Mutex<HashMap<String, String>>,
+    health: Mutex<HashMap<String, AccountHealth>>,
+    templates: Mutex<Vec<Template>>,
+    signatures: Mutex<Vec<Signature>>,
+    drafts: Mutex<Vec<Draft>>,
+    rules: Mutex<Vec<Rule>>,
+    sieve_scripts: Mutex<Vec<SieveScript>>,
+    corrections: Mutex<Vec<CategoryCorrection>>,
+    /// User-defined categories only; see `categories::all`.
+    categories: Mutex<Vec<CategoryDefinition>>,
+    deleted_accounts: Mutex<Vec<DeletedAccount>>,
+    reminders: Mutex<Vec<FollowUpReminder>>,
+    /// Keyed by `AIClassifier::cache_key`.
+    classification_cache: Mutex<HashMap<String, CachedClassification>>,
+    local_model: Mutex<LocalModel>,
+    /// Local date (`YYYY-MM-DD`) the last daily AI digest was made.
+    last_digest: Mutex<Option<String>>,
+    /// When the last quarantine digest was sent.
+    last_quarantine_digest: Mutex<Option<DateTime<Utc>>>,
+    /// One record per day, provider, model and account.
+    ai_usage: Mutex<Vec<AIUsageRecord>>,
+    /// Most recent operation last; kept for the session only.
+    undo: Mutex<VecDeque<UndoEntry>>,
+    /// Most recent last; kept for the session only.
+    background_errors: Mutex<VecDeque<BackgroundError>>,
 }
 
 impl Store {
-    pub fn new(data_dir: PathBuf) -> Result<Self> {
+    /// Opens the store in `data_dir`, converting accounts saved in the legacy
+    /// format with `migrate`.
+    pub fn new(data_dir: PathBuf, migrate: AccountMigration) -> Result<Self> {
         fs::create_dir_all(&data_dir)?;
         
         let accounts_path = data_dir.join("accounts.json");
-        let accounts = if accounts_path.exists() {
+        let (accounts, migrated) = if accounts_path.exists() {
             let data = fs::read_to_string(&accounts_path)?;
-            serde_json::from_str(&data).unwrap_or_default()
+            load_accounts(&data, migrate)
         } else {
-            Vec::new()
+            (Vec::new(), false)
         };
 
         let emails_path = data_dir.join("emails.json");
@@ -39,20 +107,93 @@ impl Store {
                 notifications: true,
                 ai_config: None,
                 theme: crate::types::Theme::System,
+                trash_days: None,
             })
         } else {
             AppSettings {
                 notifications: true,
                 ai_config: None,
                 theme: crate::types::Theme::System,
+                trash_days: None,
             }
         };
 
+        let sync_states_path = data_dir.join("sync_state.json");
+        let sync_states = if sync_states_path.exists() {
+            let data = fs::read_to_string(&sync_states_path)?;
+            serde_json::from_str(&data).unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+
+        let health_path = data_dir.join("health.json");
+        let health = if health_path.exists() {
+            let data = fs::read_to_string(&health_path)?;
+            serde_json::from_str(&data).unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+
+        let local_model_path = data_dir.join("local_model.json");
+        let local_model = if local_model_path.exists() {
+            let data = fs::read_to_string(&local_model_path)?;
+            serde_json::from_str(&data).unwrap_or_default()
+        } else {
+            LocalModel::default()
+        };
+
+        let last_digest_path = data_dir.join("ai_digest.json");
+        let last_digest = if last_digest_path.exists() {
+            let data = fs::read_to_string(&last_digest_path)?;
+            serde_json::from_str(&data).unwrap_or_default()
+        } else {
+            None
+        };
+
+        let last_quarantine_digest_path = data_dir.join("quarantine_digest.json");
+        let last_quarantine_digest = if last_quarantine_digest_path.exists() {
+            let data = fs::read_to_string(&last_quarantine_digest_path)?;
+            serde_json::from_str(&data).unwrap_or_default()
+        } else {
+            None
+        };
+
+        let classification_cache_path = data_dir.join("classification_cache.json");
+        let classification_cache = if classification_cache_path.exists() {
+            let data = fs::read_to_string(&classification_cache_path)?;
+            serde_json::from_str(&data).unwrap_or_default()
+        } else {
+            HashMap::new()
+        };
+
+        if migrated {
+            let data = serde_json::to_string_pretty(&accounts)?;
+            fs::write(&accounts_path, data)?;
+        }
+
         Ok(Self {
-            data_dir,
             accounts: Mutex::new(accounts),
             emails: Mutex::new(emails),
             settings: Mutex::new(settings),
+            sync_states: Mutex::new(sync_states),
+            health: Mutex::new(health),
+            templates: Mutex::new(load_list(&data_dir, "templates.json")?),
+            signatures: Mutex::new(load_list(&data_dir, "signatures.json")?),
+            drafts: Mutex::new(load_list(&data_dir, "drafts.json")?),
+            rules: Mutex::new(load_list(&data_dir, "rules.json")?),
+            sieve_scripts: Mutex::new(load_list(&data_dir, "sieve_scripts.json")?),
+            corrections: Mutex::new(load_list(&data_dir, "corrections.json")?),
+            categories: Mutex::new(load_list(&data_dir, "categories.json")?),
+            deleted_accounts: Mutex::new(load_list(&data_dir, "deleted_accounts.json")?),
+            reminders: Mutex::new(load_list(&data_dir, "reminders.json")?),
+            classification_cache: Mutex::new(classification_cache),
+            local_model: Mutex::new(local_model),
+            last_digest: Mutex::new(last_digest),
+            last_quarantine_digest: Mutex::new(last_quarantine_digest),
+            ai_usage: Mutex::new(load_list(&data_dir, "ai_usage.json")?),
+            undo: Mutex::new(VecDeque::new()),
+            background_errors: Mutex::new(VecDeque::new()),
+            data_dir,
         })
     }
 
@@ -77,18 +218,571 @@ impl Store {
         Ok(())
     }
 
+    /// Moves an account to the deleted accounts, from where it can be
+    /// restored until it is purged.
     pub fn delete_account(&self, id: &str) -> Result<()> {
         let mut accounts = self.accounts.lock().unwrap();
-        accounts.retain(|a| a.id != id);
+        let Some(pos) = accounts.iter().position(|a| a.id == id) else {
+            return Ok(());
+        };
+        let account = accounts.remove(pos);
+        self.save_accounts(&accounts)?;
+        let mut deleted = self.deleted_accounts.lock().unwrap();
+        deleted.push(DeletedAccount {
+            account,
+            deleted_at: chrono::Utc::now().to_rfc3339(),
+        });
+        self.save_list("deleted_accounts.json", &deleted)
+    }
+
+    pub fn get_deleted_accounts(&self) -> Result<Vec<DeletedAccount>> {
+        let deleted = self.deleted_accounts.lock().unwrap();
+        Ok(deleted.clone())
+    }
+
+    /// Brings a deleted account back, returning it if it was still there.
+    pub fn restore_account(&self, id: &str) -> Result<Option<EmailAccount>> {
+        let mut deleted = self.deleted_accounts.lock().unwrap();
+        let Some(pos) = deleted.iter().position(|d| d.account.id == id) else {
+            return Ok(None);
+        };
+        let account = deleted.remove(pos).account;
+        self.save_list("deleted_accounts.json", &deleted)?;
+        let mut accounts = self.accounts.lock().unwrap();
+        accounts.push(account.clone());
         self.save_accounts(&accounts)?;
+        Ok(Some(account))
+    }
+
+    /// Forgets a deleted account along with its sync state and health.
+    /// Its emails are left to the caller.
+    pub fn purge_account(&self, id: &str) -> Result<()> {
+        let mut deleted = self.deleted_accounts.lock().unwrap();
+        deleted.retain(|d| d.account.id != id);
+        self.save_list("deleted_accounts.json", &deleted)?;
+        self.set_sync_state(id, None)?;
+        let mut health = self.health.lock().unwrap();
+        if health.remove(id).is_some() {
+            self.save_health(&health)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_sync_state(&self, account_id: &str) -> Result<Option<String>> {
+        let sync_states = self.sync_states.lock().unwrap();
+        Ok(sync_states.get(account_id).cloned())
+    }
+
+    pub fn set_sync_state(&self, account_id: &str, state: Option<String>) -> Result<()> {
+        let mut sync_states = self.sync_states.lock().unwrap();
+        match state {
+            Some(state) => sync_states.insert(account_id.to_string(), state),
+            None => sync_states.remove(account_id),
+        };
+        let path = self.data_dir.join("sync_state.json");
+        fs::write(path, serde_json::to_string_pretty(&*sync_states)?)?;
+        Ok(())
+    }
+
+    pub fn get_health(&self) -> Result<HashMap<String, AccountHealth>> {
+        let health = self.health.lock().unwrap();
+        Ok(health.clone())
+    }
+
+    pub fn record_health_check(&self, report: HealthReport) -> Result<()> {
+        let account_id = report.account_id.clone();
+        self.update_health(&account_id, |entry| entry.last_check = Some(report))
+    }
+
+    /// Records the outcome of a sync; `None` clears a previous sync error.
+    pub fn record_sync_result(&self, account_id: &str, error: Option<String>) -> Result<()> {
+        {
+            // Avoid rewriting the file on every successful sync
+            let health = self.health.lock().unwrap();
+            let current = health.get(account_id).and_then(|h| h.last_sync_error.as_ref());
+            if current == error.as_ref() {
+                return Ok(());
+            }
+        }
+        self.update_health(account_id, |entry| entry.last_sync_error = error)
+    }
+
+    fn update_health(&self, account_id: &str, update: impl FnOnce(&mut AccountHealth)) -> Result<()> {
+        let mut health = self.health.lock().unwrap();
+        let entry = health
+            .entry(account_id.to_string())
+            .or_insert_with(|| AccountHealth {
+                account_id: account_id.to_string(),
+                ok: true,
+                last_check: None,
+                last_sync_error: None,
+                updated_at: String::new(),
+            });
+        update(entry);
+        entry.ok = entry.last_sync_error.is_none()
+            && entry.last_check.as_ref().is_none_or(|c| c.ok);
+        entry.updated_at = chrono::Utc::now().to_rfc3339();
+        self.save_health(&health)?;
+        Ok(())
+    }
+
+    pub fn get_templates(&self) -> Result<Vec<Template>> {
+        let templates = self.templates.lock().unwrap();
+        Ok(templates.clone())
+    }
+
+    pub fn add_template(&self, template: Template) -> Result<()> {
+        let mut templates = self.templates.lock().unwrap();
+        templates.push(template);
+        self.save_list("templates.json", &templates)
+    }
+
+    pub fn update_template(&self, id: &str, template: Template) -> Result<()> {
+        let mut templates = self.templates.lock().unwrap();
+        if let Some(pos) = templates.iter().position(|t| t.id == id) {
+            templates[pos] = template;
+            self.save_list("templates.json", &templates)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_template(&self, id: &str) -> Result<()> {
+        let mut templates = self.templates.lock().unwrap();
+        templates.retain(|t| t.id != id);
+        self.save_list("templates.json", &templates)
+    }
+
+    pub fn get_categories(&self) -> Result<Vec<CategoryDefinition>> {
+        let categories = self.categories.lock().unwrap();
+        Ok(categories.clone())
+    }
+
+    pub fn add_category(&self, category: CategoryDefinition) -> Result<()> {
+        let mut categories = self.categories.lock().unwrap();
+        categories.push(category);
+        self.save_list("categories.json", &categories)
+    }
+
+    pub fn update_category(&self, id: &str, category: CategoryDefinition) -> Result<()> {
+        let mut categories = self.categories.lock().unwrap();
+        if let Some(pos) = categories.iter().position(|c| c.id == id) {
+            categories[pos] = category;
+            self.save_list("categories.json", &categories)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_category(&self, id: &str) -> Result<()> {
+        let mut categories = self.categories.lock().unwrap();
+        categories.retain(|c| c.id != id);
+        self.save_list("categories.json", &categories)
+    }
+
+    pub fn get_signatures(&self) -> Result<Vec<Signature>> {
+        let signatures = self.signatures.lock().unwrap();
+        Ok(signatures.clone())
+    }
+
+    pub fn add_signature(&self, signature: Signature) -> Result<()> {
+        let mut signatures = self.signatures.lock().unwrap();
+        signatures.push(signature);
+        self.save_list("signatures.json", &signatures)
+    }
+
+    pub fn update_signature(&self, id: &str, signature: Signature) -> Result<()> {
+        let mut signatures = self.signatures.lock().unwrap();
+        if let Some(pos) = signatures.iter().position(|s| s.id == id) {
+            signatures[pos] = signature;
+            self.save_list("signatures.json", &signatures)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_signature(&self, id: &str) -> Result<()> {
+        let mut signatures = self.signatures.lock().unwrap();
+        signatures.retain(|s| s.id != id);
+        self.save_list("signatures.json", &signatures)
+    }
+
+    pub fn get_drafts(&self) -> Result<Vec<Draft>> {
+        let drafts = self.drafts.lock().unwrap();
+        Ok(drafts.clone())
+    }
+
+    pub fn add_draft(&self, draft: Draft) -> Result<()> {
+        let mut drafts = self.drafts.lock().unwrap();
+        drafts.insert(0, draft);
+        self.save_list("drafts.json", &drafts)
+    }
+
+    pub fn update_draft(&self, id: &str, draft: Draft) -> Result<()> {
+        let mut drafts = self.drafts.lock().unwrap();
+        if let Some(pos) = drafts.iter().position(|d| d.id == id) {
+            drafts[pos] = draft;
+            self.save_list("drafts.json", &drafts)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_draft(&self, id: &str) -> Result<()> {
+        let mut drafts = self.drafts.lock().unwrap();
+        drafts.retain(|d| d.id != id);
+        self.save_list("drafts.json", &drafts)
+    }
+
+    pub fn get_rules(&self) -> Result<Vec<Rule>> {
+        let rules = self.rules.lock().unwrap();
+        Ok(rules.clone())
+    }
+
+    pub fn add_rule(&self, rule: Rule) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap();
+        rules.push(rule);
+        self.save_list("rules.json", &rules)
+    }
+
+    pub fn update_rule(&self, id: &str, rule: Rule) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap();
+        if let Some(pos) = rules.iter().position(|r| r.id == id) {
+            rules[pos] = rule;
+            self.save_list("rules.json", &rules)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_rule(&self, id: &str) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap();
+        rules.retain(|r| r.id != id);
+        self.save_list("rules.json", &rules)
+    }
+
+    /// Puts the rules in the order of `ids`; rules not listed keep their
+    /// relative order after the listed ones.
+    pub fn reorder_rules(&self, ids: &[String]) -> Result<()> {
+        let mut rules = self.rules.lock().unwrap();
+        rules.sort_by_key(|r| ids.iter().position(|id| id == &r.id).unwrap_or(usize::MAX));
+        self.save_list("rules.json", &rules)
+    }
+
+    pub fn get_sieve_scripts(&self) -> Result<Vec<SieveScript>> {
+        let scripts = self.sieve_scripts.lock().unwrap();
+        Ok(scripts.clone())
+    }
+
+    pub fn add_sieve_script(&self, script: SieveScript) -> Result<()> {
+        let mut scripts = self.sieve_scripts.lock().unwrap();
+        scripts.push(script);
+        self.save_list("sieve_scripts.json", &scripts)
+    }
+
+    pub fn update_sieve_script(&self, id: &str, script: SieveScript) -> Result<()> {
+        let mut scripts = self.sieve_scripts.lock().unwrap();
+        if let Some(pos) = scripts.iter().position(|s| s.id == id) {
+            scripts[pos] = script;
+            self.save_list("sieve_scripts.json", &scripts)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_sieve_script(&self, id: &str) -> Result<()> {
+        let mut scripts = self.sieve_scripts.lock().unwrap();
+        scripts.retain(|s| s.id != id);
+        self.save_list("sieve_scripts.json", &scripts)
+    }
+
+    /// Marks `name` as the account's active script and all others inactive.
+    pub fn set_active_sieve_script(&self, account_id: &str, name: Option<&str>) -> Result<()> {
+        let mut scripts = self.sieve_scripts.lock().unwrap();
+        for script in scripts.iter_mut().filter(|s| s.account_id == account_id) {
+            script.active = Some(script.name.as_str()) == name;
+        }
+        self.save_list("sieve_scripts.json", &scripts)
+    }
+
+    /// Remembers how to undo an operation. Only the most recent ones are kept.
+    pub fn push_undo(&self, description: String, emails: Vec<Email>, account_id: Option<String>) {
+        let mut undo = self.undo.lock().unwrap();
+        undo.push_back(UndoEntry {
+            operation: UndoOperation {
+                id: uuid::Uuid::new_v4().to_string(),
+                description,
+                created_at: chrono::Utc::now().to_rfc3339(),
+                email_count: emails.len(),
+            },
+            emails,
+            account_id,
+        });
+        while undo.len() > UNDO_LIMIT {
+            undo.pop_front();
+        }
+    }
+
+    pub fn pop_undo(&self) -> Option<UndoEntry> {
+        self.undo.lock().unwrap().pop_back()
+    }
+
+    /// The operations that can be undone, most recent first.
+    pub fn get_undo_history(&self) -> Vec<UndoOperation> {
+        let undo = self.undo.lock().unwrap();
+        undo.iter().rev().map(|e| e.operation.clone()).collect()
+    }
+
+    /// Keeps a background failure for the app to show; only the most recent
+    /// ones are kept. A repeated failure is moved to the end instead of
+    /// being kept twice. Returns whether it is new.
+    pub fn add_background_error(&self, error: BackgroundError) -> bool {
+        let mut errors = self.background_errors.lock().unwrap();
+        let previous = errors
+            .iter()
+            .position(|e| e.account_id == error.account_id && e.message == error.message);
+        if let Some(pos) = previous {
+            errors.remove(pos);
+        }
+        errors.push_back(error);
+        while errors.len() > BACKGROUND_ERROR_LIMIT {
+            errors.pop_front();
+        }
+        previous.is_none()
+    }
+
+    /// Background failures, most recent first.
+    pub fn get_background_errors(&self) -> Vec<BackgroundError> {
+        let errors = self.background_errors.lock().unwrap();
+        errors.iter().rev().cloned().collect()
+    }
+
+    pub fn clear_background_errors(&self) {
+        self.background_errors.lock().unwrap().clear();
+    }
+
+    pub fn get_corrections(&self) -> Result<Vec<CategoryCorrection>> {
+        let corrections = self.corrections.lock().unwrap();
+        Ok(corrections.clone())
+    }
+
+    pub fn add_correction(&self, correction: CategoryCorrection) -> Result<()> {
+        let mut corrections = self.corrections.lock().unwrap();
+        corrections.push(correction);
+        self.save_list("corrections.json", &corrections)
+    }
+
+    pub fn get_reminders(&self) -> Result<Vec<FollowUpReminder>> {
+        let reminders = self.reminders.lock().unwrap();
+        Ok(reminders.clone())
+    }
+
+    pub fn add_reminder(&self, reminder: FollowUpReminder) -> Result<()> {
+        let mut reminders = self.reminders.lock().unwrap();
+        reminders.push(reminder);
+        self.save_list("reminders.json", &reminders)
+    }
+
+    pub fn delete_reminders(&self, ids: &[String]) -> Result<()> {
+        let mut reminders = self.reminders.lock().unwrap();
+        reminders.retain(|r| !ids.contains(&r.id));
+        self.save_list("reminders.json", &reminders)
+    }
+
+    pub fn cached_classification(&self, key: &str) -> Option<AIClassification> {
+        let cache = self.classification_cache.lock().unwrap();
+        cache.get(key).map(|c| c.classification.clone())
+    }
+
+    /// Adds classifications to the cache, dropping the oldest entries once
+    /// it is full.
+    pub fn cache_classifications(&self, entries: Vec<(String, AIClassification)>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut cache = self.classification_cache.lock().unwrap();
+        let cached_at = chrono::Utc::now().to_rfc3339();
+        for (key, classification) in entries {
+            cache.insert(key, CachedClassification {
+                classification,
+                cached_at: cached_at.clone(),
+            });
+        }
+        if cache.len() > CLASSIFICATION_CACHE_LIMIT {
+            let mut by_age: Vec<(String, String)> = cache
+                .iter()
+                .map(|(key, c)| (c.cached_at.clone(), key.clone()))
+                .collect();
+            by_age.sort_unstable();
+            for (_, key) in by_age.into_iter().take(cache.len() - CLASSIFICATION_CACHE_LIMIT) {
+                cache.remove(&key);
+            }
+        }
+        self.save_classification_cache(&cache)
+    }
+
+    /// Empties the classification cache. Returns how many entries it held.
+    pub fn clear_classification_cache(&self) -> Result<usize> {
+        let mut cache = self.classification_cache.lock().unwrap();
+        let count = cache.len();
+        cache.clear();
+        self.save_classification_cache(&cache)?;
+        Ok(count)
+    }
+
+    pub fn get_local_model(&self) -> Result<LocalModel> {
+        let model = self.local_model.lock().unwrap();
+        Ok(model.clone())
+    }
+
+    /// Changes the local classifier in place, e.g. to learn one more email.
+    pub fn update_local_model(&self, update: impl FnOnce(&mut LocalModel)) -> Result<()> {
+        let mut model = self.local_model.lock().unwrap();
+        update(&mut model);
+        let path = self.data_dir.join("local_model.json");
+        let data = serde_json::to_string(&*model)?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn get_last_digest(&self) -> Result<Option<String>> {
+        let last_digest = self.last_digest.lock().unwrap();
+        Ok(last_digest.clone())
+    }
+
+    pub fn set_last_digest(&self, date: &str) -> Result<()> {
+        let mut last_digest = self.last_digest.lock().unwrap();
+        *last_digest = Some(date.to_string());
+        let path = self.data_dir.join("ai_digest.json");
+        fs::write(path, serde_json::to_string(&*last_digest)?)?;
         Ok(())
     }
 
+    pub fn get_last_quarantine_digest(&self) -> Result<Option<DateTime<Utc>>> {
+        let last_digest = self.last_quarantine_digest.lock().unwrap();
+        Ok(*last_digest)
+    }
+
+    pub fn set_last_quarantine_digest(&self, time: DateTime<Utc>) -> Result<()> {
+        let mut last_digest = self.last_quarantine_digest.lock().unwrap();
+        *last_digest = Some(time);
+        let path = self.data_dir.join("quarantine_digest.json");
+        fs::write(path, serde_json::to_string(&*last_digest)?)?;
+        Ok(())
+    }
+
+    pub fn get_ai_usage(&self) -> Result<Vec<AIUsageRecord>> {
+        let usage = self.ai_usage.lock().unwrap();
+        Ok(usage.clone())
+    }
+
+    /// Adds usage to the records of the same day, provider, model and
+    /// account, and drops records past their retention.
+    pub fn record_ai_usage(&self, records: Vec<AIUsageRecord>) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut usage = self.ai_usage.lock().unwrap();
+        for record in records {
+            let existing = usage.iter_mut().find(|r| {
+                r.date == record.date
+                    && r.provider == record.provider
+                    && r.model == record.model
+                    && r.account_id == record.account_id
+            });
+            match existing {
+                Some(existing) => {
+                    existing.requests += record.requests;
+                    existing.prompt_tokens += record.prompt_tokens;
+                    existing.completion_tokens += record.completion_tokens;
+                }
+                None => usage.push(record),
+            }
+        }
+        let cutoff = (chrono::Utc::now() - chrono::Duration::days(AI_USAGE_RETENTION_DAYS))
+            .format("%Y-%m-%d")
+            .to_string();
+        usage.retain(|r| r.date >= cutoff);
+        self.save_list("ai_usage.json", &usage)
+    }
+
     pub fn get_emails(&self) -> Result<Vec<Email>> {
         let emails = self.emails.lock().unwrap();
         Ok(emails.clone())
     }
 
+    pub fn query_emails(&self, query: &EmailQuery) -> Result<Vec<Email>> {
+        let emails = self.emails.lock().unwrap();
+        Ok(emails.iter().filter(|e| query.matches(e)).cloned().collect())
+    }
+
+    /// Message-IDs already stored for an account, used to skip duplicates on import.
+    pub fn message_ids(&self, account_id: &str) -> Result<HashSet<String>> {
+        let emails = self.emails.lock().unwrap();
+        Ok(emails
+            .iter()
+            .filter(|e| e.account_id == account_id)
+            .filter_map(|e| e.message_id.clone())
+            .collect())
+    }
+
+    /// Ids of an account's stored emails that have no Message-ID.
+    pub fn ids_without_message_id(&self, account_id: &str) -> Result<Vec<String>> {
+        let emails = self.emails.lock().unwrap();
+        Ok(emails
+            .iter()
+            .filter(|e| e.account_id == account_id && e.message_id.is_none())
+            .map(|e| e.id.clone())
+            .collect())
+    }
+
+    /// The stored emails among `ids`, in storage order.
+    pub fn get_emails_by_id(&self, ids: &[String]) -> Result<Vec<Email>> {
+        let ids: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+        let emails = self.emails.lock().unwrap();
+        Ok(emails.iter().filter(|e| ids.contains(e.id.as_str())).cloned().collect())
+    }
+
+    pub fn get_email(&self, id: &str) -> Result<Option<Email>> {
+        let emails = self.emails.lock().unwrap();
+        Ok(emails.iter().find(|e| e.id == id).cloned())
+    }
+
+    /// Remote ids of the messages stored for an account.
+    pub fn remote_ids(&self, account_id: &str) -> Result<HashSet<String>> {
+        let emails = self.emails.lock().unwrap();
+        Ok(emails
+            .iter()
+            .filter(|e| e.account_id == account_id)
+            .filter_map(|e| e.remote_id.clone())
+            .collect())
+    }
+
+    /// Applies flag changes and removals reported by an account's backend.
+    pub fn apply_remote_changes(
+        &self,
+        account_id: &str,
+        flag_updates: &[RemoteFlags],
+        removed: &[String],
+    ) -> Result<()> {
+        if flag_updates.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+        let removed: HashSet<&str> = removed.iter().map(|r| r.as_str()).collect();
+        let updates: HashMap<&str, &RemoteFlags> = flag_updates
+            .iter()
+            .map(|u| (u.remote_id.as_str(), u))
+            .collect();
+
+        let mut emails = self.emails.lock().unwrap();
+        emails.retain(|e| {
+            e.account_id != account_id
+                || !e.remote_id.as_deref().is_some_and(|r| removed.contains(r))
+        });
+        for email in emails.iter_mut().filter(|e| e.account_id == account_id) {
+            if let Some(update) = email.remote_id.as_deref().and_then(|r| updates.get(r)) {
+                email.is_read = update.is_read;
+                email.is_starred = update.is_starred;
+            }
+        }
+        self.save_emails(&emails)?;
+        Ok(())
+    }
+
     pub fn add_email(&self, email: Email) -> Result<()> {
         let mut emails = self.emails.lock().unwrap();
         emails.insert(0, email);
@@ -96,13 +790,14 @@ impl Store {
         Ok(())
     }
 
+    /// Adds the emails not stored yet in front of the others, in reverse
+    /// order, so mail fetched oldest first ends up newest first.
     pub fn add_emails(&self, new_emails: Vec<Email>) -> Result<()> {
         let mut emails = self.emails.lock().unwrap();
-        for email in new_emails {
-            if !emails.iter().any(|e| e.id == email.id) {
-                emails.insert(0, email);
-            }
-        }
+        let mut ids: HashSet<String> = emails.iter().map(|e| e.id.clone()).collect();
+        let mut added: Vec<Email> = new_emails.into_iter().filter(|e| ids.insert(e.id.clone())).collect();
+        added.reverse();
+        emails.splice(0..0, added);
         self.save_emails(&emails)?;
         Ok(())
     }
@@ -116,13 +811,74 @@ impl Store {
         Ok(())
     }
 
-    pub fn delete_email(&self, id: &str) -> Result<()> {
+    /// Replaces several emails at once, writing the file only once.
+    pub fn update_emails(&self, updated: Vec<Email>) -> Result<()> {
+        if updated.is_empty() {
+            return Ok(());
+        }
+        let mut updated: HashMap<String, Email> =
+            updated.into_iter().map(|e| (e.id.clone(), e)).collect();
         let mut emails = self.emails.lock().unwrap();
-        emails.retain(|e| e.id != id);
+        for email in emails.iter_mut() {
+            if let Some(new) = updated.remove(&email.id) {
+                *email = new;
+            }
+        }
         self.save_emails(&emails)?;
         Ok(())
     }
 
+    /// Replaces emails and moves them to the top, as if they just arrived.
+    pub fn resurface_emails(&self, updated: Vec<Email>) -> Result<()> {
+        if updated.is_empty() {
+            return Ok(());
+        }
+        let mut emails = self.emails.lock().unwrap();
+        emails.retain(|e| !updated.iter().any(|u| u.id == e.id));
+        emails.splice(0..0, updated);
+        self.save_emails(&emails)?;
+        Ok(())
+    }
+
+    pub fn delete_emails(&self, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let ids: HashSet<&str> = ids.iter().map(|id| id.as_str()).collect();
+        let mut emails = self.emails.lock().unwrap();
+        emails.retain(|e| !ids.contains(e.id.as_str()));
+        self.save_emails(&emails)?;
+        for id in ids {
+            self.delete_raw(id)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the original RFC 822 source of an email, byte for byte.
+    pub fn save_raw(&self, id: &str, raw: &[u8]) -> Result<()> {
+        let dir = self.data_dir.join("raw");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(format!("{}.eml", id)), raw)?;
+        Ok(())
+    }
+
+    pub fn load_raw(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.data_dir.join("raw").join(format!("{}.eml", id));
+        if path.exists() {
+            Ok(Some(fs::read(path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn delete_raw(&self, id: &str) -> Result<()> {
+        let path = self.data_dir.join("raw").join(format!("{}.eml", id));
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
     pub fn get_settings(&self) -> Result<AppSettings> {
         let settings = self.settings.lock().unwrap();
         Ok(settings.clone())
@@ -149,6 +905,27 @@ impl Store {
         Ok(())
     }
 
+    fn save_health(&self, health: &HashMap<String, AccountHealth>) -> Result<()> {
+        let path = self.data_dir.join("health.json");
+        let data = serde_json::to_string_pretty(health)?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn save_classification_cache(&self, cache: &HashMap<String, CachedClassification>) -> Result<()> {
+        let path = self.data_dir.join("classification_cache.json");
+        let data = serde_json::to_string_pretty(cache)?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn save_list<T: Serialize>(&self, file: &str, items: &[T]) -> Result<()> {
+        let path = self.data_dir.join(file);
+        let data = serde_json::to_string_pretty(items)?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
     fn save_settings(&self, settings: &AppSettings) -> Result<()> {
         let path = self.data_dir.join("settings.json");
         let data = serde_json::to_string_pretty(settings)?;
@@ -156,3 +933,192 @@ impl Store {
         Ok(())
     }
 }
+
+fn load_list<T: DeserializeOwned>(data_dir: &Path, file: &str) -> Result<Vec<T>> {
+    let path = data_dir.join(file);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data).unwrap_or_default())
+}
+
+/// Account config as saved before incoming and outgoing servers were split.
+#[derive(Deserialize)]
+pub struct LegacyAccountConfig {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub oauth_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub path: Option<String>,
+}
+
+/// Converts a legacy account config, given the account's address. Which
+/// servers a provider uses is up to the email layer, see
+/// `autoconfig::migrate_legacy_config`.
+pub type AccountMigration = fn(LegacyAccountConfig, &str) -> AccountConfig;
+
+/// Parses accounts.json, converting accounts in the legacy format. Returns
+/// whether any account was converted, so the file can be rewritten.
+fn load_accounts(data: &str, migrate: AccountMigration) -> (Vec<EmailAccount>, bool) {
+    let Ok(mut values) = serde_json::from_str::<Vec<serde_json::Value>>(data) else {
+        return (Vec::new(), false);
+    };
+
+    let mut migrated = false;
+    for value in &mut values {
+        if value["config"].get("incoming").is_some() {
+            continue;
+        }
+        let Ok(legacy) = serde_json::from_value::<LegacyAccountConfig>(value["config"].clone()) else {
+            continue;
+        };
+        let email = value["email"].as_str().unwrap_or_default().to_string();
+        if let Ok(config) = serde_json::to_value(migrate(legacy, &email)) {
+            value["config"] = config;
+            migrated = true;
+        }
+    }
+
+    match serde_json::from_value(serde_json::Value::Array(values)) {
+        Ok(accounts) => (accounts, migrated),
+        Err(_) => (Vec::new(), false),
+    }
+}
+
+impl EmailQuery {
+    pub fn matches(&self, email: &Email) -> bool {
+        if let Some(account_id) = &self.account_id {
+            if &email.account_id != account_id {
+                return false;
+            }
+        }
+        if let Some(folder) = &self.folder {
+            if email.folder.as_deref().unwrap_or(INBOX) != folder {
+                return false;
+            }
+        }
+        if let Some(label) = &self.label {
+            if !email.labels.iter().flatten().any(|l| l == label) {
+                return false;
+            }
+        }
+        if let Some(search) = &self.search {
+            let needle = search.to_lowercase();
+            let found = [
+                Some(&email.subject),
+                Some(&email.body),
+                Some(&email.from.address),
+                email.from.name.as_ref(),
+            ]
+            .iter()
+            .flatten()
+            .any(|h| h.to_lowercase().contains(&needle));
+            if !found {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::autoconfig::migrate_legacy_config;
+    use crate::types::{AuthMethod, SecurityMode};
+
+    const LEGACY: &str = r#"[{
+        "id": "acc",
+        "name": "Gmail",
+        "email": "me@gmail.com",
+        "protocol": "imap",
+        "config": {"host": "imap.gmail.com", "port": 993, "username": "me@gmail.com", "password": "secret"}
+    }]"#;
+
+    #[test]
+    fn legacy_accounts_get_incoming_and_outgoing_servers() {
+        let (accounts, migrated) = load_accounts(LEGACY, migrate_legacy_config);
+        assert!(migrated);
+        let config = &accounts[0].config;
+        assert_eq!(config.incoming.host.as_deref(), Some("imap.gmail.com"));
+        assert_eq!(config.incoming.security, SecurityMode::Tls);
+        assert_eq!(config.incoming.auth, AuthMethod::Password);
+        let outgoing = config.outgoing.as_ref().unwrap();
+        assert_eq!(outgoing.host.as_deref(), Some("smtp.gmail.com"));
+        assert_eq!((outgoing.port, outgoing.security), (Some(465), SecurityMode::Tls));
+        assert_eq!(outgoing.password.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn plain_ports_migrate_to_starttls() {
+        let data = LEGACY.replace("993", "143").replace("gmail.com\",\n", "example.org\",\n");
+        let (accounts, _) = load_accounts(&data, migrate_legacy_config);
+        assert_eq!(accounts[0].config.incoming.security, SecurityMode::StartTls);
+    }
+
+    #[test]
+    fn migrated_accounts_are_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("accounts.json"), LEGACY).unwrap();
+        let store = Store::new(dir.path().to_path_buf(), migrate_legacy_config).unwrap();
+        let saved = fs::read_to_string(dir.path().join("accounts.json")).unwrap();
+        assert_ne!(saved, LEGACY);
+
+        let (accounts, migrated) = load_accounts(&saved, |_, _| panic!("migrated twice"));
+        assert!(!migrated);
+        assert_eq!(
+            serde_json::to_value(&accounts).unwrap(),
+            serde_json::to_value(store.get_accounts().unwrap()).unwrap()
+        );
+    }
+
+    fn store() -> (tempfile::TempDir, Store) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path().to_path_buf(), migrate_legacy_config).unwrap();
+        (dir, store)
+    }
+
+    fn email(id: &str) -> Email {
+        let raw = format!("Message-ID: <{}@example.com>\r\nSubject: {}\r\n\r\nHi\r\n", id, id);
+        let mut email = crate::email::mime::parse_email(raw.as_bytes(), "acc").unwrap();
+        email.id = id.to_string();
+        email
+    }
+
+    #[test]
+    fn undo_restores_the_previous_state() {
+        let (_dir, store) = store();
+        store.add_emails(vec![email("a"), email("b")]).unwrap();
+
+        let before = store.get_emails_by_id(&["a".to_string()]).unwrap();
+        let mut changed = before.clone();
+        changed[0].is_read = true;
+        changed[0].folder = Some("Archive".to_string());
+        store.push_undo("Archive 1 email".to_string(), before, None);
+        store.update_emails(changed).unwrap();
+
+        let entry = store.pop_undo().unwrap();
+        assert_eq!(entry.operation.description, "Archive 1 email");
+        assert_eq!(entry.operation.email_count, 1);
+        store.update_emails(entry.emails).unwrap();
+        let restored = store.get_email("a").unwrap().unwrap();
+        assert!(!restored.is_read);
+        assert_eq!(restored.folder, None);
+        assert!(store.pop_undo().is_none());
+    }
+
+    #[test]
+    fn undo_history_is_capped() {
+        let (_dir, store) = store();
+        for i in 0..UNDO_LIMIT + 5 {
+            store.push_undo(format!("Operation {}", i), Vec::new(), None);
+        }
+        let history = store.get_undo_history();
+        assert_eq!(history.len(), UNDO_LIMIT);
+        assert_eq!(history[0].description, format!("Operation {}", UNDO_LIMIT + 4));
+        assert_eq!(history[UNDO_LIMIT - 1].description, "Operation 5");
+    }
+}
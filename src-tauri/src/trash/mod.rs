@@ -0,0 +1,90 @@
+use crate::storage::TRASH;
+use crate::types::{AppSettings, DeletedAccount, Email, Trashed};
+use chrono::{DateTime, Duration, Utc};
+
+/// Days deleted emails and accounts are kept when no retention is configured.
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+pub fn retention_days(settings: &AppSettings) -> u32 {
+    settings.trash_days.unwrap_or(DEFAULT_RETENTION_DAYS)
+}
+
+/// Moves an email into the trash, remembering where it was.
+pub fn trash(email: &mut Email) {
+    email.trashed = Some(Trashed {
+        since: Utc::now().to_rfc3339(),
+        folder: email.folder.take(),
+    });
+    email.folder = Some(TRASH.to_string());
+}
+
+/// Takes an email out of the trash, back into its original folder.
+pub fn restore(email: &mut Email) {
+    if let Some(trashed) = email.trashed.take() {
+        email.folder = trashed.folder;
+    }
+}
+
+pub fn is_expired(email: &Email, retention_days: u32, now: DateTime<Utc>) -> bool {
+    email
+        .trashed
+        .as_ref()
+        .is_some_and(|t| expired(&t.since, retention_days, now))
+}
+
+pub fn is_account_expired(deleted: &DeletedAccount, retention_days: u32, now: DateTime<Utc>) -> bool {
+    expired(&deleted.deleted_at, retention_days, now)
+}
+
+fn expired(since: &str, retention_days: u32, now: DateTime<Utc>) -> bool {
+    DateTime::parse_from_rfc3339(since)
+        .is_ok_and(|since| since.with_timezone(&Utc) + Duration::days(retention_days.into()) <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::mime;
+
+    fn email(folder: Option<&str>) -> Email {
+        let mut email = mime::parse_email(b"From: ann@example.com\r\nSubject: Hi\r\n\r\nHi\r\n", "acc").unwrap();
+        email.folder = folder.map(|f| f.to_string());
+        email
+    }
+
+    #[test]
+    fn restores_into_the_original_folder() {
+        for folder in [None, Some("Archive")] {
+            let mut email = email(folder);
+            trash(&mut email);
+            assert_eq!(email.folder.as_deref(), Some(TRASH));
+            restore(&mut email);
+            assert_eq!(email.folder.as_deref(), folder);
+            assert!(email.trashed.is_none());
+        }
+    }
+
+    #[test]
+    fn restoring_untrashed_emails_changes_nothing() {
+        let mut email = email(Some("Archive"));
+        restore(&mut email);
+        assert_eq!(email.folder.as_deref(), Some("Archive"));
+    }
+
+    #[test]
+    fn expires_after_the_retention_days() {
+        let mut email = email(None);
+        let now = Utc::now();
+        assert!(!is_expired(&email, 0, now));
+
+        trash(&mut email);
+        let since = DateTime::parse_from_rfc3339(&email.trashed.as_ref().unwrap().since).unwrap();
+        let expiry = since.with_timezone(&Utc) + Duration::days(30);
+        assert!(!is_expired(&email, 30, expiry - Duration::seconds(1)));
+        assert!(is_expired(&email, 30, expiry));
+        assert!(is_expired(&email, 0, Utc::now()));
+
+        email.trashed.as_mut().unwrap().since = "not a date".to_string();
+        assert!(!is_expired(&email, 0, now));
+    }
+}
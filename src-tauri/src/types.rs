@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmailAccount {
@@ -10,6 +11,22 @@ pub struct EmailAccount {
     pub protocol: Protocol,
     pub provider: Option<Provider>,
     pub config: AccountConfig,
+    /// Additional addresses mail can be sent as, e.g. aliases of the mailbox.
+    pub identities: Option<Vec<Identity>>,
+    /// Id of the `Signature` for mail sent from the account's own address.
+    pub signature_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub id: String,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub reply_to: Option<String>,
+    /// Id of the `Signature` for mail sent from this identity.
+    pub signature_id: Option<String>,
+    /// Dedicated SMTP server; the account's outgoing server is used otherwise.
+    pub smtp: Option<ServerConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +35,9 @@ pub enum Protocol {
     Imap,
     Pop3,
     OAuth2,
+    Local,
+    Maildir,
+    Jmap,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,12 +50,140 @@ pub enum Provider {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AccountConfig {
+    /// Server mail is read from (IMAP, POP3 or JMAP).
+    pub incoming: ServerConfig,
+    /// SMTP server mail is sent through; JMAP accounts send through `incoming`.
+    pub outgoing: Option<ServerConfig>,
+    /// ManageSieve server; defaults to the incoming host on port 4190.
+    pub sieve: Option<ServerConfig>,
+    pub oauth_token: Option<String>,
+    pub refresh_token: Option<String>,
+    /// Root directory of a Maildir account.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerConfig {
     pub host: Option<String>,
     pub port: Option<u16>,
     pub username: Option<String>,
     pub password: Option<String>,
-    pub oauth_token: Option<String>,
-    pub refresh_token: Option<String>,
+    pub security: SecurityMode,
+    pub auth: AuthMethod,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    /// Username and password (IMAP LOGIN, POP3 USER/PASS, SMTP AUTH PLAIN).
+    #[default]
+    Password,
+    /// XOAUTH2 with the account's OAuth token.
+    OAuth2,
+    /// No authentication, e.g. a local SMTP relay.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityMode {
+    /// Implicit TLS from the first byte (IMAPS 993, POP3S 995, SMTPS 465).
+    #[default]
+    Tls,
+    /// Plain connection upgraded with STARTTLS/STLS.
+    StartTls,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerProtocol {
+    Imap,
+    Pop3,
+    Smtp,
+    Jmap,
+}
+
+/// A discovered server that an account could be configured with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerSettings {
+    pub protocol: ServerProtocol,
+    pub host: String,
+    pub port: u16,
+    pub security: SecurityMode,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+    Preset,
+    Autoconfig,
+    Autodiscover,
+    Srv,
+}
+
+/// Proposed account settings for an email address, best candidates first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSuggestion {
+    pub email: String,
+    pub provider: Provider,
+    pub source: ConfigSource,
+    pub incoming: Vec<ServerSettings>,
+    pub outgoing: Vec<ServerSettings>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStage {
+    Dns,
+    Connect,
+    Tls,
+    Greeting,
+    Auth,
+    Folders,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageResult {
+    pub stage: HealthStage,
+    pub ok: bool,
+    pub detail: String,
+    /// What the server sent during this stage, one line per response line.
+    pub server_response: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Outcome of connecting to one of an account's servers, stopping at the first failed stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerCheck {
+    pub protocol: ServerProtocol,
+    pub host: String,
+    pub port: u16,
+    pub security: SecurityMode,
+    pub stages: Vec<StageResult>,
+    pub failed_stage: Option<HealthStage>,
+    pub folders: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub account_id: String,
+    pub ok: bool,
+    pub checked_at: String,
+    pub servers: Vec<ServerCheck>,
+    /// Problems found before any server could be tried, e.g. a missing host.
+    pub error: Option<String>,
+}
+
+/// Last known state of an account, from its latest check and its latest sync.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountHealth {
+    pub account_id: String,
+    pub ok: bool,
+    pub last_check: Option<HealthReport>,
+    pub last_sync_error: Option<String>,
+    pub updated_at: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,6 +195,7 @@ pub struct Email {
     pub to: Vec<EmailAddress>,
     pub cc: Option<Vec<EmailAddress>>,
     pub bcc: Option<Vec<EmailAddress>>,
+    pub reply_to: Option<Vec<EmailAddress>>,
     pub date: String,
     pub body: String,
     pub html_body: Option<String>,
@@ -55,6 +204,249 @@ pub struct Email {
     pub is_starred: bool,
     pub labels: Option<Vec<String>>,
     pub ai_classification: Option<AIClassification>,
+    pub folder: Option<String>,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Option<Vec<String>>,
+    /// Identifier of the message on the account's backend, e.g. a Maildir unique name.
+    pub remote_id: Option<String>,
+    /// Set while the email is held in quarantine.
+    pub quarantine: Option<Quarantine>,
+    /// Set while the email is in the trash.
+    pub trashed: Option<Trashed>,
+    /// Set while the email is snoozed.
+    pub snooze: Option<Snooze>,
+    /// The AI's summary of this email, once asked for.
+    pub summary: Option<EmailSummary>,
+    /// The AI's summary of the thread this email is the newest message of.
+    pub thread_summary: Option<EmailSummary>,
+}
+
+/// What the AI made of an email or a thread, in its language.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailSummary {
+    pub summary: String,
+    pub action_items: Vec<String>,
+    pub deadlines: Vec<Deadline>,
+    /// The emails summarized, oldest first.
+    pub email_ids: Vec<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deadline {
+    pub description: String,
+    /// As the email puts it, e.g. "Friday 5pm" or "2024-07-01".
+    pub due: String,
+}
+
+/// Payload of the `ai-digest` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AIDigest {
+    pub created_at: String,
+    pub entries: Vec<DigestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestEntry {
+    pub email_id: String,
+    pub account_id: String,
+    pub subject: String,
+    pub from: EmailAddress,
+    pub summary: String,
+    pub action_items: Vec<String>,
+}
+
+/// Until when an email is hidden in the snoozed folder. It then returns to
+/// the top of its folder as unread.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snooze {
+    /// RFC 3339 time the email wakes up.
+    pub until: String,
+    /// Folder the email returns to.
+    pub folder: Option<String>,
+}
+
+/// Reminds about a sent message if no reply has arrived by `remind_at`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowUpReminder {
+    pub id: String,
+    pub account_id: String,
+    /// Message-ID of the sent message replies must refer to.
+    pub message_id: String,
+    /// The stored copy of the sent message, if there is one.
+    pub email_id: Option<String>,
+    pub subject: String,
+    pub to: Vec<EmailAddress>,
+    pub remind_at: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReminderKind {
+    /// A snoozed email woke up.
+    Snooze,
+    /// A sent message got no reply in time.
+    FollowUp,
+}
+
+/// Payload of the `reminder` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReminderNotification {
+    pub kind: ReminderKind,
+    pub account_id: String,
+    pub email_id: Option<String>,
+    pub subject: String,
+}
+
+/// When an email was moved to the trash and where it came from. It is
+/// deleted for good, also on the server, once the trash retention has passed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trashed {
+    /// RFC 3339 time the email was deleted.
+    pub since: String,
+    /// Folder the email is restored to.
+    pub folder: Option<String>,
+}
+
+/// An account deleted by the user, kept until the trash retention has passed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletedAccount {
+    pub account: EmailAccount,
+    pub deleted_at: String,
+}
+
+/// A recent operation that can be undone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoOperation {
+    pub id: String,
+    pub description: String,
+    pub created_at: String,
+    /// Number of emails the operation changed.
+    pub email_count: usize,
+}
+
+/// A failure of work running in the background, such as syncing or the
+/// scheduler, which has no caller to return it to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackgroundError {
+    /// `None` for failures not tied to an account.
+    pub account_id: Option<String>,
+    pub message: String,
+    /// RFC 3339 time of the latest occurrence.
+    pub occurred_at: String,
+}
+
+/// The emails a bulk operation applies to: a selection of ids, or
+/// everything a query matches, e.g. a search result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BulkSelection {
+    Ids { ids: Vec<String> },
+    Query { query: EmailQuery },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BulkAction {
+    Read { value: bool },
+    Star { value: bool },
+    /// Adds the label, or removes it when `value` is false.
+    Label { label: String, value: bool },
+    Move { folder: String },
+    Delete,
+    Reclassify,
+}
+
+/// Payload of the `bulk-progress` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkProgress {
+    pub operation_id: String,
+    pub processed: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkResult {
+    pub operation_id: String,
+    pub matched: usize,
+    pub updated: usize,
+    /// Emails left unchanged because their server or the AI failed.
+    pub failed: usize,
+}
+
+/// Why and since when an email is quarantined. It stays on the server until
+/// the retention period has passed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quarantine {
+    /// RFC 3339 time the email was quarantined.
+    pub since: String,
+    /// Folder the email is restored to.
+    pub folder: Option<String>,
+}
+
+/// Quarantined emails, e.g. those quarantined since the last digest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineDigest {
+    pub since: Option<String>,
+    /// All emails currently in quarantine, including older ones.
+    pub total: usize,
+    pub entries: Vec<QuarantineEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    pub email_id: String,
+    pub account_id: String,
+    pub subject: String,
+    pub from: EmailAddress,
+    pub quarantined_at: String,
+    /// When the email will be deleted, locally and on the server.
+    pub expires_at: String,
+}
+
+/// Reusable message text. Placeholders such as `{{sender.name}}`,
+/// `{{subject}}` and `{{date}}` are filled in from the message being replied to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Template {
+    pub id: String,
+    pub name: String,
+    pub subject: Option<String>,
+    pub body: String,
+    pub html_body: Option<String>,
+}
+
+/// A signature, used by the accounts and identities that refer to it by id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signature {
+    pub id: String,
+    pub name: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Draft {
+    pub id: String,
+    pub account_id: String,
+    pub identity_id: Option<String>,
+    /// Comma-separated recipients, as accepted by `send_email`.
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub html_body: Option<String>,
+    /// Id of the email this draft replies to.
+    pub in_reply_to: Option<String>,
+    pub updated_at: String,
+}
+
+/// Flag state of an already stored message as last seen on its backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteFlags {
+    pub remote_id: String,
+    pub is_read: bool,
+    pub is_starred: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,39 +464,374 @@ pub struct Attachment {
     pub content: Option<String>,
 }
 
+/// Selects a set of stored emails, e.g. a folder, a label or a search result.
+/// Every field that is set must match.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmailQuery {
+    pub account_id: Option<String>,
+    pub folder: Option<String>,
+    pub label: Option<String>,
+    pub search: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MboxFormat {
+    Mboxo,
+    #[default]
+    Mboxrd,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportProgress {
+    pub processed: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub failed: usize,
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+}
+
+/// A user-defined automation. Enabled rules are evaluated in list order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    /// Whether all conditions must match, or any of them. A rule without
+    /// conditions matches every email.
+    pub match_all: bool,
+    pub conditions: Vec<RuleCondition>,
+    pub actions: Vec<RuleAction>,
+    /// Skip the remaining rules for an email this rule matched.
+    pub stop_processing: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RuleCondition {
+    Sender(TextMatch),
+    /// Any of To, Cc and Bcc.
+    Recipients(TextMatch),
+    Subject(TextMatch),
+    Body(TextMatch),
+    Header(HeaderMatch),
+    Account { account_id: String },
+    Attachment { present: bool },
+    Category { category: Category },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextMatch {
+    pub operator: MatchOperator,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderMatch {
+    pub name: String,
+    pub operator: MatchOperator,
+    pub value: String,
+}
+
+/// Text comparisons ignore case, except for regular expressions.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchOperator {
+    Contains,
+    Equals,
+    StartsWith,
+    EndsWith,
+    Regex,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RuleAction {
+    Move { folder: String },
+    Label { label: String },
+    MarkRead,
+    Star,
+    Forward { to: String },
+    Delete,
+    Notify { message: Option<String> },
+}
+
+/// Rules that matched an email, as reported by a (dry) run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleMatch {
+    pub email_id: String,
+    pub subject: String,
+    pub rule_ids: Vec<String>,
+    pub actions: Vec<RuleAction>,
+}
+
+/// Payload of the `rule-notification` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleNotification {
+    pub rule_id: String,
+    pub email_id: String,
+    pub message: String,
+}
+
+/// A Sieve script kept locally, usually a copy of one on the account's
+/// ManageSieve server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SieveScript {
+    pub id: String,
+    pub account_id: String,
+    /// Name of the script on the server.
+    pub name: String,
+    pub content: String,
+    /// Whether this is the script the server runs.
+    pub active: bool,
+    pub updated_at: String,
+}
+
+/// A script as listed by a ManageSieve server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteSieveScript {
+    pub name: String,
+    pub active: bool,
+}
+
+/// What a Sieve script does with a message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SieveAction {
+    Keep { flags: Vec<String> },
+    FileInto { folder: String, flags: Vec<String> },
+    Redirect { address: String },
+    Discard,
+    Vacation {
+        to: String,
+        subject: String,
+        reason: String,
+        from: Option<String>,
+        days: u64,
+        /// Whether `reason` is a MIME entity rather than plain text.
+        mime: bool,
+    },
+}
+
+/// Outcome of testing a Sieve script against one stored email.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SieveTestResult {
+    pub email_id: String,
+    pub subject: String,
+    pub actions: Vec<SieveAction>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIClassification {
     pub category: Category,
     pub verification_code: Option<String>,
     pub verification_link: Option<String>,
     pub should_notify: bool,
+    /// Between 0 and 1; `None` when the model's reply had none.
+    pub confidence: Option<f32>,
+    /// Why the model chose the category.
+    pub reason: Option<String>,
 }
 
+/// A classification kept so the same message content is not sent to the
+/// AI again.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+pub struct CachedClassification {
+    pub classification: AIClassification,
+    pub cached_at: String,
+}
+
+/// Serialized as the category's id: the lowercase name of a built-in
+/// category, or the id of a user-defined one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum Category {
     Marketing,
     Important,
     Verification,
     Normal,
+    /// A user-defined category, by `CategoryDefinition::id`.
+    Custom(String),
+}
+
+/// A category the AI sorts mail into. The built-in categories always
+/// exist, with the ids marketing, important, verification and normal;
+/// users add their own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryDefinition {
+    pub id: String,
+    /// Shown to the AI as well as the user, e.g. "Invoices".
+    pub name: String,
+    /// Tells the AI which emails belong in the category.
+    pub description: String,
+    /// CSS color, e.g. `#3b82f6`.
+    pub color: Option<String>,
+    /// Notify about emails that get this category.
+    pub notify: bool,
+    /// Applied to emails that get this category, before the user's rules.
+    pub actions: Vec<RuleAction>,
+}
+
+/// On-device naive Bayes classifier learned from the user's corrections.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LocalModel {
+    /// Training emails per category id.
+    pub documents: HashMap<String, u32>,
+    /// Occurrences of each feature, per category id.
+    pub features: HashMap<String, HashMap<String, u32>>,
+    /// Occurrences of all features, per category id.
+    pub totals: HashMap<String, u64>,
+    pub trained_at: Option<String>,
+    /// Share of held-out corrections classified right at the last retraining.
+    pub accuracy: Option<f32>,
+}
+
+/// Outcome of retraining the local classifier from all corrections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainingReport {
+    /// Corrections the final model is trained on.
+    pub examples: usize,
+    /// Corrections set aside to measure accuracy.
+    pub held_out: usize,
+    /// `None` when there were too few corrections to hold any out.
+    pub accuracy: Option<f32>,
+    pub trained_at: String,
+}
+
+/// A category the user gave an email in place of the AI's, e.g. when
+/// restoring it from quarantine. Later emails from the same sender get it too.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryCorrection {
+    pub email_id: String,
+    pub sender: String,
+    pub subject: String,
+    pub category: Category,
+    pub created_at: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIConfig {
     pub enabled: bool,
     pub provider: AIProvider,
+    /// May be empty for local providers.
     pub api_key: String,
     pub api_endpoint: Option<String>,
+    /// Local providers use the first model the server lists if unset.
     pub model: Option<String>,
+    /// Quarantine marketing emails instead of showing them in the inbox.
     pub auto_delete: bool,
+    /// Days quarantined emails are kept before they are deleted; 30 if unset.
+    pub quarantine_days: Option<u32>,
+    /// Confidence (0 to 1) a classification needs before it triggers
+    /// automatic actions such as quarantine. Any confidence will do if unset.
+    pub min_confidence: Option<f32>,
+    /// Requests in flight at once per provider; 4 if unset.
+    pub max_concurrency: Option<u32>,
+    /// Requests started per minute per provider; 60 if unset.
+    pub requests_per_minute: Option<u32>,
+    /// Seconds before a request is abandoned; 30 if unset.
+    pub timeout_secs: Option<u64>,
+    /// Retries after rate limiting, server errors or timeouts; 3 if unset.
+    pub max_retries: Option<u32>,
+    /// Confidence the on-device classifier needs before its answer is used
+    /// instead of asking the AI; 0.9 if unset. Above 1 turns it off.
+    pub local_min_confidence: Option<f32>,
+    /// Summarize unread important mail once a day; off if unset.
+    pub daily_digest: Option<DigestDelivery>,
+    /// Local hour (0 to 23) from which the daily digest is made; 8 if unset.
+    pub digest_hour: Option<u32>,
+    /// Estimated cost in US dollars per calendar month (UTC) after which AI
+    /// classification pauses. No cap if unset.
+    pub monthly_budget: Option<f64>,
+    /// Prices that override or extend the built-in table, e.g. for a new
+    /// model or a self-hosted server that bills.
+    pub prices: Option<Vec<ModelPrice>>,
+}
+
+/// What a model costs, in US dollars per million tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    /// Matches model names starting with it, so `gpt-4o` covers
+    /// `gpt-4o-2024-08-06`; the longest match wins.
+    pub model: String,
+    pub prompt_per_million: f64,
+    pub completion_per_million: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
+pub enum DigestDelivery {
+    /// An `ai-digest` event the app shows as a notification.
+    Notification,
+    /// A message in the inbox of each account with important mail.
+    Message,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AIProvider {
     OpenAI,
     Anthropic,
     Gemini,
+    /// A local Ollama server, by default on `http://localhost:11434`.
+    Ollama,
+    /// A server speaking the OpenAI API, such as llama.cpp server or vLLM;
+    /// `api_endpoint` is its base URL, e.g. `http://localhost:8080/v1`.
+    OpenAICompatible,
+}
+
+/// Whether the AI backend answers, and which models it offers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AIHealth {
+    pub reachable: bool,
+    pub models: Vec<String>,
+    /// Whether the configured model is among `models`; `None` when no model
+    /// is configured or the provider cannot list its models.
+    pub model_available: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Tokens used with one provider and model for one account in a day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AIUsageRecord {
+    /// `YYYY-MM-DD`, in UTC.
+    pub date: String,
+    pub provider: AIProvider,
+    pub model: String,
+    /// `None` for requests not made for an account's emails.
+    pub account_id: Option<String>,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Usage with one provider and model for one account in a period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AIUsageSummary {
+    /// `YYYY-MM-DD` for a day, `YYYY-MM` for a month.
+    pub period: String,
+    pub provider: AIProvider,
+    pub model: String,
+    pub account_id: Option<String>,
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// In US dollars; `None` if the model's price is unknown.
+    pub estimated_cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AIUsageReport {
+    /// Newest first.
+    pub daily: Vec<AIUsageSummary>,
+    /// Newest first.
+    pub monthly: Vec<AIUsageSummary>,
+    pub monthly_budget: Option<f64>,
+    /// Estimated cost so far in the current month (UTC).
+    pub spent_this_month: f64,
+    /// Whether AI classification is paused until next month.
+    pub budget_exceeded: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,6 +839,8 @@ pub struct AppSettings {
     pub notifications: bool,
     pub ai_config: Option<AIConfig>,
     pub theme: Theme,
+    /// Days deleted emails and accounts are kept in the trash; 30 if unset.
+    pub trash_days: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
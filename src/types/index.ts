@@ -4,16 +4,200 @@ export interface EmailAccount {
   email: string;
   displayName?: string;
   tags?: string[];
-  protocol: 'imap' | 'pop3' | 'oauth2';
+  protocol: 'imap' | 'pop3' | 'oauth2' | 'local' | 'maildir' | 'jmap';
   provider?: 'gmail' | 'outlook' | 'other';
   config: {
-    host?: string;
-    port?: number;
-    username?: string;
-    password?: string;
+    incoming: ServerConfig;
+    outgoing?: ServerConfig;
+    sieve?: ServerConfig;
     oauthToken?: string;
     refreshToken?: string;
+    path?: string;
   };
+  identities?: Identity[];
+  signatureId?: string;
+}
+
+export interface Template {
+  id: string;
+  name: string;
+  subject?: string;
+  body: string;
+  htmlBody?: string;
+}
+
+export interface Signature {
+  id: string;
+  name: string;
+  text: string;
+  html?: string;
+}
+
+export interface Draft {
+  id: string;
+  accountId: string;
+  identityId?: string;
+  to: string;
+  subject: string;
+  body: string;
+  htmlBody?: string;
+  inReplyTo?: string;
+  updatedAt: string;
+}
+
+export type MatchOperator = 'contains' | 'equals' | 'startswith' | 'endswith' | 'regex';
+
+export interface TextMatch {
+  operator: MatchOperator;
+  value: string;
+}
+
+export type RuleCondition =
+  | ({ type: 'sender' | 'recipients' | 'subject' | 'body' } & TextMatch)
+  | ({ type: 'header'; name: string } & TextMatch)
+  | { type: 'account'; accountId: string }
+  | { type: 'attachment'; present: boolean }
+  | { type: 'category'; category: Category };
+
+export type RuleAction =
+  | { type: 'move'; folder: string }
+  | { type: 'label'; label: string }
+  | { type: 'markread' }
+  | { type: 'star' }
+  | { type: 'forward'; to: string }
+  | { type: 'delete' }
+  | { type: 'notify'; message?: string };
+
+export interface Rule {
+  id: string;
+  name: string;
+  enabled: boolean;
+  matchAll: boolean;
+  conditions: RuleCondition[];
+  actions: RuleAction[];
+  stopProcessing: boolean;
+}
+
+export interface RuleMatch {
+  emailId: string;
+  subject: string;
+  ruleIds: string[];
+  actions: RuleAction[];
+}
+
+export interface RuleNotification {
+  ruleId: string;
+  emailId: string;
+  message: string;
+}
+
+export interface SieveScript {
+  id: string;
+  accountId: string;
+  name: string;
+  content: string;
+  active: boolean;
+  updatedAt: string;
+}
+
+export interface RemoteSieveScript {
+  name: string;
+  active: boolean;
+}
+
+export type SieveAction =
+  | { type: 'keep'; flags: string[] }
+  | { type: 'fileinto'; folder: string; flags: string[] }
+  | { type: 'redirect'; address: string }
+  | { type: 'discard' }
+  | {
+      type: 'vacation';
+      to: string;
+      subject: string;
+      reason: string;
+      from?: string;
+      days: number;
+      mime: boolean;
+    };
+
+export interface SieveTestResult {
+  emailId: string;
+  subject: string;
+  actions: SieveAction[];
+}
+
+export interface Identity {
+  id: string;
+  email: string;
+  displayName?: string;
+  replyTo?: string;
+  signatureId?: string;
+  smtp?: ServerConfig;
+}
+
+export type SecurityMode = 'tls' | 'starttls' | 'none';
+
+export type AuthMethod = 'password' | 'oauth2' | 'none';
+
+export interface ServerConfig {
+  host?: string;
+  port?: number;
+  username?: string;
+  password?: string;
+  security: SecurityMode;
+  auth: AuthMethod;
+}
+
+export interface ServerSettings {
+  protocol: 'imap' | 'pop3' | 'smtp' | 'jmap';
+  host: string;
+  port: number;
+  security: SecurityMode;
+  username?: string;
+}
+
+export interface AccountSuggestion {
+  email: string;
+  provider: 'gmail' | 'outlook' | 'other';
+  source: 'preset' | 'autoconfig' | 'autodiscover' | 'srv';
+  incoming: ServerSettings[];
+  outgoing: ServerSettings[];
+}
+
+export type HealthStage = 'dns' | 'connect' | 'tls' | 'greeting' | 'auth' | 'folders';
+
+export interface StageResult {
+  stage: HealthStage;
+  ok: boolean;
+  detail: string;
+  serverResponse?: string;
+  durationMs: number;
+}
+
+export interface ServerCheck {
+  protocol: ServerSettings['protocol'];
+  host: string;
+  port: number;
+  security: SecurityMode;
+  stages: StageResult[];
+  failedStage?: HealthStage;
+  folders?: string[];
+}
+
+export interface HealthReport {
+  accountId: string;
+  ok: boolean;
+  checkedAt: string;
+  servers: ServerCheck[];
+  error?: string;
+}
+
+export interface AccountHealth {
+  accountId: string;
+  ok: boolean;
+  lastCheck?: HealthReport;
+  lastSyncError?: string;
+  updatedAt: string;
 }
 
 export interface Email {
@@ -24,6 +208,7 @@ export interface Email {
   to: { name?: string; address: string }[];
   cc?: { name?: string; address: string }[];
   bcc?: { name?: string; address: string }[];
+  replyTo?: { name?: string; address: string }[];
   date: Date;
   body: string;
   htmlBody?: string;
@@ -32,6 +217,123 @@ export interface Email {
   isStarred: boolean;
   labels?: string[];
   aiClassification?: AIClassification;
+  folder?: string;
+  messageId?: string;
+  inReplyTo?: string;
+  references?: string[];
+  remoteId?: string;
+  quarantine?: Quarantine;
+  trashed?: Trashed;
+  snooze?: Snooze;
+  summary?: EmailSummary;
+  threadSummary?: EmailSummary;
+}
+
+export interface Snooze {
+  until: string;
+  folder?: string;
+}
+
+export interface FollowUpReminder {
+  id: string;
+  accountId: string;
+  messageId: string;
+  emailId?: string;
+  subject: string;
+  to: { name?: string; address: string }[];
+  remindAt: string;
+  createdAt: string;
+}
+
+export interface ReminderNotification {
+  kind: 'snooze' | 'followup';
+  accountId: string;
+  emailId?: string;
+  subject: string;
+}
+
+export interface Trashed {
+  since: string;
+  folder?: string;
+}
+
+export interface DeletedAccount {
+  account: EmailAccount;
+  deletedAt: string;
+}
+
+export interface UndoOperation {
+  id: string;
+  description: string;
+  createdAt: string;
+  emailCount: number;
+}
+
+export interface BackgroundError {
+  accountId?: string;
+  message: string;
+  occurredAt: string;
+}
+
+export type BulkSelection =
+  | { type: 'ids'; ids: string[] }
+  | { type: 'query'; query: EmailQuery };
+
+export type BulkAction =
+  | { type: 'read' | 'star'; value: boolean }
+  | { type: 'label'; label: string; value: boolean }
+  | { type: 'move'; folder: string }
+  | { type: 'delete' | 'reclassify' };
+
+export interface BulkProgress {
+  operationId: string;
+  processed: number;
+  total: number;
+}
+
+export interface BulkResult {
+  operationId: string;
+  matched: number;
+  updated: number;
+  failed: number;
+}
+
+export interface Quarantine {
+  since: string;
+  folder?: string;
+}
+
+export interface QuarantineEntry {
+  emailId: string;
+  accountId: string;
+  subject: string;
+  from: { name?: string; address: string };
+  quarantinedAt: string;
+  expiresAt: string;
+}
+
+export interface QuarantineDigest {
+  since?: string;
+  total: number;
+  entries: QuarantineEntry[];
+}
+
+export interface EmailQuery {
+  accountId?: string;
+  folder?: string;
+  label?: string;
+  search?: string;
+}
+
+export type MboxFormat = 'mboxo' | 'mboxrd';
+
+export interface ImportProgress {
+  processed: number;
+  imported: number;
+  duplicates: number;
+  failed: number;
+  bytesRead: number;
+  totalBytes: number;
 }
 
 export interface Attachment {
@@ -42,24 +344,116 @@ export interface Attachment {
   content?: string;
 }
 
+// Built-in categories, or the id of a user-defined one
+export type Category = 'marketing' | 'important' | 'verification' | 'normal' | (string & {});
+
+export interface CategoryDefinition {
+  id: string;
+  name: string;
+  description: string;
+  color?: string;
+  notify: boolean;
+  actions: RuleAction[];
+}
+
 export interface AIClassification {
-  category: 'marketing' | 'important' | 'verification' | 'normal';
+  category: Category;
   verificationCode?: string;
   verificationLink?: string;
   shouldNotify: boolean;
+  confidence?: number;
+  reason?: string;
 }
 
 export interface AIConfig {
   enabled: boolean;
-  provider: 'openai' | 'anthropic' | 'gemini';
+  provider: 'openai' | 'anthropic' | 'gemini' | 'ollama' | 'openaicompatible';
   apiKey: string;
   apiEndpoint?: string;
   model?: string;
   autoDelete: boolean;
+  quarantineDays?: number;
+  minConfidence?: number;
+  maxConcurrency?: number;
+  requestsPerMinute?: number;
+  timeoutSecs?: number;
+  maxRetries?: number;
+  localMinConfidence?: number;
+  monthlyBudget?: number;
+  prices?: ModelPrice[];
+  dailyDigest?: 'notification' | 'message';
+  digestHour?: number;
+}
+
+export interface Deadline {
+  description: string;
+  due: string;
+}
+
+export interface EmailSummary {
+  summary: string;
+  actionItems: string[];
+  deadlines: Deadline[];
+  emailIds: string[];
+  createdAt: string;
+}
+
+export interface DigestEntry {
+  emailId: string;
+  accountId: string;
+  subject: string;
+  from: { name?: string; address: string };
+  summary: string;
+  actionItems: string[];
+}
+
+export interface AIDigest {
+  createdAt: string;
+  entries: DigestEntry[];
+}
+
+export interface ModelPrice {
+  model: string;
+  promptPerMillion: number;
+  completionPerMillion: number;
+}
+
+export interface TrainingReport {
+  examples: number;
+  heldOut: number;
+  accuracy?: number;
+  trainedAt: string;
+}
+
+export interface AIHealth {
+  reachable: boolean;
+  models: string[];
+  modelAvailable?: boolean;
+  error?: string;
+}
+
+export interface AIUsageSummary {
+  period: string;
+  provider: AIConfig['provider'];
+  model: string;
+  accountId?: string;
+  requests: number;
+  promptTokens: number;
+  completionTokens: number;
+  estimatedCost?: number;
+}
+
+export interface AIUsageReport {
+  daily: AIUsageSummary[];
+  monthly: AIUsageSummary[];
+  monthlyBudget?: number;
+  spentThisMonth: number;
+  budgetExceeded: boolean;
 }
 
 export interface AppSettings {
   notifications: boolean;
   aiConfig?: AIConfig;
   theme: 'light' | 'dark' | 'system';
+  trashDays?: number;
 }